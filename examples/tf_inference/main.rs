@@ -23,19 +23,26 @@ fn main() -> utilities::Result<()> {
     sess.register(&mut model)?;
     info!("Registered model {} with session {}", model.id(), sess.id());
 
-    // Load model graph
-    if let Err(err) = model.session_load(&mut sess) {
-        error!("Could not load graph for model {}: {}", model.id(), err);
+    // Close the session whether inference succeeded or not
+    let result = run_inference(&mut model, &mut sess);
 
-        info!("Destroying session {}", sess.id());
-        sess.close()
-            .expect("Could not destroy session during cleanup");
+    info!("Destroying session {}", sess.id());
+    sess.close()?;
 
-        return Err(utilities::Error::Vaccel(err));
-    }
+    result
+}
+
+/// Load the graph of `model` and run inference on a sample input
+fn run_inference(model: &mut tf::SavedModel, sess: &mut Session) -> utilities::Result<()> {
+    // Load model graph. The graph is unloaded when `loaded` is dropped.
+    let model_id = model.id();
+    let mut loaded = model.load(sess).map_err(|err| {
+        error!("Could not load graph for model {}: {}", model_id, err);
+        err
+    })?;
 
     // Prepare data for inference
-    let run_options = tf::Buffer::new(&[]);
+    let run_options = tf::RunOptions::new().to_buffer();
     let in_tensor = tf::Tensor::<f32>::new(&[1, 30]).with_data(&[1.0; 30])?;
     let in_node = tf::Node::new("serving_default_input_1", 0);
     let out_node = tf::Node::new("StatefulPartitionedCall", 0);
@@ -45,7 +52,7 @@ fn main() -> utilities::Result<()> {
     sess_args.add_input(&in_node, &in_tensor);
     sess_args.request_output(&out_node);
 
-    let result = model.session_run(&mut sess, &mut sess_args)?;
+    let result = loaded.run(&mut sess_args)?;
 
     match result.get_output::<f32>(0) {
         Ok(out) => {
@@ -66,9 +73,7 @@ fn main() -> utilities::Result<()> {
         Err(err) => println!("Inference failed: '{}'", err),
     }
 
-    model.session_delete(&mut sess)?;
-
-    sess.close()?;
+    loaded.unload()?;
 
     Ok(())
 }
@@ -24,10 +24,11 @@ fn main() -> Result<()> {
     info!("Registered model {} with session {}", model.id(), sess.id());
 
     // Read saved model data in memory
-    let (model_pb, ckpt, var_index) = utilities::load_in_mem(&path)?;
+    let (model_pb, ckpts, var_index) = utilities::load_in_mem(&path)?;
+    let ckpts: Vec<&[u8]> = ckpts.iter().map(|c| &c[..]).collect();
 
     // Create a TensorFlow model resource from data
-    let mut model2 = SavedModel::new().from_in_memory(&model_pb, &ckpt, &var_index)?;
+    let mut model2 = SavedModel::new().from_in_memory_shards(&model_pb, &ckpts, &var_index)?;
     info!("New saved model from in-memory data: {}", model.id());
     sess.register(&mut model2)?;
     info!(
@@ -0,0 +1,659 @@
+use crate::ffi;
+use crate::ops::genop::GenopArg;
+use crate::ops::inference::OwnedInferenceArgs;
+use crate::remote::transport::{method, Transport};
+use crate::resource::Resource;
+use crate::session::Session;
+use crate::tensorflow as tf;
+use crate::{Error, Result, VaccelId};
+
+use protobuf::Message;
+use protocols::error::VaccelError;
+use protocols::genop::{GenopRequest, GenopResponse, GenopResult};
+use protocols::image::{ImageClassificationRequest, ImageClassificationResponse};
+use protocols::resources::{
+    CreateResourceRequest, CreateResourceRequest_oneof_model, CreateResourceResponse,
+    DestroyResourceRequest, RegisterResourceRequest, UnregisterResourceRequest,
+};
+use protocols::session::{CreateSessionRequest, CreateSessionResponse, DestroySessionRequest};
+use protocols::tensorflow::{
+    TensorflowModelLoadRequest, TensorflowModelLoadResponse, TensorflowModelRunRequest,
+    TensorflowModelRunResponse, TensorflowModelUnloadRequest, TensorflowModelUnloadResponse,
+};
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+fn parse<M: Message>(data: &[u8]) -> Result<M> {
+    Ok(M::parse_from_bytes(data)?)
+}
+
+/// Unregister a resource from all the sessions it is registered with
+fn unregister_all<'a, I>(sessions: I, res: &mut dyn Resource) -> Result<()>
+where
+    I: IntoIterator<Item = &'a mut Session>,
+{
+    for sess in sessions {
+        if sess.has_resource(res) {
+            sess.unregister(res)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Executes requests of the vaccel-grpc protocol on the local vAccel runtime
+///
+/// The dispatcher owns the sessions and resources created through it, which
+/// requests refer to by id. It is meant to be used by agents, which receive
+/// requests from remote clients and only need to decode and encode them.
+///
+/// Requests whose response message carries an error return it in the
+/// response. The rest return a `Result`.
+///
+/// Sessions created with one of the `tf::compression::SESSION_FLAG_*` flags
+/// get the compression acknowledged in the response, and the output tensors
+/// of their TensorFlow runs compressed.
+#[derive(Default)]
+pub struct Dispatcher {
+    sessions: HashMap<VaccelId, Session>,
+    resources: HashMap<VaccelId, Box<dyn Resource>>,
+    compression: HashMap<VaccelId, tf::Compression>,
+}
+
+impl Dispatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn session(&mut self, id: u32) -> Result<&mut Session> {
+        self.sessions
+            .get_mut(&VaccelId::from(id))
+            .ok_or(Error::Runtime(ffi::VACCEL_ESESS))
+    }
+
+    /// Get a session and a SavedModel resource at the same time
+    fn session_and_model(
+        &mut self,
+        session_id: u32,
+        model_id: i64,
+    ) -> Result<(&mut Session, &mut tf::SavedModel)> {
+        let sess = self
+            .sessions
+            .get_mut(&VaccelId::from(session_id))
+            .ok_or(Error::Runtime(ffi::VACCEL_ESESS))?;
+        let model = self
+            .resources
+            .get_mut(&VaccelId::from(model_id))
+            .ok_or(Error::Runtime(ffi::VACCEL_ENOENT))?
+            .as_mut_any()
+            .downcast_mut::<tf::SavedModel>()
+            .ok_or(Error::InvalidArgument)?;
+
+        Ok((sess, model))
+    }
+
+    pub fn create_session(&mut self, req: &CreateSessionRequest) -> Result<CreateSessionResponse> {
+        let flags = req.get_flags();
+        let sess = Session::new(flags & !tf::compression::SESSION_FLAGS)?;
+
+        let mut resp = CreateSessionResponse::from(&sess);
+        let id = VaccelId::from(sess.inner().session_id);
+        if let Some(compression) = tf::Compression::from_flags(flags) {
+            compression.acknowledge(&mut resp);
+            self.compression.insert(id, compression);
+        }
+        self.sessions.insert(id, sess);
+
+        Ok(resp)
+    }
+
+    pub fn destroy_session(&mut self, req: &DestroySessionRequest) -> Result<()> {
+        let id = VaccelId::from(req);
+        let mut sess = self
+            .sessions
+            .remove(&id)
+            .ok_or(Error::Runtime(ffi::VACCEL_ESESS))?;
+        self.compression.remove(&id);
+
+        sess.close()
+    }
+
+    pub fn create_resource(
+        &mut self,
+        req: &CreateResourceRequest,
+    ) -> Result<CreateResourceResponse> {
+        let res: Box<dyn Resource> = match &req.model {
+            Some(CreateResourceRequest_oneof_model::tf_saved(_)) => {
+                Box::new(tf::SavedModel::try_from(req)?)
+            }
+            _ => return Err(Error::Runtime(ffi::VACCEL_ENOTSUP)),
+        };
+
+        let resp = CreateResourceResponse::from(res.as_ref());
+        self.resources.insert(res.id(), res);
+
+        Ok(resp)
+    }
+
+    pub fn register_resource(&mut self, req: &RegisterResourceRequest) -> Result<()> {
+        let sess = self
+            .sessions
+            .get_mut(&VaccelId::from(req.get_session_id()))
+            .ok_or(Error::Runtime(ffi::VACCEL_ESESS))?;
+        let res = self
+            .resources
+            .get_mut(&VaccelId::from(req.get_resource_id()))
+            .ok_or(Error::Runtime(ffi::VACCEL_ENOENT))?;
+
+        sess.register(res.as_mut())
+    }
+
+    pub fn unregister_resource(&mut self, req: &UnregisterResourceRequest) -> Result<()> {
+        let sess = self
+            .sessions
+            .get_mut(&VaccelId::from(req.get_session_id()))
+            .ok_or(Error::Runtime(ffi::VACCEL_ESESS))?;
+        let res = self
+            .resources
+            .get_mut(&VaccelId::from(req.get_resource_id()))
+            .ok_or(Error::Runtime(ffi::VACCEL_ENOENT))?;
+
+        sess.unregister(res.as_mut())
+    }
+
+    /// Destroy a resource, unregistering it from the sessions first
+    pub fn destroy_resource(&mut self, req: &DestroyResourceRequest) -> Result<()> {
+        let id = VaccelId::from(req);
+        let res = self
+            .resources
+            .get_mut(&id)
+            .ok_or(Error::Runtime(ffi::VACCEL_ENOENT))?;
+        unregister_all(self.sessions.values_mut(), res.as_mut())?;
+
+        match self.resources.remove(&id) {
+            Some(mut res) => res.destroy(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn tf_model_load(
+        &mut self,
+        req: &TensorflowModelLoadRequest,
+    ) -> TensorflowModelLoadResponse {
+        let mut resp = TensorflowModelLoadResponse::new();
+
+        let status = self
+            .session_and_model(req.get_session_id(), req.get_model_id())
+            .and_then(|(sess, model)| model.session_load(sess));
+
+        match status {
+            // The graph is kept by the runtime, there is nothing to send back
+            Ok(_) => resp.set_graph_def(Vec::new()),
+            Err(err) => resp.set_error(VaccelError::from(&err)),
+        }
+
+        resp
+    }
+
+    /// Unload a model from a session
+    ///
+    /// The response message has no room for an error, so failures are
+    /// returned like for requests without a response message.
+    pub fn tf_model_unload(
+        &mut self,
+        req: &TensorflowModelUnloadRequest,
+    ) -> Result<TensorflowModelUnloadResponse> {
+        let (sess, model) = self.session_and_model(req.get_session_id(), req.get_model_id())?;
+        model.session_delete(sess)?;
+
+        let mut resp = TensorflowModelUnloadResponse::new();
+        resp.set_success(true);
+
+        Ok(resp)
+    }
+
+    fn run(&mut self, req: &TensorflowModelRunRequest) -> Result<TensorflowModelRunResponse> {
+        let args = OwnedInferenceArgs::try_from(req)?;
+        let (sess, model) = self.session_and_model(req.get_session_id(), req.get_model_id())?;
+
+        let mut resp = TensorflowModelRunResponse::from(model.session_run(sess, &mut args.args())?);
+
+        let compression = self.compression.get(&VaccelId::from(req.get_session_id()));
+        if let (Some(compression), true) = (compression, resp.has_result()) {
+            for tensor in resp.mut_result().mut_out_tensors().iter_mut() {
+                compression.compress(tensor)?;
+            }
+        }
+
+        Ok(resp)
+    }
+
+    pub fn tf_model_run(&mut self, req: &TensorflowModelRunRequest) -> TensorflowModelRunResponse {
+        match self.run(req) {
+            Ok(resp) => resp,
+            Err(err) => {
+                let mut resp = TensorflowModelRunResponse::new();
+                resp.set_error(VaccelError::from(&err));
+                resp
+            }
+        }
+    }
+
+    pub fn image_classification(
+        &mut self,
+        req: &ImageClassificationRequest,
+    ) -> Result<ImageClassificationResponse> {
+        let (mut tags, _) = self
+            .session(req.get_session_id())?
+            .image_classification(req.get_image())?;
+
+        // The runtime returns the tags as a C string
+        if let Some(end) = tags.iter().position(|c| *c == 0) {
+            tags.truncate(end);
+        }
+
+        let mut resp = ImageClassificationResponse::new();
+        resp.set_tags(tags);
+
+        Ok(resp)
+    }
+
+    /// Perform image detection, returning the output image in the `tags` of
+    /// the response
+    pub fn image_detection(
+        &mut self,
+        req: &ImageClassificationRequest,
+    ) -> Result<ImageClassificationResponse> {
+        let mut img = req.get_image().to_owned();
+        let out_img = self
+            .session(req.get_session_id())?
+            .image_detection(&mut img)?;
+
+        let mut resp = ImageClassificationResponse::new();
+        resp.set_tags(out_img);
+
+        Ok(resp)
+    }
+
+    /// Perform image segmentation, returning the output image in the `tags`
+    /// of the response
+    pub fn image_segmentation(
+        &mut self,
+        req: &ImageClassificationRequest,
+    ) -> Result<ImageClassificationResponse> {
+        let mut img = req.get_image().to_owned();
+        let out_img = self
+            .session(req.get_session_id())?
+            .image_segmentation(&mut img)?;
+
+        let mut resp = ImageClassificationResponse::new();
+        resp.set_tags(out_img);
+
+        Ok(resp)
+    }
+
+    /// Execute a generic operation
+    ///
+    /// Arguments whose size is larger than their buffer are rejected with
+    /// `VACCEL_EINVAL`, as the runtime would access memory past the buffer.
+    pub fn genop(&mut self, req: &GenopRequest) -> GenopResponse {
+        let mut resp = GenopResponse::new();
+
+        if req
+            .get_read_args()
+            .iter()
+            .chain(req.get_write_args().iter())
+            .any(|arg| arg.get_size() as usize > arg.get_buf().len())
+        {
+            resp.set_error(VaccelError::from(&Error::Runtime(ffi::VACCEL_EINVAL)));
+            return resp;
+        }
+
+        let mut read_args: Vec<GenopArg> = req.get_read_args().iter().map(GenopArg::from).collect();
+        let mut write_args: Vec<GenopArg> =
+            req.get_write_args().iter().map(GenopArg::from).collect();
+
+        let result = self
+            .session(req.get_session_id())
+            .and_then(|sess| sess.genop(&mut read_args, &mut write_args));
+
+        match result {
+            Ok(()) => {
+                let mut result = GenopResult::new();
+                result.set_write_args(write_args.iter().map(|arg| arg.into()).collect());
+                resp.set_result(result);
+            }
+            Err(err) => resp.set_error(VaccelError::from(&err)),
+        }
+
+        resp
+    }
+
+    /// Execute a serialized request
+    ///
+    /// The request is decoded, executed and its response encoded, so that
+    /// agents only need to move bytes between the dispatcher and their
+    /// transport. Methods without a response message return an empty one.
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The name of the method, one of the constants of `remote::method`
+    /// * `request` - The serialized request message
+    pub fn handle(&mut self, method: &str, request: &[u8]) -> Result<Vec<u8>> {
+        let resp = match method {
+            method::CREATE_SESSION => self.create_session(&parse(request)?)?.write_to_bytes()?,
+            method::DESTROY_SESSION => {
+                self.destroy_session(&parse(request)?)?;
+                Vec::new()
+            }
+            method::CREATE_RESOURCE => self.create_resource(&parse(request)?)?.write_to_bytes()?,
+            method::REGISTER_RESOURCE => {
+                self.register_resource(&parse(request)?)?;
+                Vec::new()
+            }
+            method::UNREGISTER_RESOURCE => {
+                self.unregister_resource(&parse(request)?)?;
+                Vec::new()
+            }
+            method::DESTROY_RESOURCE => {
+                self.destroy_resource(&parse(request)?)?;
+                Vec::new()
+            }
+            method::IMAGE_CLASSIFICATION => self
+                .image_classification(&parse(request)?)?
+                .write_to_bytes()?,
+            method::IMAGE_DETECTION => self.image_detection(&parse(request)?)?.write_to_bytes()?,
+            method::IMAGE_SEGMENTATION => self
+                .image_segmentation(&parse(request)?)?
+                .write_to_bytes()?,
+            method::TENSORFLOW_MODEL_LOAD => {
+                self.tf_model_load(&parse(request)?).write_to_bytes()?
+            }
+            method::TENSORFLOW_MODEL_UNLOAD => {
+                self.tf_model_unload(&parse(request)?)?.write_to_bytes()?
+            }
+            method::TENSORFLOW_MODEL_RUN => self.tf_model_run(&parse(request)?).write_to_bytes()?,
+            method::GENOP => self.genop(&parse(request)?).write_to_bytes()?,
+            _ => return Err(Error::Runtime(ffi::VACCEL_ENOTSUP)),
+        };
+
+        Ok(resp)
+    }
+}
+
+/// Execute requests directly on the dispatcher, e.g. to record a local session
+impl Transport for Dispatcher {
+    fn call(&mut self, method: &str, request: &[u8]) -> Result<Vec<u8>> {
+        self.handle(method, request)
+    }
+}
+
+/// Close the sessions, then destroy the resources, so that no session is
+/// left with a destroyed resource
+impl Drop for Dispatcher {
+    fn drop(&mut self) {
+        for sess in self.sessions.values_mut() {
+            for res in self.resources.values_mut() {
+                let _ = unregister_all(Some(&mut *sess), res.as_mut());
+            }
+            let _ = sess.close();
+        }
+        self.sessions.clear();
+
+        for (_, mut res) in self.resources.drain() {
+            let _ = res.destroy();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use protocols::genop::GenopArg as ProtGenopArg;
+    use protocols::resources::CreateTensorflowSavedModelRequest;
+    use protocols::tensorflow::TFNode;
+
+    use std::fs;
+    use std::path::Path;
+
+    fn call<Req: Message, Resp: Message>(
+        dispatcher: &mut Dispatcher,
+        method: &str,
+        req: &Req,
+    ) -> Result<Resp> {
+        let resp = dispatcher.handle(method, &req.write_to_bytes().unwrap())?;
+        Ok(Resp::parse_from_bytes(&resp).unwrap())
+    }
+
+    fn create_session(dispatcher: &mut Dispatcher) -> u32 {
+        let resp: CreateSessionResponse = call(
+            dispatcher,
+            method::CREATE_SESSION,
+            &CreateSessionRequest::new(),
+        )
+        .unwrap();
+        resp.get_session_id()
+    }
+
+    fn create_model(dispatcher: &mut Dispatcher) -> i64 {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/files/tf/lstm2");
+        let mut model = CreateTensorflowSavedModelRequest::new();
+        model.set_model_pb(fs::read(path.join("saved_model.pb")).unwrap());
+        model.set_var_index(fs::read(path.join("variables/variables.index")).unwrap());
+        model.set_checkpoint(
+            fs::read(path.join("variables/variables.data-00000-of-00001")).unwrap(),
+        );
+        let mut req = CreateResourceRequest::new();
+        req.set_tf_saved(model);
+
+        let resp: CreateResourceResponse = call(dispatcher, method::CREATE_RESOURCE, &req).unwrap();
+        resp.get_resource_id()
+    }
+
+    fn register(session_id: u32, resource_id: i64) -> RegisterResourceRequest {
+        let mut req = RegisterResourceRequest::new();
+        req.set_session_id(session_id);
+        req.set_resource_id(resource_id);
+        req
+    }
+
+    fn is_runtime_error<T>(result: Result<T>, code: u32) -> bool {
+        matches!(result, Err(Error::Runtime(err)) if err == code)
+    }
+
+    fn vaccel_error(err: &VaccelError) -> u32 {
+        err.get_vaccel_error()
+    }
+
+    #[test]
+    fn create_and_destroy_sessions() {
+        let mut dispatcher = Dispatcher::new();
+        let first = create_session(&mut dispatcher);
+        let second = create_session(&mut dispatcher);
+        assert_ne!(first, second);
+        assert_eq!(dispatcher.sessions.len(), 2);
+
+        let mut req = DestroySessionRequest::new();
+        req.set_session_id(first);
+        let resp = dispatcher
+            .handle(method::DESTROY_SESSION, &req.write_to_bytes().unwrap())
+            .unwrap();
+        assert!(resp.is_empty());
+        assert_eq!(dispatcher.sessions.len(), 1);
+
+        assert!(is_runtime_error(
+            dispatcher.handle(method::DESTROY_SESSION, &req.write_to_bytes().unwrap()),
+            ffi::VACCEL_ESESS
+        ));
+    }
+
+    #[test]
+    fn create_and_register_resources() {
+        let mut dispatcher = Dispatcher::new();
+        let sess = create_session(&mut dispatcher);
+        let model = create_model(&mut dispatcher);
+        assert!(dispatcher.resources.contains_key(&VaccelId::from(model)));
+
+        let req = register(sess, model);
+        let resp = dispatcher
+            .handle(method::REGISTER_RESOURCE, &req.write_to_bytes().unwrap())
+            .unwrap();
+        assert!(resp.is_empty());
+        let res = dispatcher.resources[&VaccelId::from(model)].as_ref();
+        assert!(dispatcher
+            .sessions
+            .get_mut(&VaccelId::from(sess))
+            .unwrap()
+            .has_resource(res));
+
+        assert!(is_runtime_error(
+            dispatcher.register_resource(&register(sess + 100, model)),
+            ffi::VACCEL_ESESS
+        ));
+        assert!(is_runtime_error(
+            dispatcher.register_resource(&register(sess, model + 100)),
+            ffi::VACCEL_ENOENT
+        ));
+
+        // Destroying the resource unregisters it first
+        let destroy = DestroyResourceRequest::from(VaccelId::from(model));
+        dispatcher
+            .handle(method::DESTROY_RESOURCE, &destroy.write_to_bytes().unwrap())
+            .unwrap();
+        assert!(dispatcher.resources.is_empty());
+
+        let mut req = CreateResourceRequest::new();
+        req.mut_tf().set_model(vec![1, 2, 3]);
+        assert!(is_runtime_error(
+            dispatcher.create_resource(&req),
+            ffi::VACCEL_ENOTSUP
+        ));
+    }
+
+    #[test]
+    fn run_genop() {
+        let mut dispatcher = Dispatcher::new();
+        let sess = create_session(&mut dispatcher);
+
+        let arg = |buf: Vec<u8>, size| ProtGenopArg {
+            size,
+            buf,
+            ..Default::default()
+        };
+        let mut req = GenopRequest::new();
+        req.set_session_id(sess);
+        req.set_read_args(vec![arg(vec![1, 2, 3], 3)].into());
+        req.set_write_args(vec![arg(vec![0; 4], 4)].into());
+
+        let resp: GenopResponse = call(&mut dispatcher, method::GENOP, &req).unwrap();
+        let write_args = resp.get_result().get_write_args();
+        assert_eq!(write_args.len(), 1);
+        assert_eq!(write_args[0].get_buf(), [1, 2, 3, 0]);
+
+        // Arguments larger than their buffer never reach the runtime
+        req.mut_read_args()[0].set_size(4);
+        let resp: GenopResponse = call(&mut dispatcher, method::GENOP, &req).unwrap();
+        assert_eq!(vaccel_error(resp.get_error()), ffi::VACCEL_EINVAL);
+
+        req.mut_read_args()[0].set_size(3);
+        req.set_session_id(sess + 100);
+        let resp: GenopResponse = call(&mut dispatcher, method::GENOP, &req).unwrap();
+        assert_eq!(vaccel_error(resp.get_error()), ffi::VACCEL_ESESS);
+    }
+
+    #[test]
+    fn load_run_and_unload_models() {
+        let mut dispatcher = Dispatcher::new();
+        let sess = create_session(&mut dispatcher);
+        let model = create_model(&mut dispatcher);
+        dispatcher
+            .register_resource(&register(sess, model))
+            .unwrap();
+
+        let mut load = TensorflowModelLoadRequest::new();
+        load.set_session_id(sess);
+        load.set_model_id(model);
+        let resp: TensorflowModelLoadResponse =
+            call(&mut dispatcher, method::TENSORFLOW_MODEL_LOAD, &load).unwrap();
+        assert!(!resp.has_error());
+
+        let input = tf::Tensor::<f32>::new(&[1, 30]);
+        let mut node = TFNode::new();
+        node.set_name("serving_default_input_1".to_owned());
+        let mut out_node = TFNode::new();
+        out_node.set_name("StatefulPartitionedCall".to_owned());
+
+        let mut run = TensorflowModelRunRequest::new();
+        run.set_session_id(sess);
+        run.set_model_id(model);
+        run.set_in_nodes(vec![node].into());
+        run.set_in_tensors(vec![(&input).into()].into());
+        run.set_out_nodes(vec![out_node].into());
+        let resp: TensorflowModelRunResponse =
+            call(&mut dispatcher, method::TENSORFLOW_MODEL_RUN, &run).unwrap();
+        let out_tensors = resp.get_result().get_out_tensors();
+        assert_eq!(out_tensors.len(), 1);
+        assert_eq!(out_tensors[0].get_dims(), [1, 2]);
+
+        // Inputs that do not match the signature are rejected in the response
+        run.set_in_tensors(vec![(&tf::Tensor::<i32>::new(&[1, 30])).into()].into());
+        let resp: TensorflowModelRunResponse =
+            call(&mut dispatcher, method::TENSORFLOW_MODEL_RUN, &run).unwrap();
+        assert!(resp.has_error());
+
+        let mut unload = TensorflowModelUnloadRequest::new();
+        unload.set_session_id(sess);
+        unload.set_model_id(model);
+        let resp: TensorflowModelUnloadResponse =
+            call(&mut dispatcher, method::TENSORFLOW_MODEL_UNLOAD, &unload).unwrap();
+        assert!(resp.get_success());
+
+        // Unloading fails like the requests without an error in their response
+        unload.set_model_id(model + 100);
+        let resp: Result<TensorflowModelUnloadResponse> =
+            call(&mut dispatcher, method::TENSORFLOW_MODEL_UNLOAD, &unload);
+        assert!(is_runtime_error(resp, ffi::VACCEL_ENOENT));
+
+        load.set_session_id(sess + 100);
+        let resp: TensorflowModelLoadResponse =
+            call(&mut dispatcher, method::TENSORFLOW_MODEL_LOAD, &load).unwrap();
+        assert_eq!(vaccel_error(resp.get_error()), ffi::VACCEL_ESESS);
+    }
+
+    #[test]
+    fn run_image_operations() {
+        let mut dispatcher = Dispatcher::new();
+        let sess = create_session(&mut dispatcher);
+
+        let mut req = ImageClassificationRequest::new();
+        req.set_session_id(sess);
+        req.set_image(vec![7; 64]);
+
+        let resp: ImageClassificationResponse =
+            call(&mut dispatcher, method::IMAGE_CLASSIFICATION, &req).unwrap();
+        assert!(resp.get_tags().len() <= 1024);
+
+        for method in &[method::IMAGE_DETECTION, method::IMAGE_SEGMENTATION] {
+            let resp: ImageClassificationResponse = call(&mut dispatcher, method, &req).unwrap();
+            assert_eq!(resp.get_tags().len(), 64);
+        }
+
+        req.set_session_id(sess + 100);
+        let resp: Result<ImageClassificationResponse> =
+            call(&mut dispatcher, method::IMAGE_DETECTION, &req);
+        assert!(is_runtime_error(resp, ffi::VACCEL_ESESS));
+    }
+
+    #[test]
+    fn reject_unknown_methods() {
+        let mut dispatcher = Dispatcher::new();
+
+        assert!(is_runtime_error(
+            dispatcher.handle(method::UPDATE_SESSION, &[]),
+            ffi::VACCEL_ENOTSUP
+        ));
+        assert!(dispatcher.handle(method::CREATE_SESSION, &[0xff]).is_err());
+    }
+}
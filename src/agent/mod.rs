@@ -0,0 +1,5 @@
+pub mod dispatcher;
+pub mod server;
+
+pub use dispatcher::Dispatcher;
+pub use server::{Server, ServerConfig, ShutdownHandle};
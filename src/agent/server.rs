@@ -0,0 +1,370 @@
+use crate::agent::Dispatcher;
+use crate::remote::transport::Transport;
+use crate::remote::ttrpc::{self, Request, Response};
+use crate::Result;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::Shutdown;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+/// The default path of the socket of the agent
+pub const DEFAULT_SOCKET_PATH: &str = "/run/vaccel/agent.sock";
+
+/// The default number of clients served at the same time
+pub const DEFAULT_MAX_CONNECTIONS: usize = 16;
+
+/// Configuration of an agent `Server`
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    socket_path: PathBuf,
+    max_connections: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            socket_path: PathBuf::from(DEFAULT_SOCKET_PATH),
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the path of the Unix domain socket to listen on
+    pub fn with_socket_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.socket_path = path.as_ref().to_owned();
+        self
+    }
+
+    /// Set the number of clients served at the same time
+    ///
+    /// Further clients wait for a connection to close before being served.
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections.max(1);
+        self
+    }
+
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+}
+
+#[derive(Default)]
+struct Connections {
+    next_id: u64,
+    // Clones of the streams being served, to close them on shutdown
+    streams: HashMap<u64, UnixStream>,
+}
+
+#[derive(Default)]
+struct Shared {
+    shutdown: AtomicBool,
+    connections: Mutex<Connections>,
+    changed: Condvar,
+}
+
+/// A vAccel agent serving the `VaccelAgent` service over ttrpc
+///
+/// The server listens on a Unix domain socket and executes the requests of
+/// each client with its own `Dispatcher`, on a thread of its own. Sessions
+/// and resources of a client are released when it disconnects. Requests of
+/// a connection are handled one at a time, in order.
+pub struct Server {
+    config: ServerConfig,
+    listener: UnixListener,
+    shared: Arc<Shared>,
+}
+
+impl Server {
+    /// Listen on the socket of `config`
+    ///
+    /// A stale socket left at the same path, e.g. by an agent that crashed,
+    /// is replaced. Any other kind of file is left alone and fails the bind.
+    pub fn bind(config: ServerConfig) -> Result<Self> {
+        if let Ok(meta) = fs::symlink_metadata(&config.socket_path) {
+            if meta.file_type().is_socket() && UnixStream::connect(&config.socket_path).is_err() {
+                fs::remove_file(&config.socket_path)?;
+            }
+        }
+
+        let listener = UnixListener::bind(&config.socket_path)?;
+
+        Ok(Server {
+            config,
+            listener,
+            shared: Arc::new(Shared::default()),
+        })
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// Get a handle to stop the server, e.g. from another thread
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            shared: self.shared.clone(),
+            socket_path: self.config.socket_path.clone(),
+        }
+    }
+
+    /// Serve clients with a `Dispatcher` on the local vAccel runtime
+    ///
+    /// This blocks until the server is shut down. See `serve_with`.
+    pub fn serve(self) -> Result<()> {
+        self.serve_with(Dispatcher::new)
+    }
+
+    /// Serve clients with a custom handler
+    ///
+    /// This blocks until the server is shut down through a `ShutdownHandle`.
+    /// Requests being executed are then completed, all connections closed and
+    /// the socket removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - Creates the handler of the requests of each connection, on its thread
+    pub fn serve_with<F, T>(self, handler: F) -> Result<()>
+    where
+        F: Fn() -> T + Send + Sync + 'static,
+        T: Transport,
+    {
+        let handler = Arc::new(handler);
+        let result = self.accept_loop(&handler);
+
+        // Wake up the connections waiting for requests and wait for all of
+        // them to finish
+        let handle = self.shutdown_handle();
+        handle.shutdown();
+        let mut connections = self.shared.connections.lock().unwrap();
+        while !connections.streams.is_empty() {
+            connections = self.shared.changed.wait(connections).unwrap();
+        }
+        drop(connections);
+
+        // An error of the accept loop matters more than one removing the socket
+        let removed = fs::remove_file(&self.config.socket_path);
+        result?;
+        removed.map_err(Into::into)
+    }
+
+    fn accept_loop<F, T>(&self, handler: &Arc<F>) -> Result<()>
+    where
+        F: Fn() -> T + Send + Sync + 'static,
+        T: Transport,
+    {
+        for stream in self.listener.incoming() {
+            if self.shared.shutdown.load(Ordering::SeqCst) {
+                break;
+            }
+
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+
+            // Wait for a free connection slot
+            let mut connections = self.shared.connections.lock().unwrap();
+            while connections.streams.len() >= self.config.max_connections
+                && !self.shared.shutdown.load(Ordering::SeqCst)
+            {
+                connections = self.shared.changed.wait(connections).unwrap();
+            }
+            if self.shared.shutdown.load(Ordering::SeqCst) {
+                break;
+            }
+
+            let id = connections.next_id;
+            connections.next_id += 1;
+            connections.streams.insert(id, stream.try_clone()?);
+            drop(connections);
+
+            let shared = self.shared.clone();
+            let handler = handler.clone();
+            let spawned = thread::Builder::new()
+                .name(format!("vaccel-agent-{}", id))
+                .spawn(move || {
+                    let mut handler = handler();
+                    // Errors only end the connection
+                    let _ = serve_connection(stream, &mut handler);
+
+                    shared.connections.lock().unwrap().streams.remove(&id);
+                    shared.changed.notify_all();
+                });
+
+            if let Err(err) = spawned {
+                self.shared.connections.lock().unwrap().streams.remove(&id);
+                return Err(err.into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Serve the requests of a ttrpc connection until the client disconnects
+fn serve_connection<T: Transport>(mut stream: UnixStream, handler: &mut T) -> Result<()> {
+    while let Some((header, payload)) = ttrpc::read_message(&mut stream)? {
+        // Streaming messages are not part of the service
+        if header.message_type != ttrpc::MESSAGE_TYPE_REQUEST {
+            continue;
+        }
+
+        let result =
+            Request::decode(&payload).and_then(|req| handler.call(&req.path(), &req.payload));
+
+        ttrpc::write_message(
+            &mut stream,
+            header.stream_id,
+            ttrpc::MESSAGE_TYPE_RESPONSE,
+            &Response::new(result).encode()?,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// A handle to stop a `Server`
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    shared: Arc<Shared>,
+    socket_path: PathBuf,
+}
+
+impl ShutdownHandle {
+    /// Stop the server
+    ///
+    /// The server stops accepting clients, and connections are closed once
+    /// their current request completes. This returns immediately, `serve`
+    /// returns when the server has stopped.
+    pub fn shutdown(&self) {
+        if self.shared.shutdown.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        for stream in self.shared.connections.lock().unwrap().streams.values() {
+            let _ = stream.shutdown(Shutdown::Read);
+        }
+        self.shared.changed.notify_all();
+
+        // Wake up the server if it is waiting for a client
+        let _ = UnixStream::connect(&self.socket_path);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.shared.shutdown.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ffi;
+    use crate::remote::transport::method;
+    use crate::remote::ttrpc::TtrpcTransport;
+    use crate::remote::RemoteSession;
+    use crate::tensorflow as tf;
+    use crate::Error;
+
+    use protocols::resources::CreateResourceRequest;
+
+    use std::convert::TryFrom;
+
+    use std::env;
+    use std::process;
+
+    /// A handler echoing the requests to `CreateSession`, and failing others
+    struct Echo;
+
+    impl Transport for Echo {
+        fn call(&mut self, method: &str, request: &[u8]) -> Result<Vec<u8>> {
+            if method != method::CREATE_SESSION {
+                return Err(Error::InvalidArgument);
+            }
+
+            Ok(request.to_owned())
+        }
+    }
+
+    fn socket_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("vaccel-{}-test-{}.sock", name, process::id()))
+    }
+
+    #[test]
+    fn serve_and_shutdown() {
+        let path = socket_path("server");
+        let server = Server::bind(ServerConfig::new().with_socket_path(&path)).unwrap();
+        let handle = server.shutdown_handle();
+        let serving = thread::spawn(move || server.serve_with(|| Echo));
+
+        let mut transport = TtrpcTransport::connect(&path).unwrap();
+        assert_eq!(
+            transport.call(method::CREATE_SESSION, b"ping").unwrap(),
+            b"ping"
+        );
+        assert!(transport.call(method::DESTROY_SESSION, b"").is_err());
+        assert_eq!(transport.call(method::CREATE_SESSION, b"").unwrap(), b"");
+
+        handle.shutdown();
+        serving.join().unwrap().unwrap();
+        assert!(handle.is_shutdown());
+        assert!(!path.exists());
+
+        // The connection was closed
+        assert!(transport.call(method::CREATE_SESSION, b"ping").is_err());
+    }
+
+    #[test]
+    fn serve_remote_sessions() {
+        let path = socket_path("remote-session");
+        let server = Server::bind(ServerConfig::new().with_socket_path(&path)).unwrap();
+        let handle = server.shutdown_handle();
+        let serving = thread::spawn(move || server.serve());
+
+        let lstm2 = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/files/tf/lstm2");
+        let model = tf::SavedModel::new()
+            .from_export_dir_in_memory(&lstm2)
+            .unwrap();
+        let req = CreateResourceRequest::try_from(&model).unwrap();
+
+        let transport = TtrpcTransport::connect(&path).unwrap();
+        let mut sess = RemoteSession::new(transport, 0).unwrap();
+        let mut res = sess.create_resource(&req).unwrap();
+        sess.register(&mut res).unwrap();
+        sess.tf_model_load(res.id()).unwrap();
+
+        let mut img = vec![1; 16];
+        assert_eq!(sess.image_segmentation(&mut img).unwrap().len(), 16);
+
+        sess.tf_model_unload(res.id()).unwrap();
+        sess.unregister(&mut res).unwrap();
+        sess.destroy_resource(res).unwrap();
+        sess.close().unwrap();
+
+        // Errors of the dispatcher make it through ttrpc
+        assert!(matches!(
+            sess.image_detection(&mut img),
+            Err(Error::Runtime(ffi::VACCEL_ESESS))
+        ));
+
+        handle.shutdown();
+        serving.join().unwrap().unwrap();
+    }
+}
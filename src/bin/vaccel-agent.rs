@@ -0,0 +1,85 @@
+use std::env;
+use std::process;
+use std::thread;
+
+use vaccel::agent::{Server, ServerConfig, ShutdownHandle};
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: vaccel-agent [--socket <path>] [--max-connections <n>]\n\n\
+         Serve the vAccel agent service over ttrpc on a Unix domain socket.\n\n\
+         Options:\n  \
+           --socket <path>         Socket to listen on (default: {})\n  \
+           --max-connections <n>   Clients served at the same time (default: {})",
+        vaccel::agent::server::DEFAULT_SOCKET_PATH,
+        vaccel::agent::server::DEFAULT_MAX_CONNECTIONS
+    );
+    process::exit(1);
+}
+
+fn parse_args() -> ServerConfig {
+    let mut config = ServerConfig::new();
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--socket" => match args.next() {
+                Some(path) => config = config.with_socket_path(path),
+                None => usage(),
+            },
+            "--max-connections" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) => config = config.with_max_connections(n),
+                None => usage(),
+            },
+            _ => usage(),
+        }
+    }
+
+    config
+}
+
+/// Block SIGINT and SIGTERM, so that they are only received by `wait_signals`
+///
+/// This needs to run before any other thread is started, as threads inherit
+/// the signal mask.
+fn block_signals() -> libc::sigset_t {
+    unsafe {
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGINT);
+        libc::sigaddset(&mut set, libc::SIGTERM);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+        set
+    }
+}
+
+/// Shut the server down on the first SIGINT or SIGTERM
+fn wait_signals(set: libc::sigset_t, handle: ShutdownHandle) {
+    let mut sig = 0;
+    unsafe { libc::sigwait(&set, &mut sig) };
+
+    eprintln!("Received signal {}, shutting down", sig);
+    handle.shutdown();
+}
+
+fn main() {
+    let config = parse_args();
+    let signals = block_signals();
+
+    let server = match Server::bind(config) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Could not listen: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let handle = server.shutdown_handle();
+    thread::spawn(move || wait_signals(signals, handle));
+
+    eprintln!("Listening on {}", server.config().socket_path().display());
+    if let Err(e) = server.serve() {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
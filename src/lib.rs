@@ -4,10 +4,14 @@
 #![allow(dead_code)]
 #![allow(improper_ctypes)]
 
+use protocols::error::VaccelError;
+
 use std::fmt;
 
+pub mod agent;
 pub mod ffi;
 pub mod ops;
+pub mod remote;
 pub mod resource;
 pub mod session;
 pub mod tensorflow;
@@ -20,6 +24,9 @@ pub enum Error {
     // Error returned to us by vAccel runtime library
     Runtime(u32),
 
+    // Error specific to a vAccel agent, as sent back by it
+    Agent(i64),
+
     // We received an invalid argument
     InvalidArgument,
 
@@ -28,22 +35,122 @@ pub enum Error {
 
     // A TensorFlow Error
     TensorFlow(tensorflow::Code),
+
+    // An I/O error while reading or writing model or tensor files
+    IO(std::io::Error),
+
+    // Malformed or unsupported NumPy data
+    Npy(String),
+
+    // Malformed protobuf data
+    Protobuf(protobuf::ProtobufError),
+
+    // Malformed JSON data, e.g. in Keras metadata
+    Json(serde_json::Error),
+
+    // Malformed, incomplete or unsupported TensorFlow checkpoint
+    Checkpoint(String),
+
+    // An input tensor does not match the model signature
+    InputMismatch {
+        node: String,
+        expected: String,
+        actual: String,
+    },
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::Runtime(err) => write!(f, "vAccel runtime error {}", err),
+            Error::Agent(err) => write!(f, "vAccel agent error {}", err),
             Error::InvalidArgument => write!(f, "An invalid argument was given to us"),
             Error::Uninitialized => write!(f, "Uninitialized vAccel object"),
             Error::TensorFlow(code) => write!(f, "TensorFlow error: {:?}", code),
+            Error::IO(err) => write!(f, "I/O error: {}", err),
+            Error::Npy(msg) => write!(f, "NumPy error: {}", msg),
+            Error::Protobuf(err) => write!(f, "Protobuf error: {}", err),
+            Error::Json(err) => write!(f, "JSON error: {}", err),
+            Error::Checkpoint(msg) => write!(f, "Checkpoint error: {}", msg),
+            Error::InputMismatch {
+                node,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Input {} does not match the model signature: expected {}, got {}",
+                node, expected, actual
+            ),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::IO(err)
+    }
+}
+
+impl From<protobuf::ProtobufError> for Error {
+    fn from(err: protobuf::ProtobufError) -> Self {
+        Error::Protobuf(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+impl Error {
+    /// Get the vAccel error code of the error
+    ///
+    /// This is the code returned by the runtime for `Error::Runtime`, and the
+    /// closest vAccel error code for the rest of the errors.
+    pub fn runtime_code(&self) -> u32 {
+        match self {
+            Error::Runtime(code) => *code,
+            Error::Agent(_) => ffi::VACCEL_EBACKEND,
+            Error::Uninitialized => ffi::VACCEL_ENOENT,
+            Error::IO(_) => ffi::VACCEL_EIO,
+            _ => ffi::VACCEL_EINVAL,
+        }
+    }
+}
+
+/// Convert an `Error` to the `VaccelError` sent back to clients
+///
+/// `Error::Runtime` and `Error::Agent` are sent as is, and convert back to
+/// the same error. The other errors only keep their `runtime_code`, so they
+/// convert back to an `Error::Runtime`: e.g. `Error::Uninitialized` comes
+/// back as `Error::Runtime(VACCEL_ENOENT)`, and the messages of I/O, NumPy
+/// or checkpoint errors are lost.
+impl From<&Error> for VaccelError {
+    fn from(err: &Error) -> Self {
+        let mut error = VaccelError::new();
+        match err {
+            Error::Agent(code) => error.set_agent_error(*code),
+            err => error.set_vaccel_error(err.runtime_code()),
+        }
+        error
+    }
+}
+
+/// Convert a `VaccelError` sent back by an agent to `Error`
+impl From<&VaccelError> for Error {
+    fn from(err: &VaccelError) -> Self {
+        if err.has_agent_error() {
+            Error::Agent(err.get_agent_error())
+        } else {
+            Error::Runtime(err.get_vaccel_error())
         }
     }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(PartialEq, Eq, Hash, Debug)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
 pub struct VaccelId {
     inner: Option<ffi::vaccel_id_t>,
 }
@@ -98,3 +205,42 @@ impl From<u32> for VaccelId {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(err: &Error) -> Error {
+        Error::from(&VaccelError::from(err))
+    }
+
+    #[test]
+    fn error_round_trip() {
+        match round_trip(&Error::Runtime(ffi::VACCEL_ESESS)) {
+            Error::Runtime(code) => assert_eq!(code, ffi::VACCEL_ESESS),
+            err => panic!("unexpected error {:?}", err),
+        }
+
+        match round_trip(&Error::Agent(-42)) {
+            Error::Agent(code) => assert_eq!(code, -42),
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn error_round_trip_keeps_code() {
+        let errors = vec![
+            Error::InvalidArgument,
+            Error::Uninitialized,
+            Error::IO(std::io::ErrorKind::NotFound.into()),
+            Error::Checkpoint("truncated table".to_owned()),
+        ];
+
+        for err in errors.iter() {
+            match round_trip(err) {
+                Error::Runtime(code) => assert_eq!(code, err.runtime_code()),
+                other => panic!("{:?} came back as {:?}", err, other),
+            }
+        }
+    }
+}
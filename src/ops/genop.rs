@@ -39,14 +39,17 @@ impl GenopArg {
     }
 }
 
-impl From<&mut ProtGenopArg> for GenopArg {
-    fn from(arg: &mut ProtGenopArg) -> Self {
-        let size = arg.get_size();
-        let buf = arg.mut_buf();
-        GenopArg::new(
+impl From<&ProtGenopArg> for GenopArg {
+    fn from(arg: &ProtGenopArg) -> Self {
+        let mut buf = arg.get_buf().to_owned();
+        GenopArg {
+            inner: ffi::vaccel_arg {
+                buf: buf.as_mut_ptr() as *mut libc::c_void,
+                size: arg.get_size(),
+            },
             buf,
-            size as usize
-            )
+            size: arg.get_size() as usize,
+        }
     }
 }
 
@@ -90,3 +93,21 @@ impl Session {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn genop_arg_round_trip() {
+        let arg = ProtGenopArg {
+            buf: vec![1, 2, 3, 4],
+            size: 4,
+            ..Default::default()
+        };
+
+        let genop_arg = GenopArg::from(&arg);
+        assert_eq!(genop_arg.get_size(), 4);
+        assert_eq!(ProtGenopArg::from(&genop_arg), arg);
+    }
+}
@@ -1,47 +1,65 @@
 use crate::ffi;
 use crate::session::Session;
 use crate::tensorflow as tf;
+use crate::tensorflow::compression;
 use crate::tensorflow::saved_model::SavedModel;
 use crate::{Error, Result};
 
-use protobuf::{ProtobufEnum, RepeatedField};
-use protocols::tensorflow::{TFDataType, TFNode, TFTensor, TensorflowModelRunRequest};
+use protobuf::RepeatedField;
+use protocols::error::VaccelError;
+use protocols::tensorflow::{
+    InferenceResult as ProtInferenceResult, TFNode, TFStatus, TFTensor, TensorflowModelRunRequest,
+    TensorflowModelRunResponse,
+};
 
-pub struct InferenceArgs {
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::marker::PhantomData;
+
+/// Arguments of a TensorFlow session run
+///
+/// The arguments only hold pointers to the vAccel objects of the run options,
+/// nodes and tensors, so they borrow them for `'a`. This way, the borrow
+/// checker guarantees that tensor data (including data borrowed by a
+/// `TensorView`) stays alive and unmodified until the inference finishes.
+pub struct InferenceArgs<'a> {
     run_options: *const ffi::vaccel_tf_buffer,
 
     in_nodes: Vec<ffi::vaccel_tf_node>,
     in_tensors: Vec<*const ffi::vaccel_tf_tensor>,
 
     out_nodes: Vec<ffi::vaccel_tf_node>,
+
+    _marker: PhantomData<&'a ()>,
 }
 
-impl InferenceArgs {
+impl<'a> InferenceArgs<'a> {
     pub fn new() -> Self {
         InferenceArgs {
             run_options: std::ptr::null(),
             in_nodes: vec![],
             in_tensors: vec![],
             out_nodes: vec![],
+            _marker: PhantomData,
         }
     }
 
-    pub fn set_run_options(&mut self, run_opts: &tf::Buffer) {
+    pub fn set_run_options(&mut self, run_opts: &'a tf::Buffer) {
         self.run_options = run_opts.inner();
     }
 
-    pub fn add_input(&mut self, node: &tf::Node, tensor: &dyn tf::TensorAny) {
+    pub fn add_input(&mut self, node: &'a tf::Node, tensor: &'a dyn tf::TensorAny) {
         self.in_nodes.push(unsafe { *node.inner() });
         self.in_tensors.push(tensor.inner());
     }
 
-    pub fn request_output(&mut self, node: &tf::Node) {
+    pub fn request_output(&mut self, node: &'a tf::Node) {
         self.out_nodes.push(unsafe { *node.inner() });
     }
 }
 
-impl From<InferenceArgs> for TensorflowModelRunRequest {
-    fn from(args: InferenceArgs) -> Self {
+impl<'a> From<InferenceArgs<'a>> for TensorflowModelRunRequest {
+    fn from(args: InferenceArgs<'a>) -> Self {
         let in_nodes: Vec<TFNode> = args.in_nodes.into_iter().map(|ref e| e.into()).collect();
         let out_nodes: Vec<TFNode> = args.out_nodes.into_iter().map(|ref e| e.into()).collect();
         let in_tensors: Vec<TFTensor> = args
@@ -49,13 +67,17 @@ impl From<InferenceArgs> for TensorflowModelRunRequest {
             .into_iter()
             .map(|e| unsafe { e.as_ref().unwrap().into() })
             .collect();
-        let run_options = unsafe {
-            std::slice::from_raw_parts(
-                (*args.run_options).data as *const u8,
-                (*args.run_options).size as usize,
-            )
-        }
-        .to_owned();
+        let run_options = if args.run_options.is_null() {
+            Vec::new()
+        } else {
+            unsafe {
+                std::slice::from_raw_parts(
+                    (*args.run_options).data as *const u8,
+                    (*args.run_options).size as usize,
+                )
+            }
+            .to_owned()
+        };
 
         TensorflowModelRunRequest {
             in_nodes: RepeatedField::from_vec(in_nodes),
@@ -67,6 +89,120 @@ impl From<InferenceArgs> for TensorflowModelRunRequest {
     }
 }
 
+/// Owned arguments of a TensorFlow session run
+///
+/// Unlike `InferenceArgs`, these own the run options, nodes and tensors of
+/// the run, e.g. when they are decoded from a `TensorflowModelRunRequest`.
+/// The tensors are released when the arguments are dropped.
+pub struct OwnedInferenceArgs {
+    run_options: tf::Buffer,
+    in_nodes: Vec<tf::Node>,
+    in_tensors: Vec<tf::OwnedVaccelTensor>,
+    out_nodes: Vec<tf::Node>,
+}
+
+impl OwnedInferenceArgs {
+    fn new(
+        run_options: Vec<u8>,
+        in_nodes: &[TFNode],
+        in_tensors: Vec<tf::OwnedVaccelTensor>,
+        out_nodes: &[TFNode],
+    ) -> Result<Self> {
+        if in_nodes.len() != in_tensors.len() {
+            return Err(Error::InvalidArgument);
+        }
+
+        Ok(OwnedInferenceArgs {
+            run_options: tf::Buffer::from_vec(run_options),
+            in_nodes: in_nodes
+                .iter()
+                .map(tf::Node::try_from)
+                .collect::<Result<_>>()?,
+            in_tensors,
+            out_nodes: out_nodes
+                .iter()
+                .map(tf::Node::try_from)
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Get the arguments to pass to `SavedModel::session_run`
+    pub fn args(&self) -> InferenceArgs<'_> {
+        let mut args = InferenceArgs::new();
+        args.set_run_options(&self.run_options);
+        for (node, tensor) in self.in_nodes.iter().zip(self.in_tensors.iter()) {
+            args.add_input(node, tensor);
+        }
+        for node in self.out_nodes.iter() {
+            args.request_output(node);
+        }
+
+        args
+    }
+
+    pub fn in_nodes(&self) -> &[tf::Node] {
+        &self.in_nodes
+    }
+
+    pub fn in_tensors(&self) -> &[tf::OwnedVaccelTensor] {
+        &self.in_tensors
+    }
+
+    pub fn out_nodes(&self) -> &[tf::Node] {
+        &self.out_nodes
+    }
+}
+
+/// Copy the arguments of a `TensorflowModelRunRequest`
+///
+/// Compressed tensor data is decompressed.
+impl TryFrom<&TensorflowModelRunRequest> for OwnedInferenceArgs {
+    type Error = Error;
+
+    fn try_from(req: &TensorflowModelRunRequest) -> Result<Self> {
+        let in_tensors = req
+            .get_in_tensors()
+            .iter()
+            .map(|t| {
+                let data = compression::decompressed_data(t)?.into_owned();
+                tf::OwnedVaccelTensor::new(t.get_dims(), t.get_field_type(), data)
+            })
+            .collect::<Result<_>>()?;
+
+        OwnedInferenceArgs::new(
+            req.get_run_options().to_owned(),
+            req.get_in_nodes(),
+            in_tensors,
+            req.get_out_nodes(),
+        )
+    }
+}
+
+/// Move the arguments of a `TensorflowModelRunRequest`, without copying the tensor data
+///
+/// Compressed tensor data is decompressed.
+impl TryFrom<TensorflowModelRunRequest> for OwnedInferenceArgs {
+    type Error = Error;
+
+    fn try_from(mut req: TensorflowModelRunRequest) -> Result<Self> {
+        let in_tensors = req
+            .take_in_tensors()
+            .into_iter()
+            .map(|mut t| {
+                compression::decompress(&mut t)?;
+                tf::OwnedVaccelTensor::try_from(t)
+            })
+            .collect::<Result<_>>()?;
+
+        OwnedInferenceArgs::new(
+            req.take_run_options(),
+            req.get_in_nodes(),
+            in_tensors,
+            req.get_out_nodes(),
+        )
+    }
+}
+
 pub struct InferenceResult {
     out_tensors: Vec<*mut ffi::vaccel_tf_tensor>,
     status: tf::Status,
@@ -89,22 +225,40 @@ impl InferenceResult {
         }
     }
 
+    /// Get the status of the run, as reported by TensorFlow
+    pub fn status(&self) -> &tf::Status {
+        &self.status
+    }
+
+    /// Get a copy of an output tensor
     pub fn get_output<T: tf::TensorType>(&self, id: usize) -> Result<tf::Tensor<T>> {
+        tf::Tensor::from_grpc(&self.get_grpc_output(id)?)
+    }
+
+    /// Take ownership of an output tensor, whatever its data type
+    ///
+    /// The output is removed from the result, so subsequent calls for the
+    /// same `id` fail with `Code::Unavailable`.
+    pub fn take_output(&mut self, id: usize) -> Result<tf::AnyTensor> {
         if id >= self.out_tensors.len() {
             return Err(Error::TensorFlow(tf::Code::OutOfRange));
         }
 
-        let t = self.out_tensors[id];
+        let t = std::mem::replace(&mut self.out_tensors[id], std::ptr::null_mut());
         if t.is_null() {
             return Err(Error::TensorFlow(tf::Code::Unavailable));
         }
 
-        let inner_data_type = unsafe { tf::DataType::from_int((*t).data_type) };
-        if inner_data_type != T::data_type() {
-            return Err(Error::TensorFlow(tf::Code::InvalidArgument));
-        }
+        unsafe { tf::AnyTensor::from_vaccel_tensor(t) }
+    }
 
-        Ok(unsafe { tf::Tensor::from_vaccel_tensor(t).unwrap() })
+    /// Get the metadata of the run
+    ///
+    /// `vaccel_tf_session_run` has no way to return the `tensorflow.RunMetadata`
+    /// of a run, so this always fails with `VACCEL_ENOTSUP`, whatever the
+    /// trace level of the run options.
+    pub fn run_metadata(&self) -> Result<tf::RunMetadata> {
+        Err(Error::Runtime(ffi::VACCEL_ENOTSUP))
     }
 
     pub fn get_grpc_output(&self, id: usize) -> Result<TFTensor> {
@@ -117,19 +271,56 @@ impl InferenceResult {
             return Err(Error::TensorFlow(tf::Code::Unavailable));
         }
 
-        unsafe {
-            Ok(TFTensor {
-                dims: std::slice::from_raw_parts((*t).dims as *mut u64, (*t).nr_dims as usize)
-                    .to_owned(),
-                field_type: TFDataType::from_i32((*t).data_type as i32).unwrap(),
-                data: std::slice::from_raw_parts((*t).data as *mut u8, (*t).size as usize)
-                    .to_owned(),
-                ..Default::default()
-            })
+        Ok(TFTensor::from(unsafe { &*t }))
+    }
+}
+
+/// Release the outputs that were not taken from the result
+impl Drop for InferenceResult {
+    fn drop(&mut self) {
+        for t in self.out_tensors.drain(..) {
+            if !t.is_null() {
+                unsafe { ffi::vaccel_tf_tensor_destroy(t) };
+            }
         }
     }
 }
 
+/// Convert the result of a run to the response sent back to clients
+///
+/// The output tensors are copied to the response and released. If an output
+/// is missing, e.g. because it was taken from the result, the response
+/// carries an error instead.
+impl From<InferenceResult> for TensorflowModelRunResponse {
+    fn from(mut result: InferenceResult) -> Self {
+        let mut resp = TensorflowModelRunResponse::new();
+
+        let mut out_tensors = Vec::with_capacity(result.out_tensors.len());
+        let mut missing = false;
+        for t in result.out_tensors.drain(..) {
+            if t.is_null() {
+                missing = true;
+                continue;
+            }
+
+            out_tensors.push(TFTensor::from(unsafe { &*t }));
+            unsafe { ffi::vaccel_tf_tensor_destroy(t) };
+        }
+
+        if missing {
+            resp.set_error(VaccelError::from(&Error::TensorFlow(tf::Code::Unavailable)));
+            return resp;
+        }
+
+        let mut inference = ProtInferenceResult::new();
+        inference.set_out_tensors(RepeatedField::from_vec(out_tensors));
+        inference.set_status(TFStatus::from(&result.status));
+        resp.set_result(inference);
+
+        resp
+    }
+}
+
 impl SavedModel {
     /// Load a TensorFlow session from a SavedModel
     ///
@@ -154,16 +345,42 @@ impl SavedModel {
         }
     }
 
+    /// Load a TensorFlow session from a SavedModel, deleting it when done
+    ///
+    /// This is the same as `session_load`, except that the session is deleted
+    /// when the returned handle is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `sess` - The session in the context of which we perform the operation. The model needs
+    /// to be registered with this session.
+    pub fn load<'a>(&'a mut self, sess: &'a mut Session) -> Result<LoadedModel<'a>> {
+        let status = self.session_load(sess)?;
+
+        Ok(LoadedModel {
+            model: self,
+            sess,
+            status,
+            loaded: true,
+        })
+    }
+
     /// Run a TensorFlow session
     ///
     /// This will run using a TensorFlow session that has been previously loaded
     /// using `vaccel_tf_model::load_session`.
     ///
+    /// If the model carries signature metadata, the inputs are checked against
+    /// it before reaching the runtime, and mismatching inputs are rejected with
+    /// `Error::InputMismatch`.
+    ///
     pub fn session_run(
         &mut self,
         sess: &mut Session,
         args: &mut InferenceArgs,
     ) -> Result<InferenceResult> {
+        self.validate_inputs(&args.in_nodes, &args.in_tensors)?;
+
         let mut result = InferenceResult::new(args.out_nodes.len());
 
         match unsafe {
@@ -185,6 +402,63 @@ impl SavedModel {
         }
     }
 
+    /// Run a TensorFlow session through one of the model signatures
+    ///
+    /// The logical input and output names of the signature are resolved to
+    /// the underlying graph nodes, so callers do not need to know them. All
+    /// the outputs of the signature are requested.
+    ///
+    /// # Arguments
+    ///
+    /// * `sess` - The session in the context of which we perform the operation
+    /// * `name` - The name of the signature, e.g. `serving_default`
+    /// * `inputs` - The input tensors, keyed by their name in the signature. Every input of the
+    /// signature must be given.
+    ///
+    /// Returns the output tensors, keyed by their name in the signature
+    pub fn run_signature(
+        &mut self,
+        sess: &mut Session,
+        name: &str,
+        inputs: HashMap<&str, &dyn tf::TensorAny>,
+    ) -> Result<HashMap<String, tf::AnyTensor>> {
+        let signature = self.signature(name)?;
+
+        if inputs
+            .keys()
+            .any(|key| !signature.inputs.contains_key(*key))
+        {
+            return Err(Error::TensorFlow(tf::Code::InvalidArgument));
+        }
+
+        let in_nodes = signature
+            .inputs
+            .iter()
+            .map(|(key, info)| match inputs.get(key.as_str()) {
+                Some(tensor) => Ok((info.node(), *tensor)),
+                None => Err(Error::TensorFlow(tf::Code::InvalidArgument)),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let out_nodes: Vec<tf::Node> = signature.outputs.values().map(|i| i.node()).collect();
+
+        let mut args = InferenceArgs::new();
+        for (node, tensor) in in_nodes.iter() {
+            args.add_input(node, *tensor);
+        }
+        for node in out_nodes.iter() {
+            args.request_output(node);
+        }
+
+        let mut result = self.session_run(sess, &mut args)?;
+
+        signature
+            .outputs
+            .keys()
+            .enumerate()
+            .map(|(id, key)| Ok((key.clone(), result.take_output(id)?)))
+            .collect()
+    }
+
     /// Delete a TensorFlow session
     ///
     /// This will unload a TensorFlow session that was previously loaded in memory
@@ -201,3 +475,62 @@ impl SavedModel {
         }
     }
 }
+
+/// A TensorFlow session loaded for a SavedModel
+///
+/// The session is deleted when the handle is dropped. The handle mutably
+/// borrows both the model and the vAccel session, so the model cannot be
+/// loaded twice in the same session, or used after the session is deleted.
+pub struct LoadedModel<'a> {
+    model: &'a mut SavedModel,
+    sess: &'a mut Session,
+    status: tf::Status,
+    loaded: bool,
+}
+
+impl<'a> LoadedModel<'a> {
+    /// Get the status returned by the runtime when loading the session
+    pub fn status(&self) -> &tf::Status {
+        &self.status
+    }
+
+    /// Get the model of the session
+    pub fn model(&self) -> &SavedModel {
+        self.model
+    }
+
+    /// Run the TensorFlow session
+    ///
+    /// See `SavedModel::session_run`.
+    pub fn run(&mut self, args: &mut InferenceArgs) -> Result<InferenceResult> {
+        self.model.session_run(self.sess, args)
+    }
+
+    /// Run the TensorFlow session through one of the model signatures
+    ///
+    /// See `SavedModel::run_signature`.
+    pub fn run_signature(
+        &mut self,
+        name: &str,
+        inputs: HashMap<&str, &dyn tf::TensorAny>,
+    ) -> Result<HashMap<String, tf::AnyTensor>> {
+        self.model.run_signature(self.sess, name, inputs)
+    }
+
+    /// Delete the TensorFlow session
+    ///
+    /// This is what dropping the handle does, but any error of the runtime is
+    /// returned instead of being ignored.
+    pub fn unload(mut self) -> Result<()> {
+        self.loaded = false;
+        self.model.session_delete(self.sess)
+    }
+}
+
+impl<'a> Drop for LoadedModel<'a> {
+    fn drop(&mut self) {
+        if self.loaded {
+            let _ = self.model.session_delete(self.sess);
+        }
+    }
+}
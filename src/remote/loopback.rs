@@ -0,0 +1,477 @@
+use crate::agent::Dispatcher;
+use crate::remote::transport::Transport;
+use crate::{ffi, Error, Result};
+
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A fault to inject in a call of a `Loopback` transport
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// Fail the call with `Error::Runtime(code)`, without reaching the agent
+    Reject(u32),
+
+    /// Fail the call with `Error::Agent(code)`, without reaching the agent
+    RejectAgent(i64),
+
+    /// Handle the call on the agent, but lose its response
+    ///
+    /// The call fails with a timeout once the agent has handled the request,
+    /// e.g. to check that retries do not apply a request twice.
+    LoseResponse,
+
+    /// Delay the call on the agent, in addition to the latency of the transport
+    Delay(Duration),
+}
+
+struct Request {
+    seq: u64,
+    method: String,
+    data: Vec<u8>,
+    delay: Duration,
+}
+
+// Errors cross the channel as they are, so that clients can tell apart e.g.
+// an `Error::Agent` from an I/O error of the handler
+struct Response {
+    seq: u64,
+    result: Result<Vec<u8>>,
+}
+
+/// A transport to an in-process agent
+///
+/// The agent runs on its own thread, which owns the handler of the requests,
+/// typically a `Dispatcher`, so the whole pipeline of a `RemoteSession` runs
+/// without a VM or a socket. Requests and responses go through channels.
+///
+/// Calls can be delayed, time out and fail on purpose, to exercise the error
+/// paths of clients. Errors of the handler reach the client unchanged. As with a real agent, requests are handled one at a
+/// time, so a request that timed out still delays the ones after it. The
+/// agent thread stops, dropping its handler, when the transport is dropped.
+pub struct Loopback {
+    requests: Option<Sender<Request>>,
+    responses: Receiver<Response>,
+    thread: Option<JoinHandle<()>>,
+
+    seq: u64,
+    latency: Duration,
+    timeout: Option<Duration>,
+
+    // Faults to inject, with the method they apply to, if not any
+    faults: Vec<(Option<String>, Fault)>,
+}
+
+impl Loopback {
+    /// Start an agent that executes requests on the local vAccel runtime
+    pub fn new() -> Result<Self> {
+        Self::spawn(Dispatcher::new)
+    }
+
+    /// Start an agent with a custom handler
+    ///
+    /// The handler is created on the agent thread, so it does not need to be
+    /// `Send`.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - Creates the handler of the requests
+    pub fn spawn<F, T>(handler: F) -> Result<Self>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Transport,
+    {
+        let (requests, agent_requests) = mpsc::channel::<Request>();
+        let (agent_responses, responses) = mpsc::channel();
+
+        let thread = thread::Builder::new()
+            .name("vaccel-loopback".to_owned())
+            .spawn(move || {
+                let mut handler = handler();
+
+                for req in agent_requests {
+                    if !req.delay.is_zero() {
+                        thread::sleep(req.delay);
+                    }
+
+                    let result = handler.call(&req.method, &req.data);
+
+                    // The client is gone
+                    if agent_responses
+                        .send(Response {
+                            seq: req.seq,
+                            result,
+                        })
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(Loopback {
+            requests: Some(requests),
+            responses,
+            thread: Some(thread),
+            seq: 0,
+            latency: Duration::default(),
+            timeout: None,
+            faults: Vec::new(),
+        })
+    }
+
+    /// Delay every call by `latency`
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Fail calls whose response takes longer than `timeout`
+    ///
+    /// Calls that time out fail with an `io::ErrorKind::TimedOut` error. Their
+    /// response is discarded when it arrives.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn set_latency(&mut self, latency: Duration) {
+        self.latency = latency;
+    }
+
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Inject a fault in the next call
+    ///
+    /// Faults apply once, in the order they were injected.
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The method of the call to inject the fault in, or `None` for any call
+    /// * `fault` - The fault to inject
+    pub fn inject(&mut self, method: Option<&str>, fault: Fault) {
+        self.faults.push((method.map(str::to_owned), fault));
+    }
+
+    /// Get the number of injected faults that have not applied yet
+    pub fn pending_faults(&self) -> usize {
+        self.faults.len()
+    }
+
+    /// Take the first fault that applies to a call of `method`
+    fn take_fault(&mut self, method: &str) -> Option<Fault> {
+        let pos = self
+            .faults
+            .iter()
+            .position(|(m, _)| m.is_none() || m.as_deref() == Some(method))?;
+
+        Some(self.faults.remove(pos).1)
+    }
+
+    /// Wait for the response of the request `seq`
+    fn receive(&mut self, seq: u64) -> Result<Vec<u8>> {
+        loop {
+            let resp = match self.timeout {
+                Some(timeout) => self
+                    .responses
+                    .recv_timeout(timeout)
+                    .map_err(|err| match err {
+                        RecvTimeoutError::Timeout => timed_out(),
+                        RecvTimeoutError::Disconnected => disconnected(),
+                    })?,
+                None => self.responses.recv().map_err(|_| disconnected())?,
+            };
+
+            // Responses of requests that timed out
+            if resp.seq < seq {
+                continue;
+            }
+
+            return resp.result;
+        }
+    }
+}
+
+impl Transport for Loopback {
+    fn call(&mut self, method: &str, request: &[u8]) -> Result<Vec<u8>> {
+        let fault = self.take_fault(method);
+        match fault {
+            Some(Fault::Reject(code)) => return Err(Error::Runtime(code)),
+            Some(Fault::RejectAgent(code)) => return Err(Error::Agent(code)),
+            _ => (),
+        }
+
+        let delay = match fault {
+            Some(Fault::Delay(delay)) => self.latency + delay,
+            _ => self.latency,
+        };
+
+        self.seq += 1;
+        self.requests
+            .as_ref()
+            .ok_or_else(disconnected)?
+            .send(Request {
+                seq: self.seq,
+                method: method.to_owned(),
+                data: request.to_owned(),
+                delay,
+            })
+            .map_err(|_| disconnected())?;
+
+        let resp = self.receive(self.seq)?;
+        if fault == Some(Fault::LoseResponse) {
+            return Err(timed_out());
+        }
+
+        Ok(resp)
+    }
+}
+
+impl Drop for Loopback {
+    fn drop(&mut self) {
+        // Closing the channel stops the agent thread
+        self.requests = None;
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn timed_out() -> Error {
+    Error::IO(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "vAccel agent did not respond",
+    ))
+}
+
+// The agent thread stopped, e.g. because the handler panicked
+fn disconnected() -> Error {
+    Error::Runtime(ffi::VACCEL_EIO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops::genop::GenopArg;
+    use crate::ops::inference::InferenceArgs;
+    use crate::remote::transport::method;
+    use crate::remote::RemoteSession;
+    use crate::tensorflow as tf;
+    use crate::VaccelId;
+
+    use protocols::resources::CreateResourceRequest;
+
+    use std::convert::TryFrom;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// A handler echoing `method:request`, counting the requests it handled
+    struct Echo {
+        handled: Arc<AtomicUsize>,
+    }
+
+    impl Transport for Echo {
+        fn call(&mut self, method: &str, request: &[u8]) -> Result<Vec<u8>> {
+            self.handled.fetch_add(1, Ordering::SeqCst);
+            match method {
+                "fail" => return Err(Error::InvalidArgument),
+                "agent" => return Err(Error::Agent(-7)),
+                _ => (),
+            }
+
+            let mut resp = format!("{}:", method).into_bytes();
+            resp.extend_from_slice(request);
+            Ok(resp)
+        }
+    }
+
+    fn echo() -> (Loopback, Arc<AtomicUsize>) {
+        let handled = Arc::new(AtomicUsize::new(0));
+        let counter = handled.clone();
+        let loopback = Loopback::spawn(move || Echo { handled: counter }).unwrap();
+
+        (loopback, handled)
+    }
+
+    fn is_timeout<T>(result: Result<T>) -> bool {
+        matches!(result, Err(Error::IO(err)) if err.kind() == io::ErrorKind::TimedOut)
+    }
+
+    #[test]
+    fn loopback_round_trip() {
+        let (mut loopback, handled) = echo();
+
+        assert_eq!(loopback.call("a", b"1").unwrap(), b"a:1");
+        assert_eq!(loopback.call("b", b"2").unwrap(), b"b:2");
+        assert_eq!(handled.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn loopback_keeps_errors() {
+        let (mut loopback, handled) = echo();
+
+        assert!(matches!(
+            loopback.call("fail", b""),
+            Err(Error::InvalidArgument)
+        ));
+        assert!(matches!(loopback.call("agent", b""), Err(Error::Agent(-7))));
+
+        loopback.inject(None, Fault::RejectAgent(-3));
+        assert!(matches!(loopback.call("a", b""), Err(Error::Agent(-3))));
+        assert_eq!(handled.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn loopback_reject() {
+        let (mut loopback, handled) = echo();
+
+        loopback.inject(Some("a"), Fault::Reject(ffi::VACCEL_EBUSY));
+        assert_eq!(loopback.call("b", b"").unwrap(), b"b:");
+        assert_eq!(loopback.pending_faults(), 1);
+
+        assert!(matches!(
+            loopback.call("a", b""),
+            Err(Error::Runtime(ffi::VACCEL_EBUSY))
+        ));
+        assert_eq!(loopback.pending_faults(), 0);
+        assert_eq!(handled.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn loopback_lose_response() {
+        let (mut loopback, handled) = echo();
+
+        loopback.inject(None, Fault::LoseResponse);
+        assert!(is_timeout(loopback.call("a", b"1")));
+        assert_eq!(handled.load(Ordering::SeqCst), 1);
+
+        assert_eq!(loopback.call("a", b"2").unwrap(), b"a:2");
+    }
+
+    #[test]
+    fn loopback_discards_late_responses() {
+        let (loopback, handled) = echo();
+        let mut loopback = loopback.with_timeout(Duration::from_millis(50));
+
+        loopback.inject(None, Fault::Delay(Duration::from_millis(200)));
+        assert!(is_timeout(loopback.call("a", b"late")));
+
+        // Waits for the late response, which must not be taken for this one
+        loopback.set_timeout(Some(Duration::from_secs(5)));
+        assert_eq!(loopback.call("a", b"next").unwrap(), b"a:next");
+        assert_eq!(handled.load(Ordering::SeqCst), 2);
+    }
+
+    fn lstm2_request() -> CreateResourceRequest {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/files/tf/lstm2");
+        let model = tf::SavedModel::new()
+            .from_export_dir_in_memory(&path)
+            .unwrap();
+
+        CreateResourceRequest::try_from(&model).unwrap()
+    }
+
+    fn run_lstm2(sess: &mut RemoteSession<Loopback>, model: VaccelId) -> Result<Vec<u64>> {
+        let in_node = tf::Node::new("serving_default_input_1", 0);
+        let out_node = tf::Node::new("StatefulPartitionedCall", 0);
+        let input = tf::Tensor::<f32>::new(&[1, 30]);
+        let mut args = InferenceArgs::new();
+        args.add_input(&in_node, &input);
+        args.request_output(&out_node);
+
+        let result = sess.tf_model_run(model, args)?;
+        Ok(result.get_output::<f32>(0)?.dims().to_vec())
+    }
+
+    #[test]
+    fn loopback_remote_session() {
+        let loopback = Loopback::new()
+            .unwrap()
+            .with_latency(Duration::from_millis(5));
+        let mut sess = RemoteSession::new(loopback, 0).unwrap();
+        assert!(sess.id().has_id());
+
+        let mut model = sess.create_resource(&lstm2_request()).unwrap();
+        sess.register(&mut model).unwrap();
+
+        let mut input = vec![1u8, 2, 3];
+        let mut output = vec![0u8; 3];
+        let mut read = [GenopArg::new(&mut input, 3)];
+        let mut write = [GenopArg::new(&mut output, 3)];
+        sess.genop(&mut read, &mut write).unwrap();
+
+        sess.tf_model_load(model.id()).unwrap();
+        assert_eq!(run_lstm2(&mut sess, model.id()).unwrap(), [1, 2]);
+        sess.tf_model_unload(model.id()).unwrap();
+
+        sess.unregister(&mut model).unwrap();
+        sess.destroy_resource(model).unwrap();
+        sess.close().unwrap();
+
+        // Errors of the agent reach the client unchanged
+        assert!(matches!(
+            sess.tf_model_load(VaccelId::from(1i64)),
+            Err(Error::Runtime(ffi::VACCEL_ESESS))
+        ));
+        assert!(matches!(
+            sess.transport().call(method::CREATE_SESSION, &[0xff]),
+            Err(Error::Protobuf(_))
+        ));
+    }
+
+    #[test]
+    fn loopback_remote_session_faults() {
+        let loopback = Loopback::new()
+            .unwrap()
+            .with_latency(Duration::from_millis(1));
+        let mut sess = RemoteSession::new(loopback, 0).unwrap();
+
+        // Rejected calls never reach the agent
+        sess.transport().inject(
+            Some(method::CREATE_RESOURCE),
+            Fault::Reject(ffi::VACCEL_EBUSY),
+        );
+        assert!(matches!(
+            sess.create_resource(&lstm2_request()),
+            Err(Error::Runtime(ffi::VACCEL_EBUSY))
+        ));
+        let mut model = sess.create_resource(&lstm2_request()).unwrap();
+
+        // The agent registers the model, but the client does not know it
+        sess.transport()
+            .inject(Some(method::REGISTER_RESOURCE), Fault::LoseResponse);
+        assert!(is_timeout(sess.register(&mut model)));
+        sess.tf_model_load(model.id()).unwrap();
+
+        sess.transport()
+            .inject(Some(method::TENSORFLOW_MODEL_RUN), Fault::RejectAgent(-1));
+        assert!(matches!(
+            run_lstm2(&mut sess, model.id()),
+            Err(Error::Agent(-1))
+        ));
+
+        // A slow run times out, and its late response is not taken for the
+        // next one
+        sess.transport()
+            .set_timeout(Some(Duration::from_millis(20)));
+        sess.transport().inject(
+            Some(method::TENSORFLOW_MODEL_RUN),
+            Fault::Delay(Duration::from_millis(200)),
+        );
+        assert!(is_timeout(run_lstm2(&mut sess, model.id())));
+        sess.transport().set_timeout(Some(Duration::from_secs(5)));
+        assert_eq!(run_lstm2(&mut sess, model.id()).unwrap(), [1, 2]);
+        assert_eq!(sess.transport().pending_faults(), 0);
+
+        sess.tf_model_unload(model.id()).unwrap();
+        sess.unregister(&mut model).unwrap();
+        sess.destroy_resource(model).unwrap();
+        sess.close().unwrap();
+    }
+}
@@ -0,0 +1,11 @@
+pub mod loopback;
+pub mod record;
+pub mod session;
+pub mod transport;
+pub mod ttrpc;
+
+pub use loopback::{Fault, Loopback};
+pub use record::{Exchange, Mismatch, RecordedSession, Recorder, Replayer};
+pub use session::{RemoteInferenceResult, RemoteResource, RemoteSession};
+pub use transport::{method, Transport};
+pub use ttrpc::TtrpcTransport;
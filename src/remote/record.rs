@@ -0,0 +1,783 @@
+//! Recording and replay of the requests sent to a vAccel agent
+//!
+//! Recording wraps a `Transport`, not a `Session`: requests are recorded as
+//! they are sent to an agent. Sessions on the local vAccel runtime are
+//! recorded with `Recorder::local_session`, which runs a `RemoteSession` over
+//! a recorder of a `Dispatcher` executing the requests locally.
+
+use crate::agent::Dispatcher;
+use crate::ffi;
+use crate::remote::session::RemoteSession;
+use crate::remote::transport::{method, Transport};
+use crate::tensorflow::compression;
+use crate::tensorflow::DataType;
+use crate::{Error, Result};
+
+use protobuf::Message;
+use protocols::genop::{GenopRequest, GenopResponse};
+use protocols::image::ImageClassificationRequest;
+use protocols::resources::{
+    CreateResourceResponse, DestroyResourceRequest, RegisterResourceRequest,
+    UnregisterResourceRequest,
+};
+use protocols::session::{CreateSessionResponse, DestroySessionRequest};
+use protocols::tensorflow::{
+    TFTensor, TensorflowModelLoadRequest, TensorflowModelLoadResponse, TensorflowModelRunRequest,
+    TensorflowModelRunResponse, TensorflowModelUnloadRequest,
+};
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::io::{self, BufReader, Read, Write};
+
+fn write_varint(data: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        data.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+/// Read a varint, or `None` if the reader is at its end
+fn read_varint<R: Read>(reader: &mut R) -> Result<Option<u64>> {
+    let mut value = 0;
+    for i in 0..10 {
+        let mut byte = [0];
+        if reader.read(&mut byte)? == 0 {
+            if i == 0 {
+                return Ok(None);
+            }
+            return Err(truncated());
+        }
+
+        value |= ((byte[0] & 0x7f) as u64) << (7 * i);
+        if byte[0] < 0x80 {
+            return Ok(Some(value));
+        }
+    }
+
+    Err(Error::InvalidArgument)
+}
+
+fn read_bytes<R: Read>(reader: &mut R, len: u64) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(len).read_to_end(&mut data)?;
+    if data.len() as u64 != len {
+        return Err(truncated());
+    }
+
+    Ok(data)
+}
+
+fn truncated() -> Error {
+    Error::IO(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "truncated recording",
+    ))
+}
+
+/// A request and its response, as sent over a `Transport`
+///
+/// In a recording, each exchange is stored as the method name, the status of
+/// the call, the request and the response. The name, request and response
+/// are length-delimited, the status is a varint. The status is 0 on success,
+/// or the vAccel error code of the failure, in which case the response is
+/// empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exchange {
+    pub method: String,
+    pub request: Vec<u8>,
+    pub status: u32,
+    pub response: Vec<u8>,
+}
+
+impl Exchange {
+    fn new(method: &str, request: &[u8], response: &Result<Vec<u8>>) -> Self {
+        let (status, response) = match response {
+            Ok(data) => (ffi::VACCEL_OK, data.clone()),
+            Err(err) => (err.runtime_code(), Vec::new()),
+        };
+
+        Exchange {
+            method: method.to_owned(),
+            request: request.to_owned(),
+            status,
+            response,
+        }
+    }
+
+    /// Write the exchange to a recording
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let mut data = Vec::new();
+        write_varint(&mut data, self.method.len() as u64);
+        data.extend_from_slice(self.method.as_bytes());
+        write_varint(&mut data, self.status as u64);
+        write_varint(&mut data, self.request.len() as u64);
+        data.extend_from_slice(&self.request);
+        write_varint(&mut data, self.response.len() as u64);
+        data.extend_from_slice(&self.response);
+
+        writer.write_all(&data)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Read the next exchange of a recording
+    ///
+    /// Returns `None` at the end of the recording.
+    pub fn read<R: Read>(reader: &mut R) -> Result<Option<Self>> {
+        let method = match read_varint(reader)? {
+            Some(len) => read_bytes(reader, len)?,
+            None => return Ok(None),
+        };
+        let method = String::from_utf8(method).map_err(|_| Error::InvalidArgument)?;
+        let status = read_varint(reader)?.ok_or_else(truncated)? as u32;
+        let request = read_varint(reader)?.ok_or_else(truncated)?;
+        let request = read_bytes(reader, request)?;
+        let response = read_varint(reader)?.ok_or_else(truncated)?;
+        let response = read_bytes(reader, response)?;
+
+        Ok(Some(Exchange {
+            method,
+            request,
+            status,
+            response,
+        }))
+    }
+
+    /// Read all the exchanges of a recording
+    pub fn read_all<R: Read>(reader: R) -> Result<Vec<Self>> {
+        let mut reader = BufReader::new(reader);
+        let mut exchanges = Vec::new();
+        while let Some(exchange) = Exchange::read(&mut reader)? {
+            exchanges.push(exchange);
+        }
+
+        Ok(exchanges)
+    }
+}
+
+/// A transport recording the requests sent over another transport
+///
+/// Every call is forwarded to the inner transport, and the request and its
+/// response, or error, are written to `writer`. See `local_session` to
+/// record the requests of a session on the local vAccel runtime.
+///
+/// A call fails if its exchange cannot be written, even if the inner
+/// transport succeeded.
+pub struct Recorder<T: Transport, W: Write> {
+    transport: T,
+    writer: W,
+}
+
+impl<T: Transport, W: Write> Recorder<T, W> {
+    /// Create a new recorder
+    ///
+    /// # Arguments
+    ///
+    /// * `transport` - The transport to record
+    /// * `writer` - Where to write the recording, e.g. a file
+    pub fn new(transport: T, writer: W) -> Self {
+        Recorder { transport, writer }
+    }
+
+    /// Get the inner transport and the writer of the recording
+    pub fn into_inner(self) -> (T, W) {
+        (self.transport, self.writer)
+    }
+}
+
+/// A session on the local vAccel runtime whose requests are recorded
+pub type RecordedSession<W> = RemoteSession<Recorder<Dispatcher, W>>;
+
+impl<W: Write> Recorder<Dispatcher, W> {
+    /// Create a session on the local vAccel runtime, recording its requests
+    ///
+    /// The session is a `RemoteSession` over a recorder of a `Dispatcher`, so
+    /// it is used like a remote one: resources are created with
+    /// `create_resource` and live in the dispatcher. The recording can be
+    /// replayed with a `Replayer`, and is written as requests are made. Get
+    /// the writer back with `into_transport` and `into_inner`.
+    ///
+    /// # Arguments
+    ///
+    /// * `writer` - Where to write the recording, e.g. a file
+    /// * `flags` - Flags for session creation, as for `RemoteSession::new`
+    pub fn local_session(writer: W, flags: u32) -> Result<RecordedSession<W>> {
+        RemoteSession::new(Recorder::new(Dispatcher::new(), writer), flags)
+    }
+}
+
+impl<T: Transport, W: Write> Transport for Recorder<T, W> {
+    fn call(&mut self, method: &str, request: &[u8]) -> Result<Vec<u8>> {
+        let response = self.transport.call(method, request);
+        Exchange::new(method, request, &response).write(&mut self.writer)?;
+        response
+    }
+}
+
+/// A difference between a recorded response and its replay
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    /// The index of the exchange in the recording
+    pub index: usize,
+    pub method: String,
+    pub detail: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{} {}: {}", self.index, self.method, self.detail)
+    }
+}
+
+/// Decode the elements of a tensor as `f64`, for comparison
+///
+/// Returns `None` for data types we cannot compare element by element.
+fn tensor_values(tensor: &TFTensor) -> Option<Vec<f64>> {
+    fn decode<const N: usize>(data: &[u8], f: impl Fn([u8; N]) -> f64) -> Vec<f64> {
+        data.chunks_exact(N)
+            .map(|c| f(c.try_into().unwrap()))
+            .collect()
+    }
+
+    let data = tensor.get_data();
+    Some(match DataType::from(tensor.get_field_type()) {
+        DataType::Float => decode(data, |b| f32::from_ne_bytes(b) as f64),
+        DataType::Double => decode(data, f64::from_ne_bytes),
+        DataType::Int8 => decode(data, |b| i8::from_ne_bytes(b) as f64),
+        DataType::Int16 => decode(data, |b| i16::from_ne_bytes(b) as f64),
+        DataType::Int32 => decode(data, |b| i32::from_ne_bytes(b) as f64),
+        DataType::Int64 => decode(data, |b| i64::from_ne_bytes(b) as f64),
+        DataType::UInt8 | DataType::Bool => decode(data, |b| u8::from_ne_bytes(b) as f64),
+        DataType::UInt16 => decode(data, |b| u16::from_ne_bytes(b) as f64),
+        DataType::UInt32 => decode(data, |b| u32::from_ne_bytes(b) as f64),
+        DataType::UInt64 => decode(data, |b| u64::from_ne_bytes(b) as f64),
+        _ => return None,
+    })
+}
+
+fn close(expected: f64, actual: f64, tolerance: f64) -> bool {
+    expected == actual
+        || (expected - actual).abs() <= tolerance
+        || (expected.is_nan() && actual.is_nan())
+}
+
+/// Compare two tensors, returning the first difference found
+///
+/// Compressed data is compared after decompressing it.
+fn diff_tensors(expected: &TFTensor, actual: &TFTensor, tolerance: f64) -> Option<String> {
+    let mut expected = expected.clone();
+    let mut actual = actual.clone();
+    if let Err(err) =
+        compression::decompress(&mut expected).and_then(|_| compression::decompress(&mut actual))
+    {
+        return Some(format!("undecodable data: {}", err));
+    }
+
+    if expected.get_field_type() != actual.get_field_type() {
+        return Some(format!(
+            "type {:?} != {:?}",
+            expected.get_field_type(),
+            actual.get_field_type()
+        ));
+    }
+
+    if expected.get_dims() != actual.get_dims() {
+        return Some(format!(
+            "dims {:?} != {:?}",
+            expected.get_dims(),
+            actual.get_dims()
+        ));
+    }
+
+    if expected.get_data().len() != actual.get_data().len() {
+        return Some(format!(
+            "{} bytes != {}",
+            expected.get_data().len(),
+            actual.get_data().len()
+        ));
+    }
+
+    match (tensor_values(&expected), tensor_values(&actual)) {
+        (Some(expected), Some(actual)) => expected
+            .iter()
+            .zip(actual.iter())
+            .enumerate()
+            .find(|(_, (e, a))| !close(**e, **a, tolerance))
+            .map(|(i, (e, a))| format!("element {}: {} != {}", i, e, a)),
+        _ if expected.get_data() != actual.get_data() => Some("data differs".to_owned()),
+        _ => None,
+    }
+}
+
+/// Re-encode a request after changing it
+fn patch<M: Message, F: FnOnce(&mut M)>(data: &[u8], f: F) -> Result<Vec<u8>> {
+    let mut msg = M::parse_from_bytes(data)?;
+    f(&mut msg);
+    Ok(msg.write_to_bytes()?)
+}
+
+/// Re-issues recorded requests and compares the responses
+///
+/// Sessions and resources get new ids when they are created again, so the
+/// ids in the recorded requests are replaced by the new ones before the
+/// requests are sent. Responses are compared byte by byte, except for the
+/// output tensors of TensorFlow runs, which are compared element by element
+/// with a tolerance.
+pub struct Replayer<T: Transport> {
+    transport: T,
+    tolerance: f64,
+    sessions: HashMap<u32, u32>,
+    resources: HashMap<i64, i64>,
+}
+
+impl<T: Transport> Replayer<T> {
+    /// Create a new replayer
+    ///
+    /// # Arguments
+    ///
+    /// * `transport` - Where to send the requests, e.g. a `Dispatcher` to replay them against
+    ///   the local vAccel runtime
+    pub fn new(transport: T) -> Self {
+        Replayer {
+            transport,
+            tolerance: 0.0,
+            sessions: HashMap::new(),
+            resources: HashMap::new(),
+        }
+    }
+
+    /// Set the maximum absolute difference between tensor elements
+    ///
+    /// # Arguments
+    ///
+    /// * `tolerance` - The tolerance. The default is 0, i.e. exact comparison.
+    pub fn with_tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn tolerance(&self) -> f64 {
+        self.tolerance
+    }
+
+    fn session(&self, id: u32) -> u32 {
+        *self.sessions.get(&id).unwrap_or(&id)
+    }
+
+    fn resource(&self, id: i64) -> i64 {
+        *self.resources.get(&id).unwrap_or(&id)
+    }
+
+    /// Replace the recorded ids of a request with the ones of the replay
+    fn remap(&self, method: &str, req: &[u8]) -> Result<Vec<u8>> {
+        match method {
+            method::DESTROY_SESSION => patch(req, |m: &mut DestroySessionRequest| {
+                m.set_session_id(self.session(m.get_session_id()))
+            }),
+            method::DESTROY_RESOURCE => patch(req, |m: &mut DestroyResourceRequest| {
+                m.set_resource_id(self.resource(m.get_resource_id()))
+            }),
+            method::REGISTER_RESOURCE => patch(req, |m: &mut RegisterResourceRequest| {
+                m.set_session_id(self.session(m.get_session_id()));
+                m.set_resource_id(self.resource(m.get_resource_id()));
+            }),
+            method::UNREGISTER_RESOURCE => patch(req, |m: &mut UnregisterResourceRequest| {
+                m.set_session_id(self.session(m.get_session_id()));
+                m.set_resource_id(self.resource(m.get_resource_id()));
+            }),
+            method::IMAGE_CLASSIFICATION | method::IMAGE_DETECTION | method::IMAGE_SEGMENTATION => {
+                patch(req, |m: &mut ImageClassificationRequest| {
+                    m.set_session_id(self.session(m.get_session_id()))
+                })
+            }
+            method::GENOP => patch(req, |m: &mut GenopRequest| {
+                m.set_session_id(self.session(m.get_session_id()))
+            }),
+            method::TENSORFLOW_MODEL_LOAD => patch(req, |m: &mut TensorflowModelLoadRequest| {
+                m.set_session_id(self.session(m.get_session_id()));
+                m.set_model_id(self.resource(m.get_model_id()));
+            }),
+            method::TENSORFLOW_MODEL_UNLOAD => {
+                patch(req, |m: &mut TensorflowModelUnloadRequest| {
+                    m.set_session_id(self.session(m.get_session_id()));
+                    m.set_model_id(self.resource(m.get_model_id()));
+                })
+            }
+            method::TENSORFLOW_MODEL_RUN => patch(req, |m: &mut TensorflowModelRunRequest| {
+                m.set_session_id(self.session(m.get_session_id()));
+                m.set_model_id(self.resource(m.get_model_id()));
+            }),
+            _ => Ok(req.to_owned()),
+        }
+    }
+
+    /// Remember the ids of the sessions and resources created again
+    fn learn(&mut self, method: &str, recorded: &[u8], replayed: &[u8]) -> Result<()> {
+        match method {
+            method::CREATE_SESSION => {
+                let recorded = CreateSessionResponse::parse_from_bytes(recorded)?;
+                let replayed = CreateSessionResponse::parse_from_bytes(replayed)?;
+                self.sessions
+                    .insert(recorded.get_session_id(), replayed.get_session_id());
+            }
+            method::CREATE_RESOURCE => {
+                let recorded = CreateResourceResponse::parse_from_bytes(recorded)?;
+                let replayed = CreateResourceResponse::parse_from_bytes(replayed)?;
+                self.resources
+                    .insert(recorded.get_resource_id(), replayed.get_resource_id());
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Compare the responses of an exchange, returning the first difference
+    fn diff(&self, method: &str, recorded: &[u8], replayed: &[u8]) -> Result<Option<String>> {
+        Ok(match method {
+            // The ids are expected to differ
+            method::CREATE_SESSION | method::CREATE_RESOURCE => None,
+            method::TENSORFLOW_MODEL_LOAD => {
+                let recorded = TensorflowModelLoadResponse::parse_from_bytes(recorded)?;
+                let replayed = TensorflowModelLoadResponse::parse_from_bytes(replayed)?;
+                if recorded.get_error() != replayed.get_error() {
+                    Some(format!(
+                        "error {:?} != {:?}",
+                        recorded.get_error(),
+                        replayed.get_error()
+                    ))
+                } else {
+                    None
+                }
+            }
+            method::TENSORFLOW_MODEL_RUN => {
+                let recorded = TensorflowModelRunResponse::parse_from_bytes(recorded)?;
+                let replayed = TensorflowModelRunResponse::parse_from_bytes(replayed)?;
+                self.diff_run(&recorded, &replayed)
+            }
+            method::GENOP => {
+                let recorded = GenopResponse::parse_from_bytes(recorded)?;
+                let replayed = GenopResponse::parse_from_bytes(replayed)?;
+                if recorded != replayed {
+                    Some("response differs".to_owned())
+                } else {
+                    None
+                }
+            }
+            _ if recorded != replayed => Some("response differs".to_owned()),
+            _ => None,
+        })
+    }
+
+    fn diff_run(
+        &self,
+        recorded: &TensorflowModelRunResponse,
+        replayed: &TensorflowModelRunResponse,
+    ) -> Option<String> {
+        if recorded.get_error() != replayed.get_error() {
+            return Some(format!(
+                "error {:?} != {:?}",
+                recorded.get_error(),
+                replayed.get_error()
+            ));
+        }
+
+        let recorded = recorded.get_result();
+        let replayed = replayed.get_result();
+        if recorded.get_status() != replayed.get_status() {
+            return Some(format!(
+                "status {:?} != {:?}",
+                recorded.get_status(),
+                replayed.get_status()
+            ));
+        }
+
+        let (expected, actual) = (recorded.get_out_tensors(), replayed.get_out_tensors());
+        if expected.len() != actual.len() {
+            return Some(format!("{} outputs != {}", expected.len(), actual.len()));
+        }
+
+        expected
+            .iter()
+            .zip(actual.iter())
+            .enumerate()
+            .find_map(|(i, (e, a))| {
+                diff_tensors(e, a, self.tolerance).map(|d| format!("output {}: {}", i, d))
+            })
+    }
+
+    /// Replay a recording
+    ///
+    /// Returns the differences between the recorded and the new responses. A
+    /// request that fails with a different error than the recorded one is a
+    /// difference, not an error. Errors are returned if the recording or one
+    /// of the responses cannot be decoded.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The recording, as written by a `Recorder`
+    pub fn replay<R: Read>(&mut self, reader: R) -> Result<Vec<Mismatch>> {
+        let mut reader = BufReader::new(reader);
+        let mut mismatches = Vec::new();
+
+        let mut index = 0;
+        while let Some(exchange) = Exchange::read(&mut reader)? {
+            let request = self.remap(&exchange.method, &exchange.request)?;
+            let response = self.transport.call(&exchange.method, &request);
+            let replayed = Exchange::new(&exchange.method, &request, &response);
+
+            let detail = if exchange.status != replayed.status {
+                Some(format!("status {} != {}", exchange.status, replayed.status))
+            } else if exchange.status != ffi::VACCEL_OK {
+                None
+            } else {
+                self.learn(&exchange.method, &exchange.response, &replayed.response)?;
+                self.diff(&exchange.method, &exchange.response, &replayed.response)?
+            };
+
+            if let Some(detail) = detail {
+                mismatches.push(Mismatch {
+                    index,
+                    method: exchange.method,
+                    detail,
+                });
+            }
+
+            index += 1;
+        }
+
+        Ok(mismatches)
+    }
+
+    /// Get the transport the requests are replayed on
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ops::inference::InferenceArgs;
+    use crate::tensorflow as tf;
+
+    use protocols::resources::CreateResourceRequest;
+    use protocols::session::CreateSessionRequest;
+    use protocols::tensorflow::{InferenceResult, TFDataType};
+
+    use std::convert::TryFrom;
+    use std::path::Path;
+
+    /// An agent giving ids from `next_id`, and running models that output
+    /// `[1, 2]` shifted by `delta`
+    struct Agent {
+        next_id: u32,
+        delta: f32,
+        requests: Vec<(String, Vec<u8>)>,
+    }
+
+    impl Agent {
+        fn new(next_id: u32, delta: f32) -> Self {
+            Agent {
+                next_id,
+                delta,
+                requests: Vec::new(),
+            }
+        }
+
+        fn id(&mut self) -> u32 {
+            self.next_id += 1;
+            self.next_id - 1
+        }
+    }
+
+    impl Transport for Agent {
+        fn call(&mut self, method: &str, request: &[u8]) -> Result<Vec<u8>> {
+            self.requests.push((method.to_owned(), request.to_owned()));
+
+            let resp: Box<dyn Message> = match method {
+                method::CREATE_SESSION => {
+                    let mut resp = CreateSessionResponse::new();
+                    resp.set_session_id(self.id());
+                    Box::new(resp)
+                }
+                method::CREATE_RESOURCE => {
+                    let mut resp = CreateResourceResponse::new();
+                    resp.set_resource_id(self.id().into());
+                    Box::new(resp)
+                }
+                method::TENSORFLOW_MODEL_RUN => {
+                    let mut tensor = TFTensor::new();
+                    tensor.set_dims(vec![2]);
+                    tensor.set_field_type(TFDataType::FLOAT);
+                    for value in [1.0 + self.delta, 2.0].iter() {
+                        tensor.mut_data().extend_from_slice(&value.to_ne_bytes());
+                    }
+
+                    let mut result = InferenceResult::new();
+                    result.mut_out_tensors().push(tensor);
+                    let mut resp = TensorflowModelRunResponse::new();
+                    resp.set_result(result);
+                    Box::new(resp)
+                }
+                method::DESTROY_SESSION => return Err(Error::Runtime(ffi::VACCEL_ESESS)),
+                _ => return Ok(Vec::new()),
+            };
+
+            Ok(resp.write_to_bytes()?)
+        }
+    }
+
+    /// Record a session creating a model, registering it and running it
+    fn record(agent: Agent) -> Vec<u8> {
+        let mut recorder = Recorder::new(agent, Vec::new());
+
+        recorder
+            .call(
+                method::CREATE_SESSION,
+                &CreateSessionRequest::new().write_to_bytes().unwrap(),
+            )
+            .unwrap();
+        recorder
+            .call(
+                method::CREATE_RESOURCE,
+                &CreateResourceRequest::new().write_to_bytes().unwrap(),
+            )
+            .unwrap();
+
+        let mut register = RegisterResourceRequest::new();
+        register.set_session_id(1);
+        register.set_resource_id(2);
+        recorder
+            .call(
+                method::REGISTER_RESOURCE,
+                &register.write_to_bytes().unwrap(),
+            )
+            .unwrap();
+
+        let mut run = TensorflowModelRunRequest::new();
+        run.set_session_id(1);
+        run.set_model_id(2);
+        recorder
+            .call(method::TENSORFLOW_MODEL_RUN, &run.write_to_bytes().unwrap())
+            .unwrap();
+
+        let mut destroy = DestroySessionRequest::new();
+        destroy.set_session_id(1);
+        assert!(recorder
+            .call(method::DESTROY_SESSION, &destroy.write_to_bytes().unwrap())
+            .is_err());
+
+        recorder.into_inner().1
+    }
+
+    #[test]
+    fn exchange_round_trip() {
+        let exchanges = vec![
+            Exchange::new(method::GENOP, &[1, 2, 3], &Ok(vec![4; 200])),
+            Exchange::new(
+                method::DESTROY_SESSION,
+                &[5],
+                &Err(Error::Runtime(ffi::VACCEL_ESESS)),
+            ),
+        ];
+
+        let mut data = Vec::new();
+        for exchange in exchanges.iter() {
+            exchange.write(&mut data).unwrap();
+        }
+        assert_eq!(Exchange::read_all(&data[..]).unwrap(), exchanges);
+        assert_eq!(exchanges[1].status, ffi::VACCEL_ESESS);
+        assert!(exchanges[1].response.is_empty());
+
+        data.pop();
+        assert!(Exchange::read_all(&data[..]).is_err());
+    }
+
+    #[test]
+    fn replay_remaps_ids() {
+        let recording = record(Agent::new(1, 0.0));
+        assert_eq!(Exchange::read_all(&recording[..]).unwrap().len(), 5);
+
+        let mut replayer = Replayer::new(Agent::new(10, 0.0));
+        assert!(replayer.replay(&recording[..]).unwrap().is_empty());
+
+        let agent = replayer.into_inner();
+        let register = RegisterResourceRequest::parse_from_bytes(&agent.requests[2].1).unwrap();
+        assert_eq!(register.get_session_id(), 10);
+        assert_eq!(register.get_resource_id(), 11);
+
+        let run = TensorflowModelRunRequest::parse_from_bytes(&agent.requests[3].1).unwrap();
+        assert_eq!(run.get_session_id(), 10);
+        assert_eq!(run.get_model_id(), 11);
+
+        let destroy = DestroySessionRequest::parse_from_bytes(&agent.requests[4].1).unwrap();
+        assert_eq!(destroy.get_session_id(), 10);
+    }
+
+    #[test]
+    fn replay_tolerance() {
+        let recording = record(Agent::new(1, 0.0));
+
+        let mut replayer = Replayer::new(Agent::new(1, 0.001));
+        let mismatches = replayer.replay(&recording[..]).unwrap();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].index, 3);
+        assert_eq!(mismatches[0].method, method::TENSORFLOW_MODEL_RUN);
+
+        let mut replayer = Replayer::new(Agent::new(1, 0.001)).with_tolerance(0.01);
+        assert!(replayer.replay(&recording[..]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn record_local_sessions() {
+        let lstm2 = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/files/tf/lstm2");
+        let model = tf::SavedModel::new()
+            .from_export_dir_in_memory(&lstm2)
+            .unwrap();
+        let req = CreateResourceRequest::try_from(&model).unwrap();
+
+        let mut sess = Recorder::local_session(Vec::new(), 0).unwrap();
+        let mut res = sess.create_resource(&req).unwrap();
+        sess.register(&mut res).unwrap();
+        sess.tf_model_load(res.id()).unwrap();
+
+        let input = tf::Tensor::<f32>::new(&[1, 30]);
+        let in_node = tf::Node::new("serving_default_input_1", 0);
+        let out_node = tf::Node::new("StatefulPartitionedCall", 0);
+        let mut args = InferenceArgs::new();
+        args.add_input(&in_node, &input);
+        args.request_output(&out_node);
+        let result = sess.tf_model_run(res.id(), args).unwrap();
+        assert_eq!(result.get_output::<f32>(0).unwrap().dims(), [1, 2]);
+
+        sess.tf_model_unload(res.id()).unwrap();
+        sess.close().unwrap();
+
+        let (_, recording) = sess.into_transport().into_inner();
+        let methods: Vec<_> = Exchange::read_all(&recording[..])
+            .unwrap()
+            .into_iter()
+            .map(|e| e.method)
+            .collect();
+        assert_eq!(
+            methods,
+            [
+                method::CREATE_SESSION,
+                method::CREATE_RESOURCE,
+                method::REGISTER_RESOURCE,
+                method::TENSORFLOW_MODEL_LOAD,
+                method::TENSORFLOW_MODEL_RUN,
+                method::TENSORFLOW_MODEL_UNLOAD,
+                method::DESTROY_SESSION
+            ]
+        );
+
+        let mut replayer = Replayer::new(Dispatcher::new());
+        assert!(replayer.replay(&recording[..]).unwrap().is_empty());
+    }
+}
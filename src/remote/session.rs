@@ -0,0 +1,540 @@
+use crate::ffi;
+use crate::ops::genop::GenopArg;
+use crate::ops::inference::InferenceArgs;
+use crate::remote::transport::{method, Transport};
+use crate::resource::Resource;
+use crate::tensorflow as tf;
+use crate::{Error, Result, VaccelId};
+
+use protobuf::Message;
+use protocols::genop::{GenopRequest, GenopResponse};
+use protocols::image::{ImageClassificationRequest, ImageClassificationResponse};
+use protocols::resources::{
+    CreateResourceRequest, CreateResourceResponse, DestroyResourceRequest, RegisterResourceRequest,
+    UnregisterResourceRequest,
+};
+use protocols::session::{CreateSessionRequest, CreateSessionResponse, DestroySessionRequest};
+use protocols::tensorflow::{
+    TFStatus, TFTensor, TensorflowModelLoadRequest, TensorflowModelLoadResponse,
+    TensorflowModelRunRequest, TensorflowModelRunResponse, TensorflowModelUnloadRequest,
+    TensorflowModelUnloadResponse,
+};
+
+use std::any::Any;
+
+/// A vAccel resource on a remote host
+///
+/// This is a handle to a resource created on the agent by
+/// `RemoteSession::create_resource`, to register it with remote sessions. It
+/// has no local vAccel resource, and is destroyed through
+/// `RemoteSession::destroy_resource`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemoteResource {
+    id: VaccelId,
+}
+
+impl RemoteResource {
+    /// Refer to a resource the agent created with the given id
+    pub fn from_id(id: VaccelId) -> Self {
+        RemoteResource { id }
+    }
+
+    /// Get the id of the resource on the agent
+    pub fn id(&self) -> VaccelId {
+        self.id
+    }
+}
+
+impl Resource for RemoteResource {
+    fn id(&self) -> VaccelId {
+        self.id()
+    }
+
+    fn initialized(&self) -> bool {
+        self.id.has_id()
+    }
+
+    fn to_vaccel_ptr(&self) -> Option<*const ffi::vaccel_resource> {
+        None
+    }
+
+    fn to_mut_vaccel_ptr(&self) -> Option<*mut ffi::vaccel_resource> {
+        None
+    }
+
+    /// The agent destroys remote resources, so this fails with
+    /// `VACCEL_ENOTSUP`. Use `RemoteSession::destroy_resource` instead.
+    fn destroy(&mut self) -> Result<()> {
+        Err(Error::Runtime(ffi::VACCEL_ENOTSUP))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_mut_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// A vAccel session on a remote host
+///
+/// This mirrors `Session`, except that operations are sent as `protocols`
+/// messages to a vAccel agent over a `Transport`, instead of going through
+/// the vAccel runtime library. Resources live on the agent, so they are
+/// referred to by the id the agent gave them, e.g. through a `RemoteResource`.
+///
+/// Compression of tensor payloads is requested by creating the session with
+/// one of the `tf::compression::SESSION_FLAG_*` flags. It is only used if the
+/// agent acknowledges it, such as one serving a `Dispatcher`, and is then
+/// applied to the tensors sent and removed from the tensors received.
+pub struct RemoteSession<T: Transport> {
+    transport: T,
+    session_id: u32,
+    compression: Option<tf::Compression>,
+}
+
+impl<T: Transport> RemoteSession<T> {
+    /// Create a new vAccel session on the agent
+    ///
+    /// # Arguments
+    ///
+    /// * `transport` - The transport to the agent
+    /// * `flags` - Flags for session creation. Only the compression flags are used.
+    pub fn new(mut transport: T, flags: u32) -> Result<Self> {
+        let mut req = CreateSessionRequest::new();
+        req.set_flags(flags);
+
+        let resp: CreateSessionResponse = call(&mut transport, method::CREATE_SESSION, &req)?;
+
+        Ok(RemoteSession {
+            transport,
+            session_id: resp.get_session_id(),
+            compression: tf::Compression::acknowledged(&resp),
+        })
+    }
+
+    /// Get the session id
+    pub fn id(&self) -> VaccelId {
+        VaccelId::from(self.session_id)
+    }
+
+    /// Get the compression of tensor payloads the agent acknowledged, if any
+    pub fn compression(&self) -> Option<&tf::Compression> {
+        self.compression.as_ref()
+    }
+
+    /// Set the size, in bytes, below which tensor payloads are not compressed
+    pub fn set_compression_threshold(&mut self, threshold: usize) {
+        self.compression = self.compression.map(|c| c.with_threshold(threshold));
+    }
+
+    /// Get the transport of the session
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Get the transport back, e.g. to read a recording after closing the
+    /// session
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    /// Destroy the session on the agent
+    pub fn close(&mut self) -> Result<()> {
+        let req = DestroySessionRequest::from(&*self);
+        call_empty(&mut self.transport, method::DESTROY_SESSION, &req)
+    }
+
+    /// Create a vAccel resource on the agent
+    ///
+    /// The resource is not registered with the session.
+    ///
+    /// # Arguments
+    ///
+    /// * `req` - The description of the resource, including its data
+    pub fn create_resource(&mut self, req: &CreateResourceRequest) -> Result<RemoteResource> {
+        let resp: CreateResourceResponse = call(&mut self.transport, method::CREATE_RESOURCE, req)?;
+
+        Ok(RemoteResource::from_id(VaccelId::from(&resp)))
+    }
+
+    /// Destroy a vAccel resource on the agent
+    ///
+    /// The agent unregisters the resource from its sessions first.
+    ///
+    /// # Arguments
+    ///
+    /// * `res` - The resource, as returned by `create_resource`
+    pub fn destroy_resource(&mut self, res: RemoteResource) -> Result<()> {
+        let req = DestroyResourceRequest::from(res.id());
+        call_empty(&mut self.transport, method::DESTROY_RESOURCE, &req)
+    }
+
+    /// Register a vAccel resource to the session
+    ///
+    /// # Arguments
+    ///
+    /// * `res` - The resource we are registering to the session. This should have been previously
+    ///   created on the agent, e.g. with `create_resource`
+    pub fn register(&mut self, res: &mut dyn Resource) -> Result<()> {
+        if !res.initialized() {
+            return Err(Error::Uninitialized);
+        }
+
+        let mut req = RegisterResourceRequest::new();
+        req.set_session_id(self.session_id);
+        req.set_resource_id(res.id().into());
+
+        call_empty(&mut self.transport, method::REGISTER_RESOURCE, &req)
+    }
+
+    pub fn unregister(&mut self, res: &mut dyn Resource) -> Result<()> {
+        if !res.initialized() {
+            return Err(Error::Uninitialized);
+        }
+
+        let mut req = UnregisterResourceRequest::new();
+        req.set_session_id(self.session_id);
+        req.set_resource_id(res.id().into());
+
+        call_empty(&mut self.transport, method::UNREGISTER_RESOURCE, &req)
+    }
+
+    /// Send an image operation and get the bytes of its response
+    fn image_op(&mut self, method: &str, img: &[u8]) -> Result<Vec<u8>> {
+        let mut req = ImageClassificationRequest::new();
+        req.set_session_id(self.session_id);
+        req.set_image(img.to_owned());
+
+        let mut resp: ImageClassificationResponse = call(&mut self.transport, method, &req)?;
+
+        Ok(resp.take_tags())
+    }
+
+    /// Perform image classification
+    ///
+    /// Returns the tags and the output image like
+    /// `Session::image_classification`, except that the output image is
+    /// empty, as the agent does not send it back.
+    ///
+    /// # Arguments
+    ///
+    /// * `img` - The image to classify
+    pub fn image_classification(&mut self, img: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+        let tags = self.image_op(method::IMAGE_CLASSIFICATION, img)?;
+
+        Ok((tags, Vec::new()))
+    }
+
+    /// Perform image detection
+    ///
+    /// # Arguments
+    ///
+    /// * `img` - The image to run detection on
+    pub fn image_detection(&mut self, img: &mut [u8]) -> Result<Vec<u8>> {
+        self.image_op(method::IMAGE_DETECTION, img)
+    }
+
+    /// Perform image segmentation
+    ///
+    /// # Arguments
+    ///
+    /// * `img` - The image to segment
+    pub fn image_segmentation(&mut self, img: &mut [u8]) -> Result<Vec<u8>> {
+        self.image_op(method::IMAGE_SEGMENTATION, img)
+    }
+
+    /// vAccel generic operation
+    ///
+    /// See `Session::genop`. The write arguments are replaced by the ones
+    /// returned by the agent.
+    pub fn genop(&mut self, read: &mut [GenopArg], write: &mut [GenopArg]) -> Result<()> {
+        let mut req = GenopRequest::new();
+        req.set_session_id(self.session_id);
+        req.set_read_args(read.iter().map(|arg| arg.into()).collect());
+        req.set_write_args(write.iter().map(|arg| arg.into()).collect());
+
+        let mut resp: GenopResponse = call(&mut self.transport, method::GENOP, &req)?;
+        if resp.has_error() {
+            return Err(Error::from(resp.get_error()));
+        }
+
+        for (arg, resp_arg) in write.iter_mut().zip(resp.take_result().take_write_args()) {
+            *arg = GenopArg::from(&resp_arg);
+        }
+
+        Ok(())
+    }
+
+    /// Load a TensorFlow session from a SavedModel on the agent
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The id of the model. The model needs to be registered with the session.
+    pub fn tf_model_load(&mut self, model: VaccelId) -> Result<()> {
+        let mut req = TensorflowModelLoadRequest::new();
+        req.set_session_id(self.session_id);
+        req.set_model_id(model.into());
+
+        let resp: TensorflowModelLoadResponse =
+            call(&mut self.transport, method::TENSORFLOW_MODEL_LOAD, &req)?;
+        if resp.has_error() {
+            return Err(Error::from(resp.get_error()));
+        }
+
+        Ok(())
+    }
+
+    /// Delete a TensorFlow session on the agent
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The id of the model
+    pub fn tf_model_unload(&mut self, model: VaccelId) -> Result<()> {
+        let mut req = TensorflowModelUnloadRequest::new();
+        req.set_session_id(self.session_id);
+        req.set_model_id(model.into());
+
+        let resp: TensorflowModelUnloadResponse =
+            call(&mut self.transport, method::TENSORFLOW_MODEL_UNLOAD, &req)?;
+
+        // The response does not tell us why the agent failed
+        if !resp.get_success() {
+            return Err(Error::Runtime(ffi::VACCEL_EBACKEND));
+        }
+
+        Ok(())
+    }
+
+    /// Run a TensorFlow session on the agent
+    ///
+    /// The session must have been loaded with `tf_model_load`.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The id of the model
+    /// * `args` - The arguments of the run. The tensors are copied to the request.
+    pub fn tf_model_run(
+        &mut self,
+        model: VaccelId,
+        args: InferenceArgs,
+    ) -> Result<RemoteInferenceResult> {
+        let mut req = TensorflowModelRunRequest::from(args);
+        req.set_session_id(self.session_id);
+        req.set_model_id(model.into());
+        if let Some(compression) = &self.compression {
+            for tensor in req.mut_in_tensors().iter_mut() {
+                compression.compress(tensor)?;
+            }
+        }
+
+        let mut resp: TensorflowModelRunResponse =
+            call(&mut self.transport, method::TENSORFLOW_MODEL_RUN, &req)?;
+        if resp.has_error() {
+            return Err(Error::from(resp.get_error()));
+        }
+
+        let mut result = resp.take_result();
+        Ok(RemoteInferenceResult {
+            out_tensors: result.take_out_tensors().into_iter().map(Some).collect(),
+            status: result.take_status(),
+        })
+    }
+}
+
+/// Send a request and decode its response
+fn call<T, Req, Resp>(transport: &mut T, method: &str, req: &Req) -> Result<Resp>
+where
+    T: Transport,
+    Req: Message,
+    Resp: Message,
+{
+    let resp = transport.call(method, &req.write_to_bytes()?)?;
+    Ok(Resp::parse_from_bytes(&resp)?)
+}
+
+/// Send a request that has no response message
+fn call_empty<T: Transport, Req: Message>(
+    transport: &mut T,
+    method: &str,
+    req: &Req,
+) -> Result<()> {
+    transport.call(method, &req.write_to_bytes()?)?;
+    Ok(())
+}
+
+/// Convert a `RemoteSession` to the request destroying it
+impl<T: Transport> From<&RemoteSession<T>> for DestroySessionRequest {
+    fn from(sess: &RemoteSession<T>) -> Self {
+        DestroySessionRequest {
+            session_id: sess.session_id,
+            ..Default::default()
+        }
+    }
+}
+
+/// The result of a TensorFlow session run on a remote host
+///
+/// This mirrors `InferenceResult`, holding the output tensors as sent back by
+/// the agent.
+#[derive(Debug)]
+pub struct RemoteInferenceResult {
+    out_tensors: Vec<Option<TFTensor>>,
+    status: TFStatus,
+}
+
+impl RemoteInferenceResult {
+    /// Get the status of the run, as reported by TensorFlow on the agent
+    pub fn status(&self) -> &TFStatus {
+        &self.status
+    }
+
+    /// Get the number of output tensors
+    pub fn len(&self) -> usize {
+        self.out_tensors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.out_tensors.is_empty()
+    }
+
+    fn grpc_output(&self, id: usize) -> Result<&TFTensor> {
+        self.out_tensors
+            .get(id)
+            .ok_or(Error::TensorFlow(tf::Code::OutOfRange))?
+            .as_ref()
+            .ok_or(Error::TensorFlow(tf::Code::Unavailable))
+    }
+
+    pub fn get_output<U: tf::TensorType>(&self, id: usize) -> Result<tf::Tensor<U>> {
+        tf::Tensor::from_grpc(self.grpc_output(id)?)
+    }
+
+    /// Take an output tensor, whatever its data type
+    ///
+    /// The output is removed from the result, so subsequent calls for the
+    /// same `id` fail with `Code::Unavailable`.
+    pub fn take_output(&mut self, id: usize) -> Result<tf::AnyTensor> {
+        let tensor = tf::AnyTensor::from_grpc(self.grpc_output(id)?)?;
+        self.out_tensors[id] = None;
+        Ok(tensor)
+    }
+
+    /// Get a copy of an output tensor, with its data decompressed
+    pub fn get_grpc_output(&self, id: usize) -> Result<TFTensor> {
+        let mut tensor = self.grpc_output(id)?.clone();
+        tf::compression::decompress(&mut tensor)?;
+        Ok(tensor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::agent::Dispatcher;
+
+    use std::convert::TryFrom;
+    use std::path::Path;
+
+    fn lstm2_request() -> CreateResourceRequest {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/files/tf/lstm2");
+        let model = tf::SavedModel::new()
+            .from_export_dir_in_memory(&path)
+            .unwrap();
+
+        CreateResourceRequest::try_from(&model).unwrap()
+    }
+
+    #[test]
+    fn register_remote_resources() {
+        let mut sess = RemoteSession::new(Dispatcher::new(), 0).unwrap();
+        let mut model = sess.create_resource(&lstm2_request()).unwrap();
+        assert!(model.initialized());
+        assert!(model.to_vaccel_ptr().is_none());
+        assert!(matches!(
+            model.destroy(),
+            Err(Error::Runtime(ffi::VACCEL_ENOTSUP))
+        ));
+
+        sess.register(&mut model).unwrap();
+        sess.tf_model_load(model.id()).unwrap();
+        sess.tf_model_unload(model.id()).unwrap();
+        sess.unregister(&mut model).unwrap();
+
+        let mut uninitialized = RemoteResource::from_id(VaccelId::from(0i64));
+        assert!(matches!(
+            sess.register(&mut uninitialized),
+            Err(Error::Uninitialized)
+        ));
+        let mut missing = RemoteResource::from_id(VaccelId::from(i64::from(model.id()) + 100));
+        assert!(matches!(
+            sess.register(&mut missing),
+            Err(Error::Runtime(ffi::VACCEL_ENOENT))
+        ));
+
+        sess.destroy_resource(model).unwrap();
+        assert!(sess.register(&mut model).is_err());
+        sess.close().unwrap();
+    }
+
+    #[test]
+    fn remote_image_operations() {
+        let mut sess = RemoteSession::new(Dispatcher::new(), 0).unwrap();
+        let mut img = vec![7; 64];
+
+        let (_, out_img) = sess.image_classification(&img).unwrap();
+        assert!(out_img.is_empty());
+        assert_eq!(sess.image_detection(&mut img).unwrap().len(), 64);
+        assert_eq!(sess.image_segmentation(&mut img).unwrap().len(), 64);
+
+        sess.close().unwrap();
+        assert!(matches!(
+            sess.image_detection(&mut img),
+            Err(Error::Runtime(ffi::VACCEL_ESESS))
+        ));
+    }
+
+    /// An agent that does not know about compression
+    struct Unaware;
+
+    impl Transport for Unaware {
+        fn call(&mut self, method: &str, _request: &[u8]) -> Result<Vec<u8>> {
+            assert_eq!(method, method::CREATE_SESSION);
+            let mut resp = CreateSessionResponse::new();
+            resp.set_session_id(1);
+            Ok(resp.write_to_bytes()?)
+        }
+    }
+
+    #[test]
+    fn negotiate_compression() {
+        let sess = RemoteSession::new(Dispatcher::new(), 0).unwrap();
+        assert_eq!(sess.compression(), None);
+
+        let sess = RemoteSession::new(Unaware, tf::compression::SESSION_FLAG_ZSTD).unwrap();
+        assert_eq!(sess.compression(), None);
+
+        let mut sess =
+            RemoteSession::new(Dispatcher::new(), tf::compression::SESSION_FLAG_LZ4).unwrap();
+        assert_eq!(sess.compression().map(|c| c.codec()), Some(tf::Codec::Lz4));
+
+        // Compressed inputs reach the model decompressed
+        sess.set_compression_threshold(0);
+        let mut model = sess.create_resource(&lstm2_request()).unwrap();
+        sess.register(&mut model).unwrap();
+        sess.tf_model_load(model.id()).unwrap();
+
+        let in_node = tf::Node::new("serving_default_input_1", 0);
+        let out_node = tf::Node::new("StatefulPartitionedCall", 0);
+        let input = tf::Tensor::<f32>::new(&[1, 30]);
+        let mut args = InferenceArgs::new();
+        args.add_input(&in_node, &input);
+        args.request_output(&out_node);
+
+        let result = sess.tf_model_run(model.id(), args).unwrap();
+        assert_eq!(result.get_output::<f32>(0).unwrap().dims(), [1, 2]);
+        sess.close().unwrap();
+    }
+}
@@ -0,0 +1,48 @@
+use crate::Result;
+
+/// Names of the methods of the vAccel agent service
+///
+/// These follow the ttrpc naming of the `VaccelAgent` service of vaccel-grpc.
+/// The protocol has no messages for image detection and segmentation, so
+/// their methods use the ones of image classification, with the output image
+/// sent back in the `tags` of the response.
+pub mod method {
+    pub const CREATE_SESSION: &str = "/vaccel.VaccelAgent/CreateSession";
+    pub const UPDATE_SESSION: &str = "/vaccel.VaccelAgent/UpdateSession";
+    pub const DESTROY_SESSION: &str = "/vaccel.VaccelAgent/DestroySession";
+    pub const CREATE_RESOURCE: &str = "/vaccel.VaccelAgent/CreateResource";
+    pub const REGISTER_RESOURCE: &str = "/vaccel.VaccelAgent/RegisterResource";
+    pub const UNREGISTER_RESOURCE: &str = "/vaccel.VaccelAgent/UnregisterResource";
+    pub const DESTROY_RESOURCE: &str = "/vaccel.VaccelAgent/DestroyResource";
+    pub const IMAGE_CLASSIFICATION: &str = "/vaccel.VaccelAgent/ImageClassification";
+    pub const IMAGE_DETECTION: &str = "/vaccel.VaccelAgent/ImageDetection";
+    pub const IMAGE_SEGMENTATION: &str = "/vaccel.VaccelAgent/ImageSegmentation";
+    pub const TENSORFLOW_MODEL_LOAD: &str = "/vaccel.VaccelAgent/TensorflowModelLoad";
+    pub const TENSORFLOW_MODEL_UNLOAD: &str = "/vaccel.VaccelAgent/TensorflowModelUnload";
+    pub const TENSORFLOW_MODEL_RUN: &str = "/vaccel.VaccelAgent/TensorflowModelRun";
+    pub const GENOP: &str = "/vaccel.VaccelAgent/Genop";
+}
+
+/// A channel to a vAccel agent
+///
+/// A transport sends the serialized `protocols` request of a method to the
+/// agent, and returns the serialized response. Errors of the agent that are
+/// not part of the response message are returned as `Error::Runtime`.
+///
+/// `TtrpcTransport` talks to an agent over a Unix domain socket, while
+/// `Dispatcher` and `Loopback` execute requests in the same process.
+pub trait Transport {
+    /// Call a method of the agent
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The name of the method, one of the constants of `method`
+    /// * `request` - The serialized request message
+    fn call(&mut self, method: &str, request: &[u8]) -> Result<Vec<u8>>;
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn call(&mut self, method: &str, request: &[u8]) -> Result<Vec<u8>> {
+        (**self).call(method, request)
+    }
+}
@@ -0,0 +1,375 @@
+use crate::remote::transport::Transport;
+use crate::{ffi, Error, Result};
+
+use protobuf::wire_format::WireType;
+use protobuf::{CodedInputStream, CodedOutputStream};
+
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+// The wire format of ttrpc, as used by Kata-style agents. Each message has a
+// 10-byte header: the length of the payload and the id of the stream as
+// big-endian u32, then the type of the message and its flags as u8. The
+// payload of requests and responses is a `ttrpc.Request` or `ttrpc.Response`
+// protobuf message, which carries the serialized message of the method.
+
+/// The type of request messages
+pub const MESSAGE_TYPE_REQUEST: u8 = 1;
+
+/// The type of response messages
+pub const MESSAGE_TYPE_RESPONSE: u8 = 2;
+
+/// The maximum length of the payload of a message
+pub const MAX_MESSAGE_LENGTH: usize = 4 << 20;
+
+const HEADER_LENGTH: usize = 10;
+
+/// gRPC status codes, as carried by ttrpc responses
+mod code {
+    pub const OK: i32 = 0;
+    pub const INVALID_ARGUMENT: i32 = 3;
+    pub const NOT_FOUND: i32 = 5;
+    pub const ALREADY_EXISTS: i32 = 6;
+    pub const RESOURCE_EXHAUSTED: i32 = 8;
+    pub const FAILED_PRECONDITION: i32 = 9;
+    pub const UNIMPLEMENTED: i32 = 12;
+    pub const INTERNAL: i32 = 13;
+    pub const UNAVAILABLE: i32 = 14;
+}
+
+/// The header of a ttrpc message
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageHeader {
+    pub length: u32,
+    pub stream_id: u32,
+    pub message_type: u8,
+    pub flags: u8,
+}
+
+/// Write a message to a stream
+///
+/// # Arguments
+///
+/// * `stream_id` - The id of the stream. Clients use odd ids, and responses the id of their request.
+/// * `message_type` - The type of the message, e.g. `MESSAGE_TYPE_REQUEST`
+/// * `payload` - The payload of the message
+pub fn write_message<W: Write>(
+    writer: &mut W,
+    stream_id: u32,
+    message_type: u8,
+    payload: &[u8],
+) -> Result<()> {
+    if payload.len() > MAX_MESSAGE_LENGTH {
+        return Err(Error::InvalidArgument);
+    }
+
+    let mut header = [0; HEADER_LENGTH];
+    header[..4].copy_from_slice(&(payload.len() as u32).to_be_bytes());
+    header[4..8].copy_from_slice(&stream_id.to_be_bytes());
+    header[8] = message_type;
+
+    writer.write_all(&header)?;
+    writer.write_all(payload)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read a message from a stream
+///
+/// Returns `None` if the stream was closed before a new message. A stream
+/// closed in the middle of a message is an error. Messages longer than
+/// `MAX_MESSAGE_LENGTH` are rejected, as the stream cannot be trusted after
+/// them.
+pub fn read_message<R: Read>(reader: &mut R) -> Result<Option<(MessageHeader, Vec<u8>)>> {
+    let mut buf = [0; HEADER_LENGTH];
+    let mut read = 0;
+    while read < HEADER_LENGTH {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Ok(n) => read += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    let header = MessageHeader {
+        length: u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]),
+        stream_id: u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]),
+        message_type: buf[8],
+        flags: buf[9],
+    };
+    if header.length as usize > MAX_MESSAGE_LENGTH {
+        return Err(Error::InvalidArgument);
+    }
+
+    let mut payload = vec![0; header.length as usize];
+    reader.read_exact(&mut payload)?;
+
+    Ok(Some((header, payload)))
+}
+
+/// A `ttrpc.Request` message
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Request {
+    pub service: String,
+    pub method: String,
+    pub payload: Vec<u8>,
+    pub timeout_nano: i64,
+}
+
+impl Request {
+    /// Create a request for one of the `remote::method` constants
+    ///
+    /// Fails with `Error::InvalidArgument` if `path` is not of the form
+    /// `/service/method`.
+    pub fn new(path: &str, payload: &[u8]) -> Result<Self> {
+        let mut parts = path.trim_start_matches('/').splitn(2, '/');
+        match (parts.next(), parts.next()) {
+            (Some(service), Some(method)) if !service.is_empty() && !method.is_empty() => {
+                Ok(Request {
+                    service: service.to_owned(),
+                    method: method.to_owned(),
+                    payload: payload.to_owned(),
+                    timeout_nano: 0,
+                })
+            }
+            _ => Err(Error::InvalidArgument),
+        }
+    }
+
+    /// Get the full name of the method, as in the `remote::method` constants
+    pub fn path(&self) -> String {
+        format!("/{}/{}", self.service, self.method)
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        let mut os = CodedOutputStream::vec(&mut buf);
+        os.write_string(1, &self.service)?;
+        os.write_string(2, &self.method)?;
+        os.write_bytes(3, &self.payload)?;
+        if self.timeout_nano != 0 {
+            os.write_int64(4, self.timeout_nano)?;
+        }
+        os.flush()?;
+        drop(os);
+
+        Ok(buf)
+    }
+
+    /// Decode a request, skipping unknown fields such as metadata
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut req = Request::default();
+        let mut is = CodedInputStream::from_bytes(data);
+        while !is.eof()? {
+            match is.read_tag_unpack()? {
+                (1, WireType::WireTypeLengthDelimited) => req.service = is.read_string()?,
+                (2, WireType::WireTypeLengthDelimited) => req.method = is.read_string()?,
+                (3, WireType::WireTypeLengthDelimited) => req.payload = is.read_bytes()?,
+                (4, WireType::WireTypeVarint) => req.timeout_nano = is.read_int64()?,
+                (_, wire_type) => is.skip_field(wire_type)?,
+            }
+        }
+
+        Ok(req)
+    }
+}
+
+/// A `ttrpc.Response` message
+///
+/// The status is a `google.rpc.Status`, of which only the code and the
+/// message are used.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Response {
+    pub code: i32,
+    pub message: String,
+    pub payload: Vec<u8>,
+}
+
+impl Response {
+    /// Create the response of a call
+    ///
+    /// Errors are sent back with the gRPC status code closest to their vAccel
+    /// error code.
+    pub fn new(result: Result<Vec<u8>>) -> Self {
+        match result {
+            Ok(payload) => Response {
+                payload,
+                ..Default::default()
+            },
+            Err(err) => Response {
+                code: status_code(err.runtime_code()),
+                message: err.to_string(),
+                payload: Vec::new(),
+            },
+        }
+    }
+
+    /// Get the result of the call
+    ///
+    /// Errors are returned as `Error::Runtime`, with the vAccel error code
+    /// closest to their gRPC status code.
+    pub fn into_result(self) -> Result<Vec<u8>> {
+        match self.code {
+            code::OK => Ok(self.payload),
+            code => Err(Error::Runtime(runtime_code(code))),
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut status = Vec::new();
+        if self.code != code::OK {
+            let mut os = CodedOutputStream::vec(&mut status);
+            os.write_int32(1, self.code)?;
+            os.write_string(2, &self.message)?;
+            os.flush()?;
+        }
+
+        let mut buf = Vec::new();
+        let mut os = CodedOutputStream::vec(&mut buf);
+        os.write_bytes(1, &status)?;
+        os.write_bytes(2, &self.payload)?;
+        os.flush()?;
+        drop(os);
+
+        Ok(buf)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut resp = Response::default();
+        let mut is = CodedInputStream::from_bytes(data);
+        while !is.eof()? {
+            match is.read_tag_unpack()? {
+                (1, WireType::WireTypeLengthDelimited) => {
+                    let status = is.read_bytes()?;
+                    let mut is = CodedInputStream::from_bytes(&status);
+                    while !is.eof()? {
+                        match is.read_tag_unpack()? {
+                            (1, WireType::WireTypeVarint) => resp.code = is.read_int32()?,
+                            (2, WireType::WireTypeLengthDelimited) => {
+                                resp.message = is.read_string()?
+                            }
+                            (_, wire_type) => is.skip_field(wire_type)?,
+                        }
+                    }
+                }
+                (2, WireType::WireTypeLengthDelimited) => resp.payload = is.read_bytes()?,
+                (_, wire_type) => is.skip_field(wire_type)?,
+            }
+        }
+
+        Ok(resp)
+    }
+}
+
+/// Get the gRPC status code closest to a vAccel error code
+fn status_code(err: u32) -> i32 {
+    match err {
+        ffi::VACCEL_EINVAL => code::INVALID_ARGUMENT,
+        ffi::VACCEL_ENOENT => code::NOT_FOUND,
+        ffi::VACCEL_EEXISTS => code::ALREADY_EXISTS,
+        ffi::VACCEL_ENOMEM => code::RESOURCE_EXHAUSTED,
+        ffi::VACCEL_ESESS => code::FAILED_PRECONDITION,
+        ffi::VACCEL_ENOTSUP => code::UNIMPLEMENTED,
+        ffi::VACCEL_EIO => code::UNAVAILABLE,
+        _ => code::INTERNAL,
+    }
+}
+
+/// Get the vAccel error code closest to a gRPC status code
+fn runtime_code(code: i32) -> u32 {
+    match code {
+        code::INVALID_ARGUMENT => ffi::VACCEL_EINVAL,
+        code::NOT_FOUND => ffi::VACCEL_ENOENT,
+        code::ALREADY_EXISTS => ffi::VACCEL_EEXISTS,
+        code::RESOURCE_EXHAUSTED => ffi::VACCEL_ENOMEM,
+        code::FAILED_PRECONDITION => ffi::VACCEL_ESESS,
+        code::UNIMPLEMENTED => ffi::VACCEL_ENOTSUP,
+        code::UNAVAILABLE => ffi::VACCEL_EIO,
+        _ => ffi::VACCEL_EBACKEND,
+    }
+}
+
+/// A transport to a ttrpc agent over a Unix domain socket
+///
+/// Requests are sent one at a time, each call waiting for its response.
+/// Error codes other than the ones with a gRPC equivalent are returned as
+/// `VACCEL_EBACKEND`.
+#[derive(Debug)]
+pub struct TtrpcTransport {
+    stream: UnixStream,
+    next_stream_id: u32,
+}
+
+impl TtrpcTransport {
+    /// Connect to an agent listening on a Unix domain socket
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the socket
+    pub fn connect<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::from_stream(UnixStream::connect(path)?))
+    }
+
+    /// Use an already connected socket
+    pub fn from_stream(stream: UnixStream) -> Self {
+        TtrpcTransport {
+            stream,
+            next_stream_id: 1,
+        }
+    }
+}
+
+impl Transport for TtrpcTransport {
+    fn call(&mut self, method: &str, request: &[u8]) -> Result<Vec<u8>> {
+        let stream_id = self.next_stream_id;
+        self.next_stream_id = self.next_stream_id.wrapping_add(2);
+
+        let req = Request::new(method, request)?;
+        write_message(
+            &mut self.stream,
+            stream_id,
+            MESSAGE_TYPE_REQUEST,
+            &req.encode()?,
+        )?;
+
+        let (header, payload) = read_message(&mut self.stream)?
+            .ok_or_else(|| Error::IO(io::ErrorKind::UnexpectedEof.into()))?;
+        if header.stream_id != stream_id || header.message_type != MESSAGE_TYPE_RESPONSE {
+            return Err(Error::Runtime(ffi::VACCEL_EIO));
+        }
+
+        Response::decode(&payload)?.into_result()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_message_round_trip() {
+        let mut buf = Vec::new();
+        write_message(&mut buf, 3, MESSAGE_TYPE_REQUEST, b"payload").unwrap();
+
+        let mut reader = &buf[..];
+        let (header, payload) = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(header.stream_id, 3);
+        assert_eq!(header.message_type, MESSAGE_TYPE_REQUEST);
+        assert_eq!(payload, b"payload");
+
+        // Closed between messages
+        assert!(read_message(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn read_message_truncated() {
+        let mut buf = Vec::new();
+        write_message(&mut buf, 1, MESSAGE_TYPE_REQUEST, b"payload").unwrap();
+
+        assert!(read_message(&mut &buf[..HEADER_LENGTH - 1]).is_err());
+        assert!(read_message(&mut &buf[..HEADER_LENGTH + 1]).is_err());
+    }
+}
@@ -1,6 +1,9 @@
 use crate::ffi;
 use crate::Result;
 use crate::VaccelId;
+
+use protocols::resources::{CreateResourceResponse, DestroyResourceRequest};
+
 use std::any::Any;
 
 pub trait Resource {
@@ -25,3 +28,93 @@ pub trait Resource {
     /// "Cast" VaccelResource to a mutable Any type
     fn as_mut_any(&mut self) -> &mut dyn Any;
 }
+
+/// Convert a resource to the response of its creation
+impl From<&dyn Resource> for CreateResourceResponse {
+    fn from(res: &dyn Resource) -> Self {
+        CreateResourceResponse {
+            resource_id: res.id().into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Convert a resource to the request destroying it
+impl From<&dyn Resource> for DestroyResourceRequest {
+    fn from(res: &dyn Resource) -> Self {
+        DestroyResourceRequest::from(res.id())
+    }
+}
+
+/// Convert the id of a resource to the request destroying it
+impl From<VaccelId> for DestroyResourceRequest {
+    fn from(id: VaccelId) -> Self {
+        DestroyResourceRequest {
+            resource_id: id.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Get the id of the resource created by a request
+impl From<&CreateResourceResponse> for VaccelId {
+    fn from(resp: &CreateResourceResponse) -> Self {
+        VaccelId::from(resp.get_resource_id())
+    }
+}
+
+/// Get the id of the resource a request destroys
+impl From<&DestroyResourceRequest> for VaccelId {
+    fn from(req: &DestroyResourceRequest) -> Self {
+        VaccelId::from(req.get_resource_id())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Dummy(VaccelId);
+
+    impl Resource for Dummy {
+        fn id(&self) -> VaccelId {
+            self.0
+        }
+
+        fn initialized(&self) -> bool {
+            true
+        }
+
+        fn to_vaccel_ptr(&self) -> Option<*const ffi::vaccel_resource> {
+            None
+        }
+
+        fn to_mut_vaccel_ptr(&self) -> Option<*mut ffi::vaccel_resource> {
+            None
+        }
+
+        fn destroy(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_mut_any(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn resource_id_round_trip() {
+        let res = Dummy(VaccelId::from(7i64));
+
+        let resp = CreateResourceResponse::from(&res as &dyn Resource);
+        assert_eq!(VaccelId::from(&resp), res.id());
+
+        let req = DestroyResourceRequest::from(&res as &dyn Resource);
+        assert_eq!(VaccelId::from(&req), res.id());
+        assert_eq!(DestroyResourceRequest::from(res.id()), req);
+    }
+}
@@ -3,6 +3,24 @@ use crate::resource::Resource;
 use crate::VaccelId;
 use crate::{Error, Result};
 
+use protocols::session::{CreateSessionResponse, DestroySessionRequest};
+
+use std::fmt;
+use std::mem;
+
+/// A resource kept alive by the sessions it is registered with
+///
+/// Resources that are shared between sessions are handed to a session when
+/// they are registered with it, so that they are not destroyed before the
+/// session is done with them.
+pub(crate) trait HeldResource: fmt::Debug {
+    /// Get the id of the resource
+    fn id(&self) -> VaccelId;
+
+    /// Unregister the resource from a session that is being closed
+    fn release(&self, sess: &mut Session) -> Result<()>;
+}
+
 /// The vAccel session  type
 ///
 /// This is a handle for interacting with the underlying vAccel
@@ -10,6 +28,9 @@ use crate::{Error, Result};
 #[derive(Debug)]
 pub struct Session {
     inner: ffi::vaccel_session,
+
+    // Resources that live at least as long as their registration
+    held: Vec<Box<dyn HeldResource>>,
 }
 
 impl Session {
@@ -25,7 +46,10 @@ impl Session {
         let mut inner = ffi::vaccel_session::default();
 
         match unsafe { ffi::vaccel_sess_init(&mut inner, flags) as u32 } {
-            ffi::VACCEL_OK => Ok(Session { inner }),
+            ffi::VACCEL_OK => Ok(Session {
+                inner,
+                held: Vec::new(),
+            }),
             err => Err(Error::Runtime(err)),
         }
     }
@@ -37,10 +61,19 @@ impl Session {
 
     /// Destroy a vAccel session
     ///
-    /// This will close an open session and consume it.
+    /// This will close an open session and consume it. Resources held by the
+    /// session are unregistered first, and released even if that fails.
     pub fn close(&mut self) -> Result<()> {
+        let mut released = Ok(());
+        for res in mem::take(&mut self.held) {
+            let ret = res.release(self);
+            if released.is_ok() {
+                released = ret;
+            }
+        }
+
         match unsafe { ffi::vaccel_sess_free(&mut self.inner) as u32 } {
-            ffi::VACCEL_OK => Ok(()),
+            ffi::VACCEL_OK => released,
             err => Err(Error::Runtime(err)),
         }
     }
@@ -76,6 +109,38 @@ impl Session {
         }
     }
 
+    /// Check whether a vAccel resource is registered with the session
+    ///
+    /// # Arguments
+    ///
+    /// * `res` - The resource to look for
+    pub fn has_resource(&mut self, res: &dyn Resource) -> bool {
+        match res.to_mut_vaccel_ptr() {
+            Some(res_ptr) => unsafe { ffi::vaccel_sess_has_resource(&mut self.inner, res_ptr) },
+            None => false,
+        }
+    }
+
+    /// Keep a resource alive until it is released or the session is closed
+    ///
+    /// Holding the same resource more than once has no effect.
+    pub(crate) fn hold(&mut self, res: Box<dyn HeldResource>) {
+        if !self.holds(res.id()) {
+            self.held.push(res);
+        }
+    }
+
+    /// Stop holding a resource, returning it if the session held it
+    pub(crate) fn release(&mut self, id: VaccelId) -> Option<Box<dyn HeldResource>> {
+        let pos = self.held.iter().position(|res| res.id() == id)?;
+        Some(self.held.remove(pos))
+    }
+
+    /// Check whether the session holds a resource
+    pub(crate) fn holds(&self, id: VaccelId) -> bool {
+        self.held.iter().any(|res| res.id() == id)
+    }
+
     pub(crate) fn inner(&self) -> &ffi::vaccel_session {
         &self.inner
     }
@@ -84,3 +149,55 @@ impl Session {
         &mut self.inner
     }
 }
+
+/// Convert a `Session` to the response of its creation
+impl From<&Session> for CreateSessionResponse {
+    fn from(sess: &Session) -> Self {
+        CreateSessionResponse {
+            session_id: sess.inner.session_id,
+            ..Default::default()
+        }
+    }
+}
+
+/// Convert a `Session` to the request destroying it
+impl From<&Session> for DestroySessionRequest {
+    fn from(sess: &Session) -> Self {
+        DestroySessionRequest {
+            session_id: sess.inner.session_id,
+            ..Default::default()
+        }
+    }
+}
+
+/// Get the id of the session created by a request
+impl From<&CreateSessionResponse> for VaccelId {
+    fn from(resp: &CreateSessionResponse) -> Self {
+        VaccelId::from(resp.get_session_id())
+    }
+}
+
+/// Get the id of the session a request destroys
+impl From<&DestroySessionRequest> for VaccelId {
+    fn from(req: &DestroySessionRequest) -> Self {
+        VaccelId::from(req.get_session_id())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_id_round_trip() {
+        let mut sess = Session::new(0).unwrap();
+
+        let resp = CreateSessionResponse::from(&sess);
+        assert_eq!(VaccelId::from(&resp), sess.id());
+
+        let req = DestroySessionRequest::from(&sess);
+        assert_eq!(VaccelId::from(&req), sess.id());
+
+        sess.close().unwrap();
+    }
+}
@@ -0,0 +1,346 @@
+use crate::tensorflow::checkpoint;
+use crate::tensorflow::SavedModel;
+use crate::{Error, Result};
+
+use flate2::read::GzDecoder;
+
+use std::ffi::OsStr;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Files of a SavedModel found in an archive, keyed by their path in it
+#[derive(Default)]
+struct ArchiveFiles {
+    protobuf: Option<(PathBuf, Vec<u8>)>,
+    var_index: Vec<(PathBuf, Vec<u8>)>,
+    shards: Vec<(PathBuf, Vec<u8>)>,
+
+    // Files of `assets` directories, which are not read
+    assets: Vec<PathBuf>,
+}
+
+/// Strip `./` components, so that `./model/x` and `model/x` are the same path
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| *c != Component::CurDir)
+        .collect()
+}
+
+/// The export directory of a file of the `variables` directory
+fn export_dir(path: &Path) -> Option<&Path> {
+    let variables = path.parent()?;
+    if variables.file_name()? != "variables" {
+        return None;
+    }
+
+    variables.parent()
+}
+
+/// The export directory of a file of the `assets` directory, or of one of its
+/// sub-directories
+fn assets_export_dir(path: &Path) -> Option<&Path> {
+    path.ancestors()
+        .skip(1)
+        .find(|dir| dir.file_name() == Some(OsStr::new("assets")))?
+        .parent()
+}
+
+fn not_found(msg: &str) -> Error {
+    Error::IO(io::Error::new(io::ErrorKind::NotFound, msg))
+}
+
+fn invalid_data(msg: String) -> Error {
+    Error::IO(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
+
+fn read_files<R: Read>(reader: R) -> Result<ArchiveFiles> {
+    let mut files = ArchiveFiles::default();
+
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = normalize(&entry.path()?);
+        if path.components().any(|c| {
+            matches!(
+                c,
+                Component::ParentDir | Component::RootDir | Component::Prefix(_)
+            )
+        }) {
+            return Err(invalid_data(format!(
+                "archive entry {} is outside of the archive",
+                path.display()
+            )));
+        }
+
+        if assets_export_dir(&path).is_some() {
+            files.assets.push(path);
+            continue;
+        }
+
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) => name.to_owned(),
+            None => continue,
+        };
+
+        // Only read the files we need, the rest of the entries are skipped
+        // without being buffered.
+        let list = if name == "saved_model.pb" {
+            if files.protobuf.is_some() {
+                return Err(Error::InvalidArgument);
+            }
+
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            files.protobuf = Some((path, data));
+            continue;
+        } else if name == "variables.index" {
+            &mut files.var_index
+        } else if name.starts_with("variables.data-") {
+            &mut files.shards
+        } else {
+            continue;
+        };
+
+        if export_dir(&path).is_some() {
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            list.push((path, data));
+        }
+    }
+
+    Ok(files)
+}
+
+impl SavedModel {
+    /// Create the resource from a SavedModel archive
+    ///
+    /// The archive is a tarball, optionally gzip-compressed, holding an
+    /// export directory, either at its root or in a sub-directory. It is
+    /// streamed, and the files of the model are read in memory, so nothing is
+    /// extracted to the filesystem.
+    ///
+    /// The runtime loads in-memory models from their graph and variables only,
+    /// so models with files in `assets`, e.g. vocabularies, are rejected, as
+    /// are archives with entries outside of the archive root.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The archive data
+    pub fn from_archive<R: Read>(self, reader: R) -> Result<Self> {
+        let mut reader = BufReader::new(reader);
+
+        let files = if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+            read_files(GzDecoder::new(reader))?
+        } else {
+            read_files(reader)?
+        };
+
+        let (pb_path, protobuf) = files
+            .protobuf
+            .ok_or_else(|| not_found("saved_model.pb not found in archive"))?;
+        let root = pb_path.parent().unwrap_or_else(|| Path::new(""));
+
+        if let Some(asset) = files
+            .assets
+            .iter()
+            .find(|path| assets_export_dir(path) == Some(root))
+        {
+            return Err(invalid_data(format!(
+                "SavedModel assets are not supported, found {}",
+                asset.display()
+            )));
+        }
+
+        let var_index = files
+            .var_index
+            .into_iter()
+            .find(|(path, _)| export_dir(path) == Some(root))
+            .map(|(_, data)| data)
+            .ok_or_else(|| not_found("variables/variables.index not found in archive"))?;
+
+        let shards = files
+            .shards
+            .into_iter()
+            .filter(|(path, _)| export_dir(path) == Some(root))
+            .map(|(path, data)| {
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                (name, data)
+            })
+            .collect();
+        let shards = checkpoint::order_shards(shards)?;
+
+        self.from_owned_data(protobuf, var_index, shards)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use std::fs;
+
+    struct Model {
+        protobuf: Vec<u8>,
+        var_index: Vec<u8>,
+        shards: Vec<Vec<u8>>,
+    }
+
+    /// The graph of the example model, with the variables of a checkpoint
+    /// split in 2 shards
+    fn model() -> Model {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let variables = root.join("src/tensorflow/testdata/sharded");
+
+        Model {
+            protobuf: fs::read(root.join("examples/files/tf/lstm2/saved_model.pb")).unwrap(),
+            var_index: fs::read(variables.join("variables.index")).unwrap(),
+            shards: checkpoint::shard_files(&variables)
+                .unwrap()
+                .iter()
+                .map(|p| fs::read(p).unwrap())
+                .collect(),
+        }
+    }
+
+    impl Model {
+        fn files(&self, dir: &str) -> Vec<(String, &[u8])> {
+            vec![
+                (format!("{}saved_model.pb", dir), &self.protobuf),
+                (format!("{}variables/variables.index", dir), &self.var_index),
+                (
+                    format!("{}variables/variables.data-00000-of-00002", dir),
+                    &self.shards[0],
+                ),
+                (
+                    format!("{}variables/variables.data-00001-of-00002", dir),
+                    &self.shards[1],
+                ),
+            ]
+        }
+    }
+
+    /// Build a tarball, without checking the paths of the entries
+    fn tarball(files: &[(String, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in files.iter() {
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, *data).unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        std::io::Write::write_all(&mut encoder, data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn io_error_kind(result: Result<SavedModel>) -> io::ErrorKind {
+        match result {
+            Err(Error::IO(err)) => err.kind(),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("unexpected success"),
+        }
+    }
+
+    fn check_loaded(loaded: &SavedModel, model: &Model) {
+        let shards: Vec<&[u8]> = model.shards.iter().map(|s| &s[..]).collect();
+        let (var_index, checkpoint) = checkpoint::merge_shards(&model.var_index, &shards).unwrap();
+
+        assert_eq!(loaded.get_protobuf(), Some(&model.protobuf[..]));
+        assert_eq!(loaded.get_var_index(), Some(&var_index[..]));
+        assert_eq!(loaded.get_checkpoint(), Some(&checkpoint[..]));
+    }
+
+    #[test]
+    fn load_tarball() {
+        let model = model();
+        let data = tarball(&model.files("./"));
+
+        let loaded = SavedModel::new().from_archive(&data[..]).unwrap();
+        check_loaded(&loaded, &model);
+    }
+
+    #[test]
+    fn load_compressed_tarball() {
+        let model = model();
+
+        // Variables of other directories and other files of the model are
+        // ignored
+        let mut files = model.files("other/").split_off(1);
+        files.extend(model.files("lstm/"));
+        files.push(("lstm/keras_metadata.pb".to_owned(), b"metadata"));
+        files.push(("lstm/assets.extra/requests".to_owned(), b"requests"));
+        files.push(("README".to_owned(), b"readme"));
+
+        let loaded = SavedModel::new()
+            .from_archive(&gzip(&tarball(&files))[..])
+            .unwrap();
+        check_loaded(&loaded, &model);
+    }
+
+    #[test]
+    fn reject_incomplete_models() {
+        let model = model();
+
+        let mut files = model.files("lstm/");
+        files.remove(3);
+        let result = SavedModel::new().from_archive(&gzip(&tarball(&files))[..]);
+        assert!(matches!(result, Err(Error::Checkpoint(msg)) if msg == "found 1 out of 2 shards"));
+
+        let files = &model.files("lstm/")[1..];
+        let result = SavedModel::new().from_archive(&tarball(files)[..]);
+        assert_eq!(io_error_kind(result), io::ErrorKind::NotFound);
+
+        let mut files = model.files("lstm/");
+        files.remove(1);
+        let result = SavedModel::new().from_archive(&tarball(&files)[..]);
+        assert_eq!(io_error_kind(result), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn reject_path_traversal() {
+        let model = model();
+
+        let mut files = model.files("lstm/");
+        files[1].0 = "lstm/../../variables/variables.index".to_owned();
+        let result = SavedModel::new().from_archive(&tarball(&files)[..]);
+        assert_eq!(io_error_kind(result), io::ErrorKind::InvalidData);
+
+        let files = model.files("/lstm/");
+        let result = SavedModel::new().from_archive(&tarball(&files)[..]);
+        assert_eq!(io_error_kind(result), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reject_assets() {
+        let model = model();
+
+        let mut files = model.files("lstm/");
+        files.push(("lstm/assets/vocab/words.txt".to_owned(), b"words"));
+        let result = SavedModel::new().from_archive(&gzip(&tarball(&files))[..]);
+        assert_eq!(io_error_kind(result), io::ErrorKind::InvalidData);
+
+        // Assets of another model do not matter
+        let mut files = model.files("lstm/");
+        files.push(("other/assets/words.txt".to_owned(), b"words"));
+        let loaded = SavedModel::new()
+            .from_archive(&tarball(&files)[..])
+            .unwrap();
+        check_loaded(&loaded, &model);
+    }
+}
@@ -6,6 +6,9 @@ use std::ops::{Deref, DerefMut};
 pub struct Buffer {
     inner: *mut ffi::vaccel_tf_buffer,
     vaccel_owned: bool,
+
+    // Data owned by the buffer, if it was created from a vector
+    storage: Option<Vec<u8>>,
 }
 
 impl Buffer {
@@ -17,9 +20,19 @@ impl Buffer {
         Buffer {
             inner,
             vaccel_owned: false,
+            storage: None,
         }
     }
 
+    /// Create a buffer that owns its data
+    pub fn from_vec(data: Vec<u8>) -> Self {
+        let mut buffer = Buffer::new(&data);
+
+        // Moving the vector does not move its data
+        buffer.storage = Some(data);
+        buffer
+    }
+
     pub unsafe fn from_vaccel_buffer(buffer: *mut ffi::vaccel_tf_buffer) -> Result<Self> {
         let mut size = Default::default();
         let data = ffi::vaccel_tf_buffer_get_data(buffer, &mut size);
@@ -30,6 +43,7 @@ impl Buffer {
         Ok(Buffer {
             inner: buffer,
             vaccel_owned: true,
+            storage: None,
         })
     }
 
@@ -0,0 +1,383 @@
+use crate::ffi;
+use crate::session::{HeldResource, Session};
+use crate::tensorflow::SavedModel;
+use crate::{Error, Result, VaccelId};
+
+use sha2::{Digest, Sha256};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError, Weak};
+
+/// SHA-256 digest identifying the contents of a model
+pub type ModelDigest = [u8; 32];
+
+struct CachedModel {
+    model: Mutex<SavedModel>,
+    digest: ModelDigest,
+
+    // The id of the model does not change once it is loaded, so it is kept
+    // here to be read without locking the model
+    id: VaccelId,
+}
+
+// The model, including its runtime resource, is only accessed through the
+// mutex: `SharedModel` does not hand out the resource itself, and registers it
+// with sessions while holding the lock. Neither is tied to the thread that
+// created them.
+unsafe impl Send for CachedModel {}
+unsafe impl Sync for CachedModel {}
+
+impl CachedModel {
+    fn new(model: SavedModel, digest: ModelDigest) -> Self {
+        CachedModel {
+            id: model.id(),
+            model: Mutex::new(model),
+            digest,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SavedModel> {
+        self.model.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Drop for CachedModel {
+    fn drop(&mut self) {
+        // Sessions hold a handle to the models registered with them, so the
+        // model is not registered anywhere by now
+        let model = self.model.get_mut().unwrap_or_else(|err| err.into_inner());
+        let _ = model.destroy();
+    }
+}
+
+/// A reference-counted handle to a model of a `ModelCache`
+///
+/// Cloning the handle does not create a new resource. The model is destroyed
+/// when the last handle to it is dropped. Sessions the model is registered
+/// with through `register` hold a handle until it is unregistered or they are
+/// closed. Handles can be sent to other threads.
+#[derive(Clone)]
+pub struct SharedModel {
+    inner: Arc<CachedModel>,
+}
+
+impl SharedModel {
+    /// Get the id of the model
+    pub fn id(&self) -> VaccelId {
+        self.inner.id
+    }
+
+    /// Get the content digest of the model
+    pub fn digest(&self) -> &ModelDigest {
+        &self.inner.digest
+    }
+
+    /// Number of live handles to the model, including the ones held by
+    /// sessions
+    pub fn holders(&self) -> usize {
+        Arc::strong_count(&self.inner)
+    }
+
+    /// Get the model, e.g. to load it in a session or run inference with it
+    ///
+    /// The model must not be destroyed, registered or unregistered through the
+    /// returned guard, as the handle does that. Fails with `VACCEL_EBUSY` if
+    /// the model is already in use through another handle.
+    pub fn model(&self) -> Result<MutexGuard<'_, SavedModel>> {
+        match self.inner.model.try_lock() {
+            Ok(model) => Ok(model),
+            Err(TryLockError::Poisoned(err)) => Ok(err.into_inner()),
+            Err(TryLockError::WouldBlock) => Err(Error::Runtime(ffi::VACCEL_EBUSY)),
+        }
+    }
+
+    /// Register the model with a session, unless it is already registered
+    ///
+    /// The session keeps the model alive until it is unregistered or the
+    /// session is closed. Waits for the model if it is in use through another
+    /// handle, so it must not be called while holding the guard of `model`.
+    ///
+    /// # Arguments
+    ///
+    /// * `sess` - The session to register the model with
+    pub fn register(&self, sess: &mut Session) -> Result<()> {
+        {
+            let mut model = self.inner.lock();
+            if !sess.has_resource(&*model) {
+                sess.register(&mut *model)?;
+            }
+        }
+
+        sess.hold(Box::new(self.clone()));
+        Ok(())
+    }
+
+    /// Unregister the model from a session
+    ///
+    /// The model is destroyed if the session held its last handle. Waits for
+    /// the model if it is in use through another handle, like `register`.
+    ///
+    /// # Arguments
+    ///
+    /// * `sess` - The session to unregister the model from
+    pub fn unregister(&self, sess: &mut Session) -> Result<()> {
+        self.release(sess)?;
+        sess.release(self.id());
+
+        Ok(())
+    }
+}
+
+impl HeldResource for SharedModel {
+    fn id(&self) -> VaccelId {
+        self.id()
+    }
+
+    fn release(&self, sess: &mut Session) -> Result<()> {
+        let mut model = self.inner.lock();
+        if sess.has_resource(&*model) {
+            sess.unregister(&mut *model)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for SharedModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedModel")
+            .field("id", &self.inner.id)
+            .field("digest", &self.inner.digest)
+            .finish()
+    }
+}
+
+/// Add a length-prefixed blob to a digest, so that the boundaries between the
+/// files of a model are part of the hash
+fn hash_blob(hasher: &mut Sha256, data: &[u8]) {
+    hasher.update((data.len() as u64).to_le_bytes());
+    hasher.update(data);
+}
+
+fn hash_file(hasher: &mut Sha256, path: &Path) -> Result<()> {
+    let mut file = File::open(path)?;
+    hasher.update(file.metadata()?.len().to_le_bytes());
+    io::copy(&mut file, hasher)?;
+
+    Ok(())
+}
+
+/// A cache of SavedModel resources, keyed by the contents of the models
+///
+/// Loading the same model several times, e.g. once per session, returns
+/// handles to a single resource, so the model is only registered with the
+/// runtime once. The cache does not keep models alive on its own: a model is
+/// destroyed as soon as all of its handles are dropped, and loaded again the
+/// next time it is requested.
+///
+/// The digest of a model does not depend on how it was loaded, so a model
+/// loaded from memory and the same model loaded from disk share a resource.
+#[derive(Default)]
+pub struct ModelCache {
+    models: HashMap<ModelDigest, Weak<CachedModel>>,
+}
+
+impl ModelCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of models of the cache which are still alive
+    pub fn len(&self) -> usize {
+        self.models
+            .values()
+            .filter(|m| m.strong_count() > 0)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get_or_insert<F>(&mut self, digest: ModelDigest, load: F) -> Result<SharedModel>
+    where
+        F: FnOnce() -> Result<SavedModel>,
+    {
+        // Forget about models that have been dropped in the meantime
+        self.models.retain(|_, m| m.strong_count() > 0);
+
+        if let Some(inner) = self.models.get(&digest).and_then(Weak::upgrade) {
+            return Ok(SharedModel { inner });
+        }
+
+        let inner = Arc::new(CachedModel::new(load()?, digest));
+        self.models.insert(digest, Arc::downgrade(&inner));
+
+        Ok(SharedModel { inner })
+    }
+
+    /// Get a model from an export directory
+    ///
+    /// The digest of the model is computed from `saved_model.pb`, the
+    /// variables index and the variables data shards of the directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path in the filesystem to the export directory
+    pub fn load_export_dir(&mut self, path: &Path) -> Result<SharedModel> {
+        let mut hasher = Sha256::new();
+        hash_file(&mut hasher, &path.join("saved_model.pb"))?;
+        hash_file(&mut hasher, &path.join("variables").join("variables.index"))?;
+        for shard in SavedModel::variable_shards(path)? {
+            hash_file(&mut hasher, &shard)?;
+        }
+
+        self.get_or_insert(hasher.finalize().into(), || {
+            SavedModel::new().from_export_dir(path)
+        })
+    }
+
+    /// Get a model from in-memory data
+    ///
+    /// The digest of the model is computed from all of its data. On a cache
+    /// miss the data is copied, since the model may outlive it.
+    ///
+    /// # Arguments
+    ///
+    /// * `protobuf` - The contents of `saved_model.pb`
+    /// * `shards` - The contents of the `variables.data-*-of-*` files, in shard order
+    /// * `variable_index` - The contents of `variables.index`
+    pub fn load_in_memory(
+        &mut self,
+        protobuf: &[u8],
+        shards: &[&[u8]],
+        variable_index: &[u8],
+    ) -> Result<SharedModel> {
+        let mut hasher = Sha256::new();
+        hash_blob(&mut hasher, protobuf);
+        hash_blob(&mut hasher, variable_index);
+        for shard in shards.iter() {
+            hash_blob(&mut hasher, shard);
+        }
+
+        self.get_or_insert(hasher.finalize().into(), || {
+            let protobuf = protobuf.to_vec();
+            let variable_index = variable_index.to_vec();
+            let shards = shards.iter().map(|s| s.to_vec()).collect();
+
+            SavedModel::new().from_owned_data(protobuf, variable_index, shards)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn lstm2() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/files/tf/lstm2")
+    }
+
+    fn digest_of(blobs: &[&[u8]]) -> ModelDigest {
+        let mut hasher = Sha256::new();
+        for blob in blobs {
+            hash_blob(&mut hasher, blob);
+        }
+
+        hasher.finalize().into()
+    }
+
+    #[test]
+    fn digests_include_boundaries() {
+        assert_eq!(digest_of(&[b"ab", b"c"]), digest_of(&[b"ab", b"c"]));
+        assert_ne!(digest_of(&[b"ab", b"c"]), digest_of(&[b"a", b"bc"]));
+        assert_ne!(digest_of(&[b"abc"]), digest_of(&[b"abc", b""]));
+
+        let path = lstm2().join("saved_model.pb");
+        let mut hasher = Sha256::new();
+        hash_file(&mut hasher, &path).unwrap();
+        let digest: ModelDigest = hasher.finalize().into();
+        assert_eq!(digest, digest_of(&[&fs::read(&path).unwrap()]));
+    }
+
+    #[test]
+    fn models_are_shared() {
+        let path = lstm2();
+        let protobuf = fs::read(path.join("saved_model.pb")).unwrap();
+        let index = fs::read(path.join("variables/variables.index")).unwrap();
+        let shard = fs::read(path.join("variables/variables.data-00000-of-00001")).unwrap();
+
+        let mut cache = ModelCache::new();
+        let first = cache.load_export_dir(&path).unwrap();
+        let second = cache.load_in_memory(&protobuf, &[&shard], &index).unwrap();
+        assert_eq!(first.id(), second.id());
+        assert_eq!(first.digest(), second.digest());
+        assert_eq!(first.holders(), 2);
+        assert_eq!(cache.len(), 1);
+
+        let other = cache.load_in_memory(&protobuf, &[&shard], &[]).unwrap();
+        assert_ne!(other.id(), first.id());
+        assert_eq!(cache.len(), 2);
+
+        drop(other);
+        drop(first);
+        assert_eq!(cache.len(), 1);
+        drop(second);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn register_is_idempotent() {
+        let mut cache = ModelCache::new();
+        let model = cache.load_export_dir(&lstm2()).unwrap();
+        let mut sess = Session::new(0).unwrap();
+
+        model.register(&mut sess).unwrap();
+        model.register(&mut sess).unwrap();
+        assert!(sess.has_resource(&*model.model().unwrap()));
+        assert!(sess.holds(model.id()));
+        assert_eq!(model.holders(), 2);
+
+        model.unregister(&mut sess).unwrap();
+        assert!(!sess.has_resource(&*model.model().unwrap()));
+        assert!(!sess.holds(model.id()));
+        assert_eq!(model.holders(), 1);
+
+        // Unregistering a model which is not registered does nothing
+        model.unregister(&mut sess).unwrap();
+        sess.close().unwrap();
+    }
+
+    #[test]
+    fn sessions_keep_models_alive() {
+        let mut cache = ModelCache::new();
+        let model = cache.load_export_dir(&lstm2()).unwrap();
+        let mut first = Session::new(0).unwrap();
+        let mut second = Session::new(0).unwrap();
+        model.register(&mut first).unwrap();
+        model.register(&mut second).unwrap();
+
+        drop(model);
+        assert_eq!(cache.len(), 1);
+
+        let model = cache.load_export_dir(&lstm2()).unwrap();
+        model.unregister(&mut first).unwrap();
+        drop(model);
+        assert_eq!(cache.len(), 1);
+
+        // Closing the last session holding the model unregisters and destroys it
+        let weak = Arc::downgrade(&cache.load_export_dir(&lstm2()).unwrap().inner);
+        second.close().unwrap();
+        assert!(weak.upgrade().is_none());
+        assert!(cache.is_empty());
+
+        first.close().unwrap();
+    }
+}
@@ -0,0 +1,775 @@
+// Support for TensorFlow checkpoints split in multiple data shards.
+//
+// The runtime only accepts a single checkpoint blob, so sharded checkpoints
+// are merged before being handed over: the shards are concatenated and the
+// variables index, which records the shard and offset of every tensor, is
+// rewritten to point into the concatenated data.
+//
+// The index is a LevelDB-style table (see `tensorflow/core/lib/io/table.h`)
+// whose values are `BundleHeaderProto` and `BundleEntryProto` messages.
+
+use crate::{Error, Result};
+
+use protobuf::wire_format::WireType;
+use protobuf::{CodedInputStream, CodedOutputStream};
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const TABLE_MAGIC: u64 = 0xdb47_7524_8b80_fb57;
+const FOOTER_LEN: usize = 48;
+const BLOCK_TRAILER_LEN: usize = 5;
+const BLOCK_SIZE: usize = 256 * 1024;
+
+// Entries between restart points in data blocks. Index blocks have a restart
+// point at every entry.
+const BLOCK_RESTART_INTERVAL: usize = 16;
+
+fn checkpoint_error(msg: &str) -> Error {
+    Error::Checkpoint(msg.to_owned())
+}
+
+/// CRC32-C (Castagnoli), as used by the table block trailers
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82f6_3b78
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+fn masked_crc32c(data: &[u8]) -> u32 {
+    let crc = crc32c(data);
+    crc.rotate_right(15).wrapping_add(0xa282_ead8)
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data
+            .get(*pos)
+            .ok_or_else(|| checkpoint_error("truncated varint"))?;
+        *pos += 1;
+
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err(checkpoint_error("malformed varint"))
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_fixed32(data: &[u8], pos: usize) -> Result<u32> {
+    let bytes = data
+        .get(pos..pos + 4)
+        .ok_or_else(|| checkpoint_error("truncated table"))?;
+
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Read the contents of the block pointed to by an encoded block handle
+fn read_block<'a>(table: &'a [u8], handle: &[u8]) -> Result<&'a [u8]> {
+    let mut pos = 0;
+    let offset = read_varint(handle, &mut pos)? as usize;
+    let size = read_varint(handle, &mut pos)? as usize;
+
+    let block = offset
+        .checked_add(size)
+        .and_then(|end| end.checked_add(BLOCK_TRAILER_LEN))
+        .and_then(|end| table.get(offset..end))
+        .ok_or_else(|| checkpoint_error("block out of bounds"))?;
+
+    if block[size] != 0 {
+        return Err(checkpoint_error("compressed tables are not supported"));
+    }
+
+    if masked_crc32c(&block[..size + 1]) != read_fixed32(block, size + 1)? {
+        return Err(checkpoint_error("block checksum mismatch"));
+    }
+
+    Ok(&block[..size])
+}
+
+/// Decode the key-value entries of a block
+fn block_entries(block: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    if block.len() < 4 {
+        return Err(checkpoint_error("truncated block"));
+    }
+
+    let num_restarts = read_fixed32(block, block.len() - 4)? as usize;
+    let end = block
+        .len()
+        .checked_sub(4)
+        .and_then(|len| len.checked_sub(num_restarts.checked_mul(4)?))
+        .ok_or_else(|| checkpoint_error("truncated block"))?;
+
+    let mut entries = Vec::new();
+    let mut key: Vec<u8> = Vec::new();
+    let mut pos = 0;
+    while pos < end {
+        let shared = read_varint(block, &mut pos)? as usize;
+        let non_shared = read_varint(block, &mut pos)? as usize;
+        let value_len = read_varint(block, &mut pos)? as usize;
+
+        let entry_end = pos
+            .checked_add(non_shared)
+            .and_then(|p| p.checked_add(value_len));
+        match entry_end {
+            Some(entry_end) if shared <= key.len() && entry_end <= end => (),
+            _ => return Err(checkpoint_error("malformed block entry")),
+        }
+
+        key.truncate(shared);
+        key.extend_from_slice(&block[pos..pos + non_shared]);
+        pos += non_shared;
+
+        entries.push((key.clone(), block[pos..pos + value_len].to_vec()));
+        pos += value_len;
+    }
+
+    Ok(entries)
+}
+
+/// Read all the key-value entries of a table, in key order
+fn read_table(table: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    if table.len() < FOOTER_LEN {
+        return Err(checkpoint_error("truncated table"));
+    }
+
+    let footer = &table[table.len() - FOOTER_LEN..];
+    let magic = read_fixed32(footer, 40)? as u64 | (read_fixed32(footer, 44)? as u64) << 32;
+    if magic != TABLE_MAGIC {
+        return Err(checkpoint_error("bad table magic number"));
+    }
+
+    // Skip the metaindex handle, we only need the index one
+    let mut pos = 0;
+    read_varint(footer, &mut pos)?;
+    read_varint(footer, &mut pos)?;
+
+    let mut entries = Vec::new();
+    for (_, handle) in block_entries(read_block(table, &footer[pos..])?)? {
+        entries.extend(block_entries(read_block(table, &handle)?)?);
+    }
+
+    Ok(entries)
+}
+
+/// A block being built, as `BlockBuilder` in `tensorflow/core/lib/io/block_builder.cc`
+///
+/// Keys are stored as the suffix they do not share with the previous key,
+/// except at restart points, which are every `interval` entries.
+struct BlockBuilder {
+    buf: Vec<u8>,
+    restarts: Vec<u32>,
+    interval: usize,
+    counter: usize,
+    last_key: Vec<u8>,
+}
+
+impl BlockBuilder {
+    fn new(interval: usize) -> Self {
+        BlockBuilder {
+            buf: Vec::new(),
+            restarts: vec![0],
+            interval,
+            counter: 0,
+            last_key: Vec::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Add an entry, whose key must be after the keys already added
+    fn add(&mut self, key: &[u8], value: &[u8]) {
+        let mut shared = 0;
+        if self.counter < self.interval {
+            shared = self
+                .last_key
+                .iter()
+                .zip(key)
+                .take_while(|(a, b)| a == b)
+                .count();
+        } else {
+            self.restarts.push(self.buf.len() as u32);
+            self.counter = 0;
+        }
+
+        write_varint(&mut self.buf, shared as u64);
+        write_varint(&mut self.buf, (key.len() - shared) as u64);
+        write_varint(&mut self.buf, value.len() as u64);
+        self.buf.extend_from_slice(&key[shared..]);
+        self.buf.extend_from_slice(value);
+
+        self.last_key = key.to_vec();
+        self.counter += 1;
+    }
+
+    /// The size of the block if it was finished now
+    fn size_estimate(&self) -> usize {
+        self.buf.len() + 4 * self.restarts.len() + 4
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        for restart in self.restarts.iter() {
+            self.buf.extend_from_slice(&restart.to_le_bytes());
+        }
+        self.buf
+            .extend_from_slice(&(self.restarts.len() as u32).to_le_bytes());
+
+        self.buf
+    }
+}
+
+/// The shortest key in `[start, limit)`, as the bytewise comparator of LevelDB
+fn shortest_separator(start: &[u8], limit: &[u8]) -> Vec<u8> {
+    let diff = start.iter().zip(limit).take_while(|(a, b)| a == b).count();
+    if diff < start.len().min(limit.len()) {
+        let byte = start[diff];
+        if byte < 0xff && byte + 1 < limit[diff] {
+            let mut key = start[..=diff].to_vec();
+            key[diff] += 1;
+            return key;
+        }
+    }
+
+    start.to_vec()
+}
+
+/// A short key that is not before `key`, as the bytewise comparator of LevelDB
+fn short_successor(key: &[u8]) -> Vec<u8> {
+    match key.iter().position(|b| *b != 0xff) {
+        Some(i) => {
+            let mut key = key[..=i].to_vec();
+            key[i] += 1;
+            key
+        }
+        None => key.to_vec(),
+    }
+}
+
+/// Append a block and its trailer to `table`, returning the encoded handle
+fn append_block(table: &mut Vec<u8>, block: &[u8]) -> Vec<u8> {
+    let mut handle = Vec::new();
+    write_varint(&mut handle, table.len() as u64);
+    write_varint(&mut handle, block.len() as u64);
+
+    let start = table.len();
+    table.extend_from_slice(block);
+    table.push(0);
+    let crc = masked_crc32c(&table[start..]);
+    table.extend_from_slice(&crc.to_le_bytes());
+
+    handle
+}
+
+/// Write a table holding `entries`, which must be sorted by key
+///
+/// This follows `TableBuilder` in `tensorflow/core/lib/io/table_builder.cc`,
+/// with the options TensorFlow writes checkpoints with, so reading a table
+/// and writing its entries back gives the same bytes.
+fn write_table(entries: &[(Vec<u8>, Vec<u8>)]) -> Vec<u8> {
+    let mut table = Vec::new();
+    let mut index = BlockBuilder::new(1);
+    let mut block = BlockBuilder::new(BLOCK_RESTART_INTERVAL);
+
+    // The handle of the last block written, whose index entry is added once
+    // the first key of the next block is known
+    let mut pending: Option<Vec<u8>> = None;
+    let mut last_key: &[u8] = &[];
+
+    for (key, value) in entries.iter() {
+        if let Some(handle) = pending.take() {
+            index.add(&shortest_separator(last_key, key), &handle);
+        }
+
+        block.add(key, value);
+        last_key = key;
+
+        if block.size_estimate() >= BLOCK_SIZE {
+            let full = std::mem::replace(&mut block, BlockBuilder::new(BLOCK_RESTART_INTERVAL));
+            pending = Some(append_block(&mut table, &full.finish()));
+        }
+    }
+
+    if !block.is_empty() {
+        pending = Some(append_block(&mut table, &block.finish()));
+    }
+
+    let metaindex_handle = append_block(
+        &mut table,
+        &BlockBuilder::new(BLOCK_RESTART_INTERVAL).finish(),
+    );
+
+    if let Some(handle) = pending {
+        index.add(&short_successor(last_key), &handle);
+    }
+    let index_handle = append_block(&mut table, &index.finish());
+
+    let mut footer = Vec::with_capacity(FOOTER_LEN);
+    footer.extend_from_slice(&metaindex_handle);
+    footer.extend_from_slice(&index_handle);
+    footer.resize(FOOTER_LEN - 8, 0);
+    footer.extend_from_slice(&(TABLE_MAGIC as u32).to_le_bytes());
+    footer.extend_from_slice(&((TABLE_MAGIC >> 32) as u32).to_le_bytes());
+    table.extend_from_slice(&footer);
+
+    table
+}
+
+/// Get the value of a varint field of a message
+fn varint_field(msg: &[u8], number: u32) -> Result<Option<u64>> {
+    let mut is = CodedInputStream::from_bytes(msg);
+    let mut value = None;
+
+    while !is.eof()? {
+        let (field, wire_type) = is.read_tag_unpack()?;
+        if field == number && wire_type == WireType::WireTypeVarint {
+            value = Some(is.read_raw_varint64()?);
+        } else {
+            is.skip_field(wire_type)?;
+        }
+    }
+
+    Ok(value)
+}
+
+/// Rewrite the varint fields of a message
+///
+/// `f` is called with the number of each varint field and its value, and
+/// returns the value to write, or `None` to drop the field. All other fields
+/// are copied as is.
+fn rewrite_varint_fields<F>(msg: &[u8], mut f: F) -> Result<Vec<u8>>
+where
+    F: FnMut(u32, u64) -> Result<Option<u64>>,
+{
+    let mut out = Vec::new();
+
+    {
+        let mut is = CodedInputStream::from_bytes(msg);
+        let mut os = CodedOutputStream::vec(&mut out);
+
+        while !is.eof()? {
+            let start = is.pos() as usize;
+            let (field, wire_type) = is.read_tag_unpack()?;
+
+            if wire_type == WireType::WireTypeVarint {
+                if let Some(value) = f(field, is.read_raw_varint64()?)? {
+                    os.write_tag(field, wire_type)?;
+                    os.write_raw_varint64(value)?;
+                }
+            } else {
+                is.skip_field(wire_type)?;
+                os.write_raw_bytes(&msg[start..is.pos() as usize])?;
+            }
+        }
+
+        os.flush()?;
+    }
+
+    Ok(out)
+}
+
+/// Rewrite a variables index for the concatenation of its data shards
+///
+/// The header is changed to describe a single shard and the offset of every
+/// tensor is relocated to where its shard starts in the concatenated data.
+///
+/// # Arguments
+///
+/// * `index` - The contents of the `variables.index` file
+/// * `shard_lens` - The size of each data shard, in shard order
+pub(crate) fn merge_index(index: &[u8], shard_lens: &[u64]) -> Result<Vec<u8>> {
+    let mut offsets = Vec::with_capacity(shard_lens.len());
+    let mut total = 0u64;
+    for len in shard_lens.iter() {
+        offsets.push(total);
+        total = total
+            .checked_add(*len)
+            .ok_or_else(|| checkpoint_error("shards too large"))?;
+    }
+
+    let mut entries = read_table(index)?;
+    for (key, value) in entries.iter_mut() {
+        *value = if key.is_empty() {
+            // BundleHeaderProto: num_shards = 1
+            rewrite_varint_fields(value, |field, v| {
+                Ok(match field {
+                    1 if v as usize != shard_lens.len() => {
+                        return Err(checkpoint_error("number of shards mismatch"))
+                    }
+                    1 => Some(1),
+                    _ => Some(v),
+                })
+            })?
+        } else {
+            // BundleEntryProto: shard_id = 3, offset = 4. The shard is
+            // dropped, as it defaults to 0, and the offset is relocated.
+            let shard_id = varint_field(value, 3)?.unwrap_or(0) as usize;
+            let base = *offsets
+                .get(shard_id)
+                .ok_or_else(|| checkpoint_error("shard id out of range"))?;
+
+            let mut entry = rewrite_varint_fields(value, |field, v| {
+                Ok(match field {
+                    3 => None,
+                    4 => Some(
+                        v.checked_add(base)
+                            .ok_or_else(|| checkpoint_error("offset out of range"))?,
+                    ),
+                    _ => Some(v),
+                })
+            })?;
+
+            // An offset of 0 is not serialized, so entries at the start of a
+            // shard have none to relocate
+            if base != 0 && varint_field(value, 4)?.is_none() {
+                let mut os = CodedOutputStream::vec(&mut entry);
+                os.write_uint64(4, base)?;
+                os.flush()?;
+            }
+
+            entry
+        };
+    }
+
+    Ok(write_table(&entries))
+}
+
+/// Merge the data shards of a checkpoint in a single one
+///
+/// Returns the new variables index and the merged data, which is a copy of
+/// all the shards.
+///
+/// # Arguments
+///
+/// * `index` - The contents of the `variables.index` file
+/// * `shards` - The contents of the `variables.data-*-of-*` files, in shard order
+pub(crate) fn merge_shards(index: &[u8], shards: &[&[u8]]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let lens: Vec<u64> = shards.iter().map(|s| s.len() as u64).collect();
+    let index = merge_index(index, &lens)?;
+
+    let mut data = Vec::with_capacity(shards.iter().map(|s| s.len()).sum());
+    for shard in shards.iter() {
+        data.extend_from_slice(shard);
+    }
+
+    Ok((index, data))
+}
+
+/// Get a checkpoint with a single data shard, merging the shards if needed
+///
+/// Returns the variables index and the data of the checkpoint. A single
+/// shard is used as is, without copying it. Otherwise the shards are appended
+/// to the first one, and each is released once appended.
+///
+/// # Arguments
+///
+/// * `index` - The contents of the `variables.index` file
+/// * `shards` - The contents of the `variables.data-*-of-*` files, in shard order
+pub(crate) fn single_shard(index: Vec<u8>, shards: Vec<Vec<u8>>) -> Result<(Vec<u8>, Vec<u8>)> {
+    if shards.len() == 1 {
+        return Ok((index, shards.into_iter().next().unwrap_or_default()));
+    }
+
+    let lens: Vec<u64> = shards.iter().map(|s| s.len() as u64).collect();
+    let index = merge_index(&index, &lens)?;
+
+    let mut shards = shards.into_iter();
+    let mut data = shards.next().ok_or(Error::InvalidArgument)?;
+    data.reserve(lens[1..].iter().sum::<u64>() as usize);
+    for shard in shards {
+        data.extend_from_slice(&shard);
+    }
+
+    Ok((index, data))
+}
+
+/// Read the data shards of a checkpoint, concatenated in a single buffer
+///
+/// Returns the new variables index, see `merge_index`, and the data. Shards
+/// are read straight into the buffer, so they are never held twice in memory.
+///
+/// # Arguments
+///
+/// * `index` - The contents of the `variables.index` file
+/// * `paths` - The paths of the `variables.data-*-of-*` files, in shard order
+pub(crate) fn read_shards(index: Vec<u8>, paths: &[PathBuf]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut total = 0u64;
+    for path in paths.iter() {
+        total += fs::metadata(path)?.len();
+    }
+
+    let mut data = Vec::with_capacity(total as usize);
+    let mut lens = Vec::with_capacity(paths.len());
+    for path in paths.iter() {
+        let start = data.len();
+        File::open(path)?.read_to_end(&mut data)?;
+        lens.push((data.len() - start) as u64);
+    }
+
+    match lens.len() {
+        0 => Err(Error::InvalidArgument),
+        1 => Ok((index, data)),
+        _ => Ok((merge_index(&index, &lens)?, data)),
+    }
+}
+
+/// Parse a `variables.data-NNNNN-of-MMMMM` file name
+fn parse_shard_name(name: &str) -> Option<(usize, usize)> {
+    let rest = name.strip_prefix("variables.data-")?;
+    let (idx, total) = rest.split_once("-of-")?;
+
+    Some((idx.parse().ok()?, total.parse().ok()?))
+}
+
+/// Check that `shards` form a complete set of shards and sort them
+///
+/// # Arguments
+///
+/// * `shards` - The file names of the shards, along with their contents or paths
+pub(crate) fn order_shards<T>(shards: Vec<(String, T)>) -> Result<Vec<T>> {
+    let mut ordered = Vec::with_capacity(shards.len());
+    for (name, shard) in shards {
+        let (idx, total) = parse_shard_name(&name)
+            .ok_or_else(|| Error::Checkpoint(format!("unexpected shard file name {}", name)))?;
+        ordered.push((idx, total, name, shard));
+    }
+
+    if ordered.is_empty() {
+        return Err(checkpoint_error("no variables data shards found"));
+    }
+
+    ordered.sort_by_key(|a| a.0);
+    let total = ordered[0].1;
+    for (i, (idx, t, name, _)) in ordered.iter().enumerate() {
+        if *t != total || *idx != i {
+            return Err(Error::Checkpoint(format!(
+                "shard {} does not belong to a complete set of {} shards",
+                name, total
+            )));
+        }
+    }
+    if ordered.len() != total {
+        return Err(Error::Checkpoint(format!(
+            "found {} out of {} shards",
+            ordered.len(),
+            total
+        )));
+    }
+
+    Ok(ordered.into_iter().map(|(_, _, _, shard)| shard).collect())
+}
+
+/// Find the data shards of the checkpoint in `dir`, in shard order
+///
+/// Fails if the set of shards is incomplete or inconsistent.
+pub(crate) fn shard_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut shards = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) if name.starts_with("variables.data-") => {
+                shards.push((name.to_owned(), path.clone()))
+            }
+            _ => continue,
+        }
+    }
+
+    order_shards(shards)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testdata(path: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join(path)
+    }
+
+    /// The index of the single shard checkpoint of the example model
+    fn example_index() -> Vec<u8> {
+        fs::read(testdata(
+            "examples/files/tf/lstm2/variables/variables.index",
+        ))
+        .unwrap()
+    }
+
+    /// A checkpoint with 20 variables split in 2 shards, as written by
+    /// TensorFlow's `MergeBundles`. Every entry records the CRC32-C of its
+    /// tensor data.
+    fn sharded() -> (Vec<u8>, Vec<Vec<u8>>) {
+        let dir = testdata("src/tensorflow/testdata/sharded");
+        let shards = shard_files(&dir)
+            .unwrap()
+            .iter()
+            .map(|p| fs::read(p).unwrap())
+            .collect();
+
+        (fs::read(dir.join("variables.index")).unwrap(), shards)
+    }
+
+    fn fixed32_field(msg: &[u8], number: u32) -> Option<u32> {
+        let mut is = CodedInputStream::from_bytes(msg);
+        while !is.eof().unwrap() {
+            let (field, wire_type) = is.read_tag_unpack().unwrap();
+            if field == number && wire_type == WireType::WireTypeFixed32 {
+                return Some(is.read_fixed32().unwrap());
+            }
+            is.skip_field(wire_type).unwrap();
+        }
+
+        None
+    }
+
+    /// The data of the tensor described by a `BundleEntryProto`
+    fn tensor_data<'a>(entry: &[u8], shards: &[&'a [u8]]) -> &'a [u8] {
+        let shard = varint_field(entry, 3).unwrap().unwrap_or(0) as usize;
+        let offset = varint_field(entry, 4).unwrap().unwrap_or(0) as usize;
+        let size = varint_field(entry, 5).unwrap().unwrap_or(0) as usize;
+
+        &shards[shard][offset..offset + size]
+    }
+
+    #[test]
+    fn crc32c_check_value() {
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(crc32c(b""), 0);
+    }
+
+    #[test]
+    fn table_round_trip() {
+        for index in [example_index(), sharded().0] {
+            let entries = read_table(&index).unwrap();
+            assert_eq!(write_table(&entries), index);
+        }
+
+        let keys: Vec<Vec<u8>> = read_table(&example_index())
+            .unwrap()
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(keys.len(), 15);
+        assert!(keys[0].is_empty());
+        assert_eq!(keys[1], b"_CHECKPOINTABLE_OBJECT_GRAPH");
+    }
+
+    #[test]
+    fn table_with_several_blocks() {
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..300u32)
+            .map(|i| (format!("key/{:04}", i).into_bytes(), vec![i as u8; 1024]))
+            .collect();
+
+        let table = write_table(&entries);
+        assert_eq!(read_table(&table).unwrap(), entries);
+
+        assert_eq!(shortest_separator(b"key/0255", b"key/0256"), b"key/0255");
+        assert_eq!(shortest_separator(b"abc", b"abx"), b"abd");
+        assert_eq!(short_successor(b"key/0299"), b"l");
+        assert_eq!(short_successor(b"\xff\xffa"), b"\xff\xffb");
+    }
+
+    #[test]
+    fn reject_corrupt_tables() {
+        let mut index = example_index();
+        index[20] ^= 1;
+        assert!(matches!(read_table(&index), Err(Error::Checkpoint(_))));
+
+        let index = example_index();
+        assert!(read_table(&index[1..]).is_err());
+        assert!(read_table(&index[..40]).is_err());
+    }
+
+    #[test]
+    fn merged_offsets_point_to_the_same_data() {
+        let (index, shards) = sharded();
+        let shards: Vec<&[u8]> = shards.iter().map(|s| &s[..]).collect();
+
+        let (merged_index, data) = merge_shards(&index, &shards).unwrap();
+        assert_eq!(data.len(), shards[0].len() + shards[1].len());
+
+        let original = read_table(&index).unwrap();
+        let merged = read_table(&merged_index).unwrap();
+        assert_eq!(original.len(), 22);
+        assert_eq!(merged.len(), original.len());
+
+        // The header now describes a single shard
+        assert_eq!(varint_field(&original[0].1, 1).unwrap(), Some(2));
+        assert_eq!(varint_field(&merged[0].1, 1).unwrap(), Some(1));
+
+        for ((key, entry), (merged_key, merged_entry)) in original[1..].iter().zip(&merged[1..]) {
+            assert_eq!(key, merged_key);
+            assert_eq!(varint_field(merged_entry, 3).unwrap(), None);
+
+            let expected = tensor_data(entry, &shards);
+            let actual = tensor_data(merged_entry, &[&data]);
+            assert_eq!(actual, expected);
+            assert_eq!(
+                fixed32_field(merged_entry, 6),
+                Some(masked_crc32c(actual)),
+                "{}",
+                String::from_utf8_lossy(key)
+            );
+        }
+    }
+
+    #[test]
+    fn merge_from_owned_shards_and_files() {
+        let (index, shards) = sharded();
+        let borrowed: Vec<&[u8]> = shards.iter().map(|s| &s[..]).collect();
+        let expected = merge_shards(&index, &borrowed).unwrap();
+
+        assert_eq!(
+            single_shard(index.clone(), shards.clone()).unwrap(),
+            expected
+        );
+
+        let paths = shard_files(&testdata("src/tensorflow/testdata/sharded")).unwrap();
+        assert_eq!(read_shards(index.clone(), &paths).unwrap(), expected);
+
+        // A single shard is used as is
+        let single = single_shard(index.clone(), vec![shards[0].clone()]).unwrap();
+        assert_eq!(single, (index.clone(), shards[0].clone()));
+
+        assert!(single_shard(index.clone(), Vec::new()).is_err());
+        assert!(matches!(
+            merge_shards(&index, &borrowed[..1]),
+            Err(Error::Checkpoint(_))
+        ));
+        assert!(merge_shards(&index, &[&[], &[], &[]]).is_err());
+    }
+
+    #[test]
+    fn complete_shard_sets() {
+        let name = |i: usize, n: usize| format!("variables.data-{:05}-of-{:05}", i, n);
+
+        let shards = vec![(name(1, 2), 1), (name(0, 2), 0)];
+        assert_eq!(order_shards(shards).unwrap(), &[0, 1]);
+
+        assert!(order_shards(vec![(name(0, 2), 0)]).is_err());
+        assert!(order_shards(vec![(name(0, 2), 0), (name(2, 2), 2)]).is_err());
+        assert!(order_shards(vec![(name(0, 1), 0), (name(1, 2), 1)]).is_err());
+        assert!(order_shards(vec![("variables.data".to_owned(), 0)]).is_err());
+        assert!(order_shards::<u8>(Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,335 @@
+use crate::tensorflow::{Code, DataType, OwnedVaccelTensor, TensorAny};
+use crate::{Error, Result};
+
+use protocols::tensorflow::{TFDataType, TFTensor};
+
+/// The maximum data size allocated up front for a tensor a peer describes
+///
+/// The data of larger tensors is allocated as it arrives, so that a header or
+/// the dimensions of a tensor cannot make us allocate memory for data that is
+/// never sent.
+pub(crate) const MAX_PREALLOCATION: usize = 64 << 20;
+
+/// The size in bytes of the elements of a data type
+///
+/// Returns `None` for data types with elements of variable size.
+pub(crate) fn element_size(data_type: DataType) -> Option<usize> {
+    Some(match data_type {
+        DataType::Int8 | DataType::UInt8 | DataType::QInt8 | DataType::QUInt8 | DataType::Bool => 1,
+        DataType::Int16
+        | DataType::UInt16
+        | DataType::QInt16
+        | DataType::QUInt16
+        | DataType::BFloat16
+        | DataType::Half => 2,
+        DataType::Float | DataType::Int32 | DataType::UInt32 | DataType::QInt32 => 4,
+        DataType::Double | DataType::Int64 | DataType::UInt64 | DataType::Complex64 => 8,
+        DataType::Complex128 => 16,
+        _ => return None,
+    })
+}
+
+/// Splits a tensor into a sequence of `TFTensor` fragments
+///
+/// The first fragment is a header, holding the dimensions and data type of
+/// the tensor and no data. It is followed by fragments of at most
+/// `chunk_size` bytes of data each, which only carry the data type. The
+/// fragments must be reassembled in order, e.g. with a `ChunkDecoder`.
+///
+/// This allows sending tensors that do not fit in a single message. Only
+/// the fragment being yielded is copied, so the whole payload is never
+/// held in memory twice.
+///
+/// `RemoteSession` and `Dispatcher` send tensors in a single message, so
+/// fragments need a channel of their own, e.g. a stream of messages between
+/// a client and an agent that both support it.
+pub struct ChunkEncoder<'a> {
+    header: Option<TFTensor>,
+    data_type: TFDataType,
+    data: &'a [u8],
+    chunk_size: usize,
+}
+
+impl<'a> ChunkEncoder<'a> {
+    /// Create a new encoder
+    ///
+    /// # Arguments
+    ///
+    /// * `tensor` - The tensor to split
+    /// * `chunk_size` - The maximum size of the data of each fragment, in bytes
+    pub fn new(tensor: &'a dyn TensorAny, chunk_size: usize) -> Result<Self> {
+        let inner = tensor.inner();
+        if inner.is_null() || chunk_size == 0 {
+            return Err(Error::InvalidArgument);
+        }
+
+        let (dims, data) = unsafe {
+            let dims =
+                std::slice::from_raw_parts((*inner).dims as *const u64, (*inner).nr_dims as usize);
+            let data = if (*inner).data.is_null() {
+                &[]
+            } else {
+                std::slice::from_raw_parts((*inner).data as *const u8, (*inner).size as usize)
+            };
+            (dims, data)
+        };
+
+        let data_type = TFDataType::from(tensor.data_type());
+
+        Ok(ChunkEncoder {
+            header: Some(TFTensor {
+                dims: dims.to_owned(),
+                field_type: data_type,
+                ..Default::default()
+            }),
+            data_type,
+            data,
+            chunk_size,
+        })
+    }
+
+    /// Get the number of fragments left, including the header
+    pub fn remaining(&self) -> usize {
+        let chunks = self.data.chunks(self.chunk_size).len();
+        chunks + self.header.is_some() as usize
+    }
+}
+
+impl<'a> Iterator for ChunkEncoder<'a> {
+    type Item = TFTensor;
+
+    fn next(&mut self) -> Option<TFTensor> {
+        if let Some(header) = self.header.take() {
+            return Some(header);
+        }
+
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let len = self.chunk_size.min(self.data.len());
+        let (chunk, rest) = self.data.split_at(len);
+        self.data = rest;
+
+        Some(TFTensor {
+            data: chunk.to_owned(),
+            field_type: self.data_type,
+            ..Default::default()
+        })
+    }
+}
+
+/// Reassembles a tensor from the fragments of a `ChunkEncoder`
+///
+/// Each fragment is copied in place in a single buffer, which the resulting
+/// vAccel tensor uses as is. For tensors with elements of fixed size, the
+/// buffer is allocated from the dimensions in the header, up to 64 MiB, and
+/// fragments beyond the size of the tensor are rejected.
+pub struct ChunkDecoder {
+    dims: Vec<u64>,
+    data_type: TFDataType,
+    data: Vec<u8>,
+    // The size of the data, when it is known from the header
+    size: Option<usize>,
+}
+
+impl ChunkDecoder {
+    /// Create a new decoder
+    ///
+    /// # Arguments
+    ///
+    /// * `header` - The first fragment, with the dimensions and data type of the tensor
+    pub fn new(header: &TFTensor) -> Result<Self> {
+        if !header.get_data().is_empty() {
+            return Err(Error::InvalidArgument);
+        }
+
+        let count = header
+            .get_dims()
+            .iter()
+            .try_fold(1usize, |count, dim| count.checked_mul(*dim as usize))
+            .ok_or(Error::InvalidArgument)?;
+        let size = match element_size(DataType::from(header.get_field_type())) {
+            Some(size) => Some(size.checked_mul(count).ok_or(Error::InvalidArgument)?),
+            None => None,
+        };
+
+        Ok(ChunkDecoder {
+            dims: header.get_dims().to_owned(),
+            data_type: header.get_field_type(),
+            data: Vec::with_capacity(size.unwrap_or(0).min(MAX_PREALLOCATION)),
+            size,
+        })
+    }
+
+    /// Add the next fragment of the tensor
+    pub fn push(&mut self, chunk: &TFTensor) -> Result<()> {
+        if chunk.get_field_type() != self.data_type || !chunk.get_dims().is_empty() {
+            return Err(Error::TensorFlow(Code::InvalidArgument));
+        }
+
+        if let Some(size) = self.size {
+            if self.data.len() + chunk.get_data().len() > size {
+                return Err(Error::TensorFlow(Code::OutOfRange));
+            }
+        }
+
+        self.data.extend_from_slice(chunk.get_data());
+        Ok(())
+    }
+
+    /// Whether all the data of the tensor has been received
+    ///
+    /// This is only known for tensors with elements of fixed size. For the
+    /// rest, the tensor is complete when the fragments run out.
+    pub fn is_complete(&self) -> bool {
+        self.size == Some(self.data.len())
+    }
+
+    /// Get the reassembled tensor
+    ///
+    /// Fails with `Code::DataLoss` if fragments are missing.
+    pub fn finish(self) -> Result<OwnedVaccelTensor> {
+        if matches!(self.size, Some(size) if size != self.data.len()) {
+            return Err(Error::TensorFlow(Code::DataLoss));
+        }
+
+        OwnedVaccelTensor::new(&self.dims, self.data_type, self.data)
+    }
+}
+
+/// Reassemble a tensor from all of its fragments
+///
+/// # Arguments
+///
+/// * `chunks` - The header followed by the data fragments, in order
+pub fn decode_chunks<'a, I>(chunks: I) -> Result<OwnedVaccelTensor>
+where
+    I: IntoIterator<Item = &'a TFTensor>,
+{
+    let mut chunks = chunks.into_iter();
+    let header = chunks
+        .next()
+        .ok_or(Error::TensorFlow(Code::InvalidArgument))?;
+
+    let mut decoder = ChunkDecoder::new(header)?;
+    for chunk in chunks {
+        decoder.push(chunk)?;
+    }
+
+    decoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tensorflow::Tensor;
+
+    fn header(dims: &[u64], data_type: DataType) -> TFTensor {
+        TFTensor {
+            dims: dims.to_owned(),
+            field_type: TFDataType::from(data_type),
+            ..Default::default()
+        }
+    }
+
+    fn chunk(data: &[u8], data_type: DataType) -> TFTensor {
+        TFTensor {
+            data: data.to_owned(),
+            field_type: TFDataType::from(data_type),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn chunk_round_trip() {
+        let values: Vec<f32> = (0..15).map(|v| v as f32 * 0.5).collect();
+        let tensor = Tensor::<f32>::new(&[3, 5]).with_data(&values).unwrap();
+
+        // Fragments do not have to hold whole elements
+        let encoder = ChunkEncoder::new(&tensor, 7).unwrap();
+        assert_eq!(encoder.remaining(), 1 + 9);
+        let chunks: Vec<TFTensor> = encoder.collect();
+        assert_eq!(chunks.len(), 10);
+        assert_eq!(chunks[0].get_dims(), [3, 5]);
+        assert!(chunks[0].get_data().is_empty());
+        assert!(chunks[1..].iter().all(|c| c.get_dims().is_empty()));
+        assert_eq!(chunks[9].get_data().len(), 60 - 8 * 7);
+
+        let decoded = decode_chunks(&chunks).unwrap();
+        assert_eq!(decoded.dims(), [3, 5]);
+        assert_eq!(decoded.data_type(), DataType::Float);
+        assert_eq!(decoded.data(), tensor.as_grpc().get_data());
+
+        let mut decoder = ChunkDecoder::new(&chunks[0]).unwrap();
+        for (i, c) in chunks[1..].iter().enumerate() {
+            assert!(!decoder.is_complete(), "complete after {} fragments", i);
+            decoder.push(c).unwrap();
+        }
+        assert!(decoder.is_complete());
+    }
+
+    #[test]
+    fn chunk_empty_tensors() {
+        let tensor = Tensor::<i32>::new(&[0, 4]);
+        let chunks: Vec<TFTensor> = ChunkEncoder::new(&tensor, 16).unwrap().collect();
+        assert_eq!(chunks.len(), 1);
+
+        let decoded = decode_chunks(&chunks).unwrap();
+        assert_eq!(decoded.dims(), [0, 4]);
+        assert!(decoded.data().is_empty());
+
+        assert!(ChunkEncoder::new(&tensor, 0).is_err());
+    }
+
+    #[test]
+    fn preallocation_is_capped() {
+        // A header describing 64 GiB of data must not allocate it
+        let decoder = ChunkDecoder::new(&header(&[1 << 30, 16], DataType::Float)).unwrap();
+        assert_eq!(decoder.size, Some(64 << 30));
+        assert!(decoder.data.capacity() <= MAX_PREALLOCATION);
+
+        let decoder = ChunkDecoder::new(&header(&[3, 5], DataType::Float)).unwrap();
+        assert!(decoder.data.capacity() >= 60);
+
+        // Elements of variable size give no size to preallocate
+        let decoder = ChunkDecoder::new(&header(&[1 << 40], DataType::String)).unwrap();
+        assert_eq!(decoder.size, None);
+        assert_eq!(decoder.data.capacity(), 0);
+        assert!(!decoder.is_complete());
+    }
+
+    #[test]
+    fn reject_invalid_chunks() {
+        let mut with_data = header(&[2], DataType::UInt8);
+        with_data.set_data(vec![1, 2]);
+        assert!(ChunkDecoder::new(&with_data).is_err());
+
+        let overflow = header(&[u64::MAX, 2], DataType::UInt8);
+        assert!(ChunkDecoder::new(&overflow).is_err());
+        let overflow = header(&[u64::MAX / 2], DataType::Double);
+        assert!(ChunkDecoder::new(&overflow).is_err());
+
+        let mut decoder = ChunkDecoder::new(&header(&[4], DataType::UInt8)).unwrap();
+        assert!(matches!(
+            decoder.push(&chunk(&[1, 2], DataType::Int8)),
+            Err(Error::TensorFlow(Code::InvalidArgument))
+        ));
+        assert!(matches!(
+            decoder.push(&header(&[4], DataType::UInt8)),
+            Err(Error::TensorFlow(Code::InvalidArgument))
+        ));
+        decoder.push(&chunk(&[1, 2, 3], DataType::UInt8)).unwrap();
+        assert!(matches!(
+            decoder.push(&chunk(&[4, 5], DataType::UInt8)),
+            Err(Error::TensorFlow(Code::OutOfRange))
+        ));
+        assert!(matches!(
+            decoder.finish(),
+            Err(Error::TensorFlow(Code::DataLoss))
+        ));
+
+        assert!(decode_chunks(&[]).is_err());
+    }
+}
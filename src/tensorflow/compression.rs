@@ -0,0 +1,375 @@
+use crate::tensorflow::chunk::{element_size, MAX_PREALLOCATION};
+use crate::tensorflow::{Code, DataType};
+use crate::{Error, Result};
+
+use protobuf::{Message, UnknownFields};
+use protocols::session::CreateSessionResponse;
+use protocols::tensorflow::TFTensor;
+
+use std::borrow::Cow;
+use std::io::{Read, Write};
+
+/// Session flag requesting zstd compression of tensor payloads
+pub const SESSION_FLAG_ZSTD: u32 = 1 << 29;
+
+/// Session flag requesting lz4 compression of tensor payloads
+pub const SESSION_FLAG_LZ4: u32 = 1 << 30;
+
+/// All the session flags about compression
+///
+/// These are not known to the vAccel runtime, so they need to be cleared from
+/// the flags of a session before creating it.
+pub const SESSION_FLAGS: u32 = SESSION_FLAG_ZSTD | SESSION_FLAG_LZ4;
+
+/// The default size, in bytes, below which payloads are not compressed
+pub const DEFAULT_THRESHOLD: usize = 4096;
+
+/// The field of `TFTensor` marking compressed data, with the id of its codec
+///
+/// `TFTensor` has no field for this, so the marker is sent as a field unknown
+/// to the message definition. Peers that do not know it ignore it.
+const CODEC_FIELD: u32 = 1000;
+
+/// The field of `CreateSessionResponse` acknowledging a compression request,
+/// with the id of the codec the agent accepted
+const ACK_FIELD: u32 = 1000;
+
+// zstd's default, which favors speed
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Zstd,
+    Lz4,
+}
+
+impl Codec {
+    fn id(&self) -> u64 {
+        match self {
+            Codec::Zstd => 1,
+            Codec::Lz4 => 2,
+        }
+    }
+
+    fn from_id(id: u64) -> Option<Self> {
+        match id {
+            1 => Some(Codec::Zstd),
+            2 => Some(Codec::Lz4),
+            _ => None,
+        }
+    }
+}
+
+/// Get the id of a marker field of a message, if it is set
+fn marker(fields: &UnknownFields, field: u32) -> Option<u64> {
+    fields.get(field).and_then(|v| v.varint.first().copied())
+}
+
+/// Compression of the data of `TFTensor`s
+///
+/// Compression is negotiated when creating a session: the client requests it
+/// by setting one of the `SESSION_FLAG_*` flags, and the agent accepts it by
+/// acknowledging a codec in its `CreateSessionResponse`. Agents that do not
+/// support compression do not acknowledge it, so both ends only compress
+/// after an acknowledgement. Tensor payloads of at least `threshold` bytes are
+/// then compressed, if that makes them smaller.
+///
+/// Compressed tensors are marked with the id of their codec, so data is only
+/// ever decompressed if it was compressed. Data is decompressed transparently
+/// when converting a `TFTensor` back, e.g. with `Tensor::from_grpc`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Compression {
+    codec: Codec,
+    threshold: usize,
+}
+
+impl Compression {
+    pub fn new(codec: Codec) -> Self {
+        Compression {
+            codec,
+            threshold: DEFAULT_THRESHOLD,
+        }
+    }
+
+    /// Get the compression requested by the flags of a session, if any
+    ///
+    /// If both flags are set, zstd is preferred.
+    pub fn from_flags(flags: u32) -> Option<Self> {
+        if flags & SESSION_FLAG_ZSTD != 0 {
+            Some(Compression::new(Codec::Zstd))
+        } else if flags & SESSION_FLAG_LZ4 != 0 {
+            Some(Compression::new(Codec::Lz4))
+        } else {
+            None
+        }
+    }
+
+    /// Set the size, in bytes, below which payloads are not compressed
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Get the compression an agent acknowledged in the response to a session
+    /// creation, if any
+    pub fn acknowledged(resp: &CreateSessionResponse) -> Option<Self> {
+        marker(resp.get_unknown_fields(), ACK_FIELD)
+            .and_then(Codec::from_id)
+            .map(Compression::new)
+    }
+
+    /// Acknowledge the compression in the response to a session creation
+    pub fn acknowledge(&self, resp: &mut CreateSessionResponse) {
+        let fields = resp.mut_unknown_fields();
+        fields.remove(ACK_FIELD);
+        fields.add_varint(ACK_FIELD, self.codec.id());
+    }
+
+    /// Get the session flag requesting this compression
+    pub fn flags(&self) -> u32 {
+        match self.codec {
+            Codec::Zstd => SESSION_FLAG_ZSTD,
+            Codec::Lz4 => SESSION_FLAG_LZ4,
+        }
+    }
+
+    /// Compress the data of a tensor in place
+    ///
+    /// The data is left as is if it is below the threshold, if its data type
+    /// has elements of variable size, if it is already compressed, or if
+    /// compressing it does not save space. Otherwise, the tensor is marked as
+    /// compressed.
+    pub fn compress(&self, tensor: &mut TFTensor) -> Result<()> {
+        let data = tensor.get_data();
+        if data.len() < self.threshold
+            || expected_size(tensor) != Some(data.len())
+            || marker(tensor.get_unknown_fields(), CODEC_FIELD).is_some()
+        {
+            return Ok(());
+        }
+
+        let compressed = match self.codec {
+            Codec::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL)?,
+            Codec::Lz4 => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+                encoder.write_all(data)?;
+                encoder
+                    .finish()
+                    .map_err(|_| Error::TensorFlow(Code::Internal))?
+            }
+        };
+
+        if compressed.len() < data.len() {
+            tensor.set_data(compressed);
+            tensor
+                .mut_unknown_fields()
+                .add_varint(CODEC_FIELD, self.codec.id());
+        }
+
+        Ok(())
+    }
+}
+
+/// The size of the data of a tensor, if its elements have fixed size
+fn expected_size(tensor: &TFTensor) -> Option<usize> {
+    let size = element_size(DataType::from(tensor.get_field_type()))?;
+    tensor
+        .get_dims()
+        .iter()
+        .try_fold(size, |size, dim| size.checked_mul(*dim as usize))
+}
+
+/// Get the data of a tensor, decompressing it if it is marked as compressed
+///
+/// Fails with `Code::DataLoss` if compressed data does not decompress to the
+/// size the dimensions of the tensor require, and with `Code::Unimplemented`
+/// if it is compressed with an unknown codec.
+pub fn decompressed_data(tensor: &TFTensor) -> Result<Cow<'_, [u8]>> {
+    let data = tensor.get_data();
+    let codec = match marker(tensor.get_unknown_fields(), CODEC_FIELD) {
+        Some(id) => Codec::from_id(id).ok_or(Error::TensorFlow(Code::Unimplemented))?,
+        None => return Ok(Cow::Borrowed(data)),
+    };
+    let size = expected_size(tensor).ok_or(Error::TensorFlow(Code::DataLoss))?;
+
+    // `size` comes from the dimensions the peer sent, so only trust it as a
+    // bound. Read one more byte than expected, to catch oversized payloads.
+    let mut decompressed = Vec::with_capacity(size.min(MAX_PREALLOCATION));
+    let limit = size as u64 + 1;
+    match codec {
+        Codec::Zstd => zstd::stream::read::Decoder::new(data)?
+            .take(limit)
+            .read_to_end(&mut decompressed),
+        Codec::Lz4 => lz4_flex::frame::FrameDecoder::new(data)
+            .take(limit)
+            .read_to_end(&mut decompressed),
+    }
+    .map_err(|_| Error::TensorFlow(Code::DataLoss))?;
+
+    if decompressed.len() != size {
+        return Err(Error::TensorFlow(Code::DataLoss));
+    }
+
+    Ok(Cow::Owned(decompressed))
+}
+
+/// Decompress the data of a tensor in place, if it is marked as compressed
+///
+/// The marker is removed. See `decompressed_data`.
+pub fn decompress(tensor: &mut TFTensor) -> Result<()> {
+    if let Cow::Owned(data) = decompressed_data(tensor)? {
+        tensor.set_data(data);
+        tensor.mut_unknown_fields().remove(CODEC_FIELD);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+    const LZ4_MAGIC: [u8; 4] = [0x04, 0x22, 0x4d, 0x18];
+
+    fn float_tensor(data: Vec<u8>) -> TFTensor {
+        TFTensor {
+            dims: vec![1, data.len() as u64 / 4],
+            field_type: DataType::Float.into(),
+            data,
+            ..Default::default()
+        }
+    }
+
+    fn round_trip(tensor: &TFTensor) -> TFTensor {
+        let bytes = tensor.write_to_bytes().unwrap();
+        TFTensor::parse_from_bytes(&bytes).unwrap()
+    }
+
+    #[test]
+    fn skip_payloads_below_threshold() {
+        let data = vec![0u8; 1024];
+        let mut tensor = float_tensor(data.clone());
+
+        Compression::new(Codec::Zstd).compress(&mut tensor).unwrap();
+        assert_eq!(tensor.get_data(), &data[..]);
+        assert!(tensor.get_unknown_fields().get(CODEC_FIELD).is_none());
+    }
+
+    #[test]
+    fn compress_payloads_above_threshold() {
+        let data = vec![0u8; 2 * DEFAULT_THRESHOLD];
+        for codec in [Codec::Zstd, Codec::Lz4] {
+            let mut tensor = float_tensor(data.clone());
+            Compression::new(codec).compress(&mut tensor).unwrap();
+            assert!(tensor.get_data().len() < data.len());
+
+            // Compressing twice leaves the tensor as is
+            let compressed = tensor.clone();
+            Compression::new(codec).compress(&mut tensor).unwrap();
+            assert_eq!(tensor, compressed);
+
+            // The marker is sent along with the tensor
+            let mut tensor = round_trip(&tensor);
+            assert_eq!(decompressed_data(&tensor).unwrap(), &data[..]);
+            decompress(&mut tensor).unwrap();
+            assert_eq!(tensor.get_data(), &data[..]);
+            assert!(tensor.get_unknown_fields().get(CODEC_FIELD).is_none());
+        }
+    }
+
+    #[test]
+    fn compress_with_threshold() {
+        let data = vec![0u8; 64];
+        let mut tensor = float_tensor(data.clone());
+
+        Compression::new(Codec::Lz4)
+            .with_threshold(64)
+            .compress(&mut tensor)
+            .unwrap();
+        assert!(tensor.get_data().len() < data.len());
+        assert_eq!(decompressed_data(&tensor).unwrap(), &data[..]);
+    }
+
+    #[test]
+    fn skip_variable_size_payloads() {
+        let mut tensor = TFTensor {
+            dims: vec![1],
+            field_type: DataType::String.into(),
+            data: vec![0u8; 2 * DEFAULT_THRESHOLD],
+            ..Default::default()
+        };
+
+        Compression::new(Codec::Zstd).compress(&mut tensor).unwrap();
+        assert_eq!(tensor.get_data().len(), 2 * DEFAULT_THRESHOLD);
+    }
+
+    #[test]
+    fn keep_raw_payloads_with_magic() {
+        // Payloads that look like compressed frames, and are shorter than
+        // their dimensions require, are only decompressed if marked
+        for magic in [ZSTD_MAGIC, LZ4_MAGIC] {
+            let mut data = magic.to_vec();
+            data.extend_from_slice(&[0u8; 12]);
+            let mut tensor = float_tensor(data.clone());
+            tensor.set_dims(vec![1, 64]);
+
+            assert_eq!(decompressed_data(&tensor).unwrap(), &data[..]);
+            decompress(&mut tensor).unwrap();
+            assert_eq!(tensor.get_data(), &data[..]);
+        }
+    }
+
+    #[test]
+    fn reject_invalid_compressed_payloads() {
+        let mut tensor = float_tensor(vec![0u8; 2 * DEFAULT_THRESHOLD]);
+        Compression::new(Codec::Zstd).compress(&mut tensor).unwrap();
+
+        // The payload decompresses to fewer bytes than required
+        let mut larger = tensor.clone();
+        larger.set_dims(vec![1, DEFAULT_THRESHOLD as u64]);
+        assert!(matches!(
+            decompressed_data(&larger),
+            Err(Error::TensorFlow(Code::DataLoss))
+        ));
+
+        // The payload is not a zstd frame
+        let mut corrupt = tensor.clone();
+        corrupt.set_data(vec![0u8; 16]);
+        assert!(decompressed_data(&corrupt).is_err());
+
+        // The codec is unknown
+        let mut unknown = tensor;
+        unknown.mut_unknown_fields().remove(CODEC_FIELD);
+        unknown.mut_unknown_fields().add_varint(CODEC_FIELD, 42);
+        assert!(matches!(
+            decompressed_data(&unknown),
+            Err(Error::TensorFlow(Code::Unimplemented))
+        ));
+    }
+
+    #[test]
+    fn negotiate_compression() {
+        let mut resp = CreateSessionResponse::new();
+        resp.set_session_id(1);
+        assert_eq!(Compression::acknowledged(&resp), None);
+
+        for flags in [SESSION_FLAG_ZSTD, SESSION_FLAG_LZ4] {
+            let compression = Compression::from_flags(flags).unwrap();
+            compression.acknowledge(&mut resp);
+
+            let bytes = resp.write_to_bytes().unwrap();
+            let resp = CreateSessionResponse::parse_from_bytes(&bytes).unwrap();
+            assert_eq!(Compression::acknowledged(&resp), Some(compression));
+            assert_eq!(resp.get_session_id(), 1);
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use crate::tensorflow::proto::{parse_fields, parse_map_entry, TensorShapeProto};
+use crate::tensorflow::{DataType, Node};
+use crate::Result;
+
+use protobuf::wire_format::WireType;
+use protobuf::CodedInputStream;
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// Read a packed or unpacked repeated `DataType` field value
+fn read_types(
+    is: &mut CodedInputStream,
+    wire_type: WireType,
+    types: &mut Vec<DataType>,
+) -> Result<()> {
+    match wire_type {
+        WireType::WireTypeVarint => types.push(DataType::from_int(is.read_int32()? as u32)),
+        WireType::WireTypeLengthDelimited => {
+            let data = is.read_bytes()?;
+            let mut is = CodedInputStream::from_bytes(&data);
+            while !is.eof()? {
+                types.push(DataType::from_int(is.read_int32()? as u32));
+            }
+        }
+        wt => is.skip_field(wt)?,
+    }
+
+    Ok(())
+}
+
+fn shape_from_proto(shape: TensorShapeProto) -> Option<Vec<i64>> {
+    if shape.unknown_rank {
+        None
+    } else {
+        Some(shape.dims)
+    }
+}
+
+/// Type and shape attributes of a node, decoded from an `AttrValue`
+#[derive(Default)]
+struct AttrValue {
+    types: Vec<DataType>,
+    shapes: Vec<Option<Vec<i64>>>,
+}
+
+impl AttrValue {
+    fn parse(data: &[u8]) -> Result<Self> {
+        let mut attr = AttrValue::default();
+
+        parse_fields(data, |is, field, wire_type| {
+            match (field, wire_type) {
+                (1, WireType::WireTypeLengthDelimited) => {
+                    // AttrValue.ListValue
+                    parse_fields(&is.read_bytes()?, |is, field, wire_type| {
+                        match (field, wire_type) {
+                            (6, wt) => read_types(is, wt, &mut attr.types)?,
+                            (7, WireType::WireTypeLengthDelimited) => attr.shapes.push(
+                                shape_from_proto(TensorShapeProto::parse(&is.read_bytes()?)?),
+                            ),
+                            (_, wt) => is.skip_field(wt)?,
+                        }
+                        Ok(())
+                    })?;
+                }
+                (6, wt) => read_types(is, wt, &mut attr.types)?,
+                (7, WireType::WireTypeLengthDelimited) => attr.shapes.push(shape_from_proto(
+                    TensorShapeProto::parse(&is.read_bytes()?)?,
+                )),
+                (_, wt) => is.skip_field(wt)?,
+            }
+            Ok(())
+        })?;
+
+        Ok(attr)
+    }
+}
+
+/// A node of a TensorFlow graph
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GraphNode {
+    /// Name of the node
+    pub name: String,
+
+    /// Operation of the node, e.g. `Placeholder`
+    pub op: String,
+
+    /// Inputs of the node, as `node`, `node:index`, or `^node` for control inputs
+    pub inputs: Vec<String>,
+
+    /// Device the node is assigned to, if any
+    pub device: String,
+
+    /// Type attributes of the node, e.g. `dtype` or `T`
+    pub types: BTreeMap<String, Vec<DataType>>,
+
+    /// Shape attributes of the node, e.g. `shape` or `_output_shapes`. Dimensions of unknown
+    /// size are `-1`, and shapes of unknown rank are `None`.
+    pub shapes: BTreeMap<String, Vec<Option<Vec<i64>>>>,
+}
+
+impl GraphNode {
+    fn parse(data: &[u8]) -> Result<Self> {
+        let mut node = GraphNode::default();
+
+        parse_fields(data, |is, field, wire_type| {
+            match (field, wire_type) {
+                (1, WireType::WireTypeLengthDelimited) => node.name = is.read_string()?,
+                (2, WireType::WireTypeLengthDelimited) => node.op = is.read_string()?,
+                (3, WireType::WireTypeLengthDelimited) => node.inputs.push(is.read_string()?),
+                (4, WireType::WireTypeLengthDelimited) => node.device = is.read_string()?,
+                (5, WireType::WireTypeLengthDelimited) => {
+                    let (key, value) = parse_map_entry(&is.read_bytes()?)?;
+                    let attr = AttrValue::parse(&value)?;
+                    if !attr.types.is_empty() {
+                        node.types.insert(key.clone(), attr.types);
+                    }
+                    if !attr.shapes.is_empty() {
+                        node.shapes.insert(key, attr.shapes);
+                    }
+                }
+                (_, wt) => is.skip_field(wt)?,
+            }
+            Ok(())
+        })?;
+
+        Ok(node)
+    }
+
+    /// The element type of the node's first output
+    ///
+    /// This is looked up in the attributes usually holding it: `dtype`, `T`,
+    /// `Tout` and `out_type`.
+    pub fn dtype(&self) -> Option<DataType> {
+        ["dtype", "T", "Tout", "out_type"]
+            .iter()
+            .find_map(|attr| self.types.get(*attr))
+            .and_then(|t| t.first().copied())
+    }
+
+    /// The shape of the node's output, from its `shape` or `_output_shapes` attribute
+    pub fn shape(&self) -> Option<&[i64]> {
+        self.shapes
+            .get("shape")
+            .or_else(|| self.shapes.get("_output_shapes"))
+            .and_then(|s| s.first())
+            .and_then(|s| s.as_deref())
+    }
+
+    /// Create the `Node` to use for the first output of this node in `InferenceArgs`
+    pub fn node(&self) -> Node {
+        Node::new(&self.name, 0)
+    }
+}
+
+/// Name of the node an input string refers to
+fn input_node_name(input: &str) -> &str {
+    let input = input.trim_start_matches('^');
+    match input.rsplit_once(':') {
+        Some((name, _)) => name,
+        None => input,
+    }
+}
+
+/// A TensorFlow graph, decoded from a `GraphDef` protobuf, e.g. a frozen model
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Graph {
+    pub nodes: Vec<GraphNode>,
+}
+
+impl Graph {
+    /// Parse a serialized `GraphDef`
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The contents of the `.pb` model file
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut graph = Graph::default();
+
+        parse_fields(data, |is, field, wire_type| {
+            match (field, wire_type) {
+                (1, WireType::WireTypeLengthDelimited) => {
+                    graph.nodes.push(GraphNode::parse(&is.read_bytes()?)?)
+                }
+                (_, wt) => is.skip_field(wt)?,
+            }
+            Ok(())
+        })?;
+
+        Ok(graph)
+    }
+
+    /// Read and parse a `GraphDef` file
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the `.pb` model file
+    pub fn read(path: &Path) -> Result<Self> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    /// Get a node by name
+    pub fn node(&self, name: &str) -> Option<&GraphNode> {
+        self.nodes.iter().find(|n| n.name == name)
+    }
+
+    /// Nodes that are likely inputs of the model, i.e. `Placeholder` ops
+    pub fn input_candidates(&self) -> Vec<&GraphNode> {
+        self.nodes
+            .iter()
+            .filter(|n| n.op == "Placeholder" || n.op == "PlaceholderWithDefault")
+            .collect()
+    }
+
+    /// Nodes that are likely outputs of the model, i.e. nodes whose outputs
+    /// are not consumed by any other node
+    ///
+    /// `NoOp` nodes are skipped, as they do not produce any tensors.
+    pub fn output_candidates(&self) -> Vec<&GraphNode> {
+        let consumed: HashSet<&str> = self
+            .nodes
+            .iter()
+            .flat_map(|n| n.inputs.iter())
+            .map(|i| input_node_name(i))
+            .collect();
+
+        self.nodes
+            .iter()
+            .filter(|n| n.op != "NoOp" && !consumed.contains(n.name.as_str()))
+            .collect()
+    }
+
+    /// The likely inputs of the model, ready for `InferenceArgs::add_input`
+    pub fn suggested_inputs(&self) -> Vec<Node> {
+        self.input_candidates().iter().map(|n| n.node()).collect()
+    }
+
+    /// The likely outputs of the model, ready for `InferenceArgs::request_output`
+    pub fn suggested_outputs(&self) -> Vec<Node> {
+        self.output_candidates().iter().map(|n| n.node()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The nodes of the lstm2 example model's graph, without its function library
+    fn lstm2_graph() -> Graph {
+        Graph::read(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("src/tensorflow/testdata/lstm2_graph.pb"),
+        )
+        .unwrap()
+    }
+
+    fn names(nodes: &[&GraphNode]) -> Vec<String> {
+        nodes.iter().map(|n| n.name.clone()).collect()
+    }
+
+    #[test]
+    fn parse_graph_nodes() {
+        let graph = lstm2_graph();
+        assert_eq!(graph.nodes.len(), 33);
+
+        let input = graph.node("serving_default_input_1").unwrap();
+        assert_eq!(input.op, "Placeholder");
+        assert_eq!(input.dtype(), Some(DataType::Float));
+        assert_eq!(input.shape(), Some(&[-1, 30][..]));
+        assert!(input.inputs.is_empty());
+
+        let call = graph.node("StatefulPartitionedCall").unwrap();
+        assert_eq!(call.inputs.len(), 10);
+        assert_eq!(call.inputs[0], "serving_default_input_1");
+        assert_eq!(call.types["Tin"].len(), 10);
+        assert_eq!(call.types["Tout"], [DataType::Float]);
+        assert_eq!(call.dtype(), Some(DataType::Float));
+        assert_eq!(call.shape(), Some(&[-1, 30, 61][..]));
+
+        let embeddings = graph.node("embedding/embeddings").unwrap();
+        assert_eq!(embeddings.shape(), Some(&[1001, 40][..]));
+        assert_eq!(embeddings.shapes["_output_shapes"], [Some(vec![])]);
+
+        let constant = graph.node("Const").unwrap();
+        assert_eq!(constant.device, "/device:CPU:0");
+        assert_eq!(constant.dtype(), Some(DataType::String));
+
+        assert!(graph.node("NoOp").unwrap().dtype().is_none());
+        assert!(graph.node("missing").is_none());
+    }
+
+    #[test]
+    fn graph_candidates() {
+        let graph = lstm2_graph();
+
+        assert_eq!(
+            names(&graph.input_candidates()),
+            ["serving_default_input_1", "saver_filename"]
+        );
+        assert_eq!(
+            names(&graph.output_candidates()),
+            [
+                "StatefulPartitionedCall",
+                "StatefulPartitionedCall_1",
+                "StatefulPartitionedCall_2"
+            ]
+        );
+
+        let inputs = graph.suggested_inputs();
+        assert_eq!(inputs[0].name(), "serving_default_input_1");
+        assert_eq!(inputs[0].id(), 0);
+        assert_eq!(graph.suggested_outputs().len(), 3);
+    }
+
+    #[test]
+    fn parse_node_attributes() {
+        // node {
+        //   name: "n" op: "IdentityN" input: "^a" input: "b:1"
+        //   attr { key: "T" value { list { type: [DT_FLOAT, DT_INT32] } } }  (packed)
+        //   attr { key: "U" value { list { type: DT_INT64 type: DT_FLOAT } } }  (unpacked)
+        //   attr { key: "shape" value { shape { unknown_rank: true } } }
+        //   attr { key: "_output_shapes" value { list {
+        //     shape { dim { size: -1 } dim { size: 3 } } shape { unknown_rank: true } } } }
+        // }
+        let data = [
+            0x0a, 0x6d, 0x0a, 0x01, 0x6e, 0x12, 0x09, 0x49, 0x64, 0x65, 0x6e, 0x74, 0x69, 0x74,
+            0x79, 0x4e, 0x1a, 0x02, 0x5e, 0x61, 0x1a, 0x03, 0x62, 0x3a, 0x31, 0x2a, 0x0b, 0x0a,
+            0x01, 0x54, 0x12, 0x06, 0x0a, 0x04, 0x32, 0x02, 0x01, 0x03, 0x2a, 0x0b, 0x0a, 0x01,
+            0x55, 0x12, 0x06, 0x0a, 0x04, 0x30, 0x09, 0x30, 0x01, 0x2a, 0x0d, 0x0a, 0x05, 0x73,
+            0x68, 0x61, 0x70, 0x65, 0x12, 0x04, 0x3a, 0x02, 0x18, 0x01, 0x2a, 0x2b, 0x0a, 0x0e,
+            0x5f, 0x6f, 0x75, 0x74, 0x70, 0x75, 0x74, 0x5f, 0x73, 0x68, 0x61, 0x70, 0x65, 0x73,
+            0x12, 0x19, 0x0a, 0x17, 0x3a, 0x11, 0x12, 0x0b, 0x08, 0xff, 0xff, 0xff, 0xff, 0xff,
+            0xff, 0xff, 0xff, 0xff, 0x01, 0x12, 0x02, 0x08, 0x03, 0x3a, 0x02, 0x18, 0x01,
+        ];
+        let graph = Graph::parse(&data).unwrap();
+        let node = &graph.nodes[0];
+
+        assert_eq!(node.inputs, ["^a", "b:1"]);
+        assert_eq!(node.types["T"], [DataType::Float, DataType::Int32]);
+        assert_eq!(node.types["U"], [DataType::Int64, DataType::Float]);
+        assert_eq!(node.shapes["shape"], [None]);
+        assert_eq!(node.shapes["_output_shapes"], [Some(vec![-1, 3]), None]);
+
+        // The unknown rank of `shape` takes precedence over `_output_shapes`
+        assert_eq!(node.shape(), None);
+        assert_eq!(node.dtype(), Some(DataType::Float));
+
+        assert!(Graph::parse(&data[..50]).is_err());
+    }
+
+    #[test]
+    fn input_names() {
+        assert_eq!(input_node_name("a"), "a");
+        assert_eq!(input_node_name("scope/a:1"), "scope/a");
+        assert_eq!(input_node_name("^scope/a"), "scope/a");
+    }
+}
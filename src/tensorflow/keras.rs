@@ -0,0 +1,331 @@
+use crate::ffi;
+use crate::tensorflow::proto::parse_fields;
+use crate::tensorflow::SavedModel;
+use crate::{Error, Result};
+
+use protobuf::wire_format::WireType;
+use serde_json::Value;
+
+use std::collections::BTreeMap;
+use std::fs;
+
+/// Parse a Keras shape, e.g. `{"class_name": "TensorShape", "items": [null, 30]}`
+///
+/// Dimensions of unknown size are `-1`.
+fn parse_shape(value: &Value) -> Option<Vec<i64>> {
+    let items = match value {
+        Value::Object(obj) => obj.get("items")?,
+        _ => value,
+    };
+
+    items
+        .as_array()?
+        .iter()
+        .map(|d| match d {
+            Value::Null => Some(-1),
+            d => d.as_i64(),
+        })
+        .collect()
+}
+
+/// An object of a Keras model, e.g. a layer or a metric
+#[derive(Debug, Clone, PartialEq)]
+pub struct KerasObject {
+    /// Path of the object in the model, e.g. `root.layer_with_weights-0`
+    pub path: String,
+
+    /// Kind of the object, e.g. `_tf_keras_layer` or `_tf_keras_metric`
+    pub identifier: String,
+
+    /// Name of the object, e.g. `embedding`
+    pub name: String,
+
+    /// Keras class of the object, e.g. `Embedding`
+    pub class_name: String,
+
+    /// Type of the object's variables and computations, e.g. `float32`
+    pub dtype: Option<String>,
+
+    /// Shape of the input the object was built with, if known
+    pub input_shape: Option<Vec<i64>>,
+
+    /// Configuration of the object, as passed to the Keras constructor
+    pub config: Value,
+
+    /// All the metadata of the object
+    pub metadata: Value,
+}
+
+impl KerasObject {
+    fn parse(data: &[u8]) -> Result<Self> {
+        let mut path = String::new();
+        let mut identifier = String::new();
+        let mut metadata = String::new();
+
+        // SavedObject
+        parse_fields(data, |is, field, wire_type| {
+            match (field, wire_type) {
+                (3, WireType::WireTypeLengthDelimited) => path = is.read_string()?,
+                (4, WireType::WireTypeLengthDelimited) => identifier = is.read_string()?,
+                (5, WireType::WireTypeLengthDelimited) => metadata = is.read_string()?,
+                (_, wt) => is.skip_field(wt)?,
+            }
+            Ok(())
+        })?;
+
+        let metadata: Value = serde_json::from_str(&metadata)?;
+        let string = |key: &str| metadata.get(key).and_then(Value::as_str).map(str::to_owned);
+
+        let input_shape = ["build_input_shape", "batch_input_shape"]
+            .iter()
+            .find_map(|key| metadata.get(*key).and_then(parse_shape));
+
+        Ok(KerasObject {
+            path,
+            identifier,
+            name: string("name").unwrap_or_default(),
+            class_name: string("class_name").unwrap_or_default(),
+            dtype: string("dtype"),
+            input_shape,
+            config: metadata.get("config").cloned().unwrap_or(Value::Null),
+            metadata,
+        })
+    }
+
+    /// Whether the object is a layer, including nested layers and the model
+    pub fn is_layer(&self) -> bool {
+        self.identifier.starts_with("_tf_keras_")
+            && self.identifier != "_tf_keras_metric"
+            && self.identifier != "_tf_keras_input_layer"
+    }
+}
+
+/// Keras metadata of a SavedModel, read from `keras_metadata.pb`
+#[derive(Debug, Clone, PartialEq)]
+pub struct KerasMetadata {
+    /// The model itself
+    pub model: KerasObject,
+
+    /// All the other objects of the model, in the order they are saved
+    pub objects: Vec<KerasObject>,
+
+    /// Version of Keras used to save the model
+    pub keras_version: Option<String>,
+
+    /// Loss, metrics and optimizer used to train the model
+    pub training_config: Option<Value>,
+
+    /// Shapes of the model inputs, keyed by input name
+    ///
+    /// These come from the `serving_default` signature of the model, see
+    /// `SavedModel::keras_metadata`.
+    pub input_shapes: BTreeMap<String, Option<Vec<i64>>>,
+
+    /// Shapes of the model outputs, keyed by output name
+    ///
+    /// These come from the `serving_default` signature of the model, see
+    /// `SavedModel::keras_metadata`.
+    pub output_shapes: BTreeMap<String, Option<Vec<i64>>>,
+}
+
+impl KerasMetadata {
+    /// Parse the contents of a `keras_metadata.pb` file
+    ///
+    /// The shapes of the model inputs and outputs are not part of the Keras
+    /// metadata, so they are left empty. `SavedModel::keras_metadata` fills
+    /// them from the signatures of the model.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut objects = Vec::new();
+
+        // SavedMetadata
+        parse_fields(data, |is, field, wire_type| {
+            match (field, wire_type) {
+                (1, WireType::WireTypeLengthDelimited) => {
+                    objects.push(KerasObject::parse(&is.read_bytes()?)?)
+                }
+                (_, wt) => is.skip_field(wt)?,
+            }
+            Ok(())
+        })?;
+
+        let root = objects
+            .iter()
+            .position(|o| o.path == "root")
+            .ok_or(Error::InvalidArgument)?;
+        let model = objects.remove(root);
+
+        Ok(KerasMetadata {
+            keras_version: model
+                .metadata
+                .get("keras_version")
+                .and_then(Value::as_str)
+                .map(str::to_owned),
+            training_config: model
+                .metadata
+                .get("training_config")
+                .filter(|c| !c.is_null())
+                .cloned(),
+            model,
+            objects,
+            input_shapes: BTreeMap::new(),
+            output_shapes: BTreeMap::new(),
+        })
+    }
+
+    /// The layers directly under the model, in the order they are saved
+    pub fn layers(&self) -> impl Iterator<Item = &KerasObject> {
+        self.objects
+            .iter()
+            .filter(|o| o.is_layer() && o.path.matches('.').count() == 1)
+    }
+}
+
+impl SavedModel {
+    /// Get the Keras metadata of the model
+    ///
+    /// This parses the `keras_metadata.pb` file of the export directory, so
+    /// it is only available for models created with `from_export_dir`. Models
+    /// loaded from memory have no export directory and fail with
+    /// `VACCEL_ENOENT`; use `keras_metadata_from` for them instead. The input
+    /// and output shapes are taken from the `serving_default` signature, if
+    /// the model has one.
+    pub fn keras_metadata(&self) -> Result<KerasMetadata> {
+        if !self.initialized() {
+            return Err(Error::Uninitialized);
+        }
+
+        let path = self.get_path().ok_or(Error::Runtime(ffi::VACCEL_ENOENT))?;
+        self.keras_metadata_from(&fs::read(path.join("keras_metadata.pb"))?)
+    }
+
+    /// Get the Keras metadata of the model from the contents of its
+    /// `keras_metadata.pb` file
+    ///
+    /// This works whichever way the model was loaded. The input and output
+    /// shapes are taken from the `serving_default` signature, like for
+    /// `keras_metadata`.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The contents of `keras_metadata.pb`
+    pub fn keras_metadata_from(&self, data: &[u8]) -> Result<KerasMetadata> {
+        let mut metadata = KerasMetadata::parse(data)?;
+
+        if let Some(signature) = self
+            .signatures()?
+            .into_iter()
+            .find(|s| s.name == "serving_default")
+        {
+            metadata.input_shapes = signature
+                .inputs
+                .into_iter()
+                .map(|(name, info)| (name, info.shape))
+                .collect();
+            metadata.output_shapes = signature
+                .outputs
+                .into_iter()
+                .map(|(name, info)| (name, info.shape))
+                .collect();
+        }
+
+        Ok(metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::{Path, PathBuf};
+
+    fn lstm2() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/files/tf/lstm2")
+    }
+
+    #[test]
+    fn parse_keras_metadata() {
+        let data = fs::read(lstm2().join("keras_metadata.pb")).unwrap();
+        let metadata = KerasMetadata::parse(&data).unwrap();
+
+        assert_eq!(metadata.model.path, "root");
+        assert_eq!(metadata.model.identifier, "_tf_keras_sequential");
+        assert_eq!(metadata.model.class_name, "Sequential");
+        assert_eq!(metadata.model.input_shape, Some(vec![-1, 30]));
+        assert_eq!(metadata.keras_version.as_deref(), Some("2.5.0"));
+        assert_eq!(
+            metadata.training_config.as_ref().unwrap()["loss"],
+            "categorical_crossentropy"
+        );
+        assert!(metadata.input_shapes.is_empty());
+
+        assert_eq!(metadata.objects.len(), 11);
+        let layers: Vec<_> = metadata
+            .layers()
+            .map(|l| (l.name.as_str(), l.class_name.as_str()))
+            .collect();
+        assert_eq!(
+            layers,
+            [
+                ("embedding", "Embedding"),
+                ("bidirectional", "Bidirectional"),
+                ("time_distributed", "TimeDistributed"),
+                ("activation", "Activation")
+            ]
+        );
+
+        let bidirectional = &metadata.objects[1];
+        assert_eq!(bidirectional.path, "root.layer_with_weights-1");
+        assert_eq!(bidirectional.dtype.as_deref(), Some("float32"));
+        assert_eq!(bidirectional.input_shape, Some(vec![-1, 30, 40]));
+        assert_eq!(bidirectional.config["name"], "bidirectional");
+
+        let metrics: Vec<_> = metadata
+            .objects
+            .iter()
+            .filter(|o| !o.is_layer())
+            .map(|o| o.name.as_str())
+            .collect();
+        assert_eq!(metrics, ["loss", "accuracy"]);
+    }
+
+    #[test]
+    fn reject_invalid_keras_metadata() {
+        let data = fs::read(lstm2().join("keras_metadata.pb")).unwrap();
+
+        assert!(KerasMetadata::parse(&data[..100]).is_err());
+        assert!(matches!(
+            KerasMetadata::parse(&[]),
+            Err(Error::InvalidArgument)
+        ));
+    }
+
+    #[test]
+    fn keras_metadata_of_export_dir() {
+        let model = SavedModel::new().from_export_dir(&lstm2()).unwrap();
+        let metadata = model.keras_metadata().unwrap();
+
+        assert_eq!(metadata.model.name, "sequential");
+        assert_eq!(metadata.input_shapes["input_1"], Some(vec![-1, 30]));
+        assert_eq!(metadata.output_shapes["activation"], Some(vec![-1, 30, 61]));
+    }
+
+    #[test]
+    fn keras_metadata_of_in_memory_model() {
+        let path = lstm2();
+        let model = SavedModel::new().from_export_dir_in_memory(&path).unwrap();
+
+        assert!(matches!(
+            model.keras_metadata(),
+            Err(Error::Runtime(ffi::VACCEL_ENOENT))
+        ));
+
+        let data = fs::read(path.join("keras_metadata.pb")).unwrap();
+        let metadata = model.keras_metadata_from(&data).unwrap();
+        assert_eq!(metadata.input_shapes["input_1"], Some(vec![-1, 30]));
+
+        assert!(matches!(
+            SavedModel::new().keras_metadata(),
+            Err(Error::Uninitialized)
+        ));
+    }
+}
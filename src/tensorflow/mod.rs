@@ -1,17 +1,44 @@
 use crate::ffi;
 
+use protobuf::ProtobufEnum;
+use protocols::tensorflow::{TFDataType, TFStatus};
+
 use std::ffi::CStr;
 use std::fmt;
 
+mod archive;
 pub mod buffer;
+pub mod cache;
+mod checkpoint;
+pub mod chunk;
+pub mod compression;
+pub mod graph;
+pub mod keras;
 pub mod node;
+pub mod npy;
+mod proto;
+pub mod run_metadata;
+pub mod run_options;
 pub mod saved_model;
+pub mod signature;
+pub mod slice;
 pub mod tensor;
+pub mod view;
 
 pub use buffer::Buffer;
+pub use cache::{ModelCache, SharedModel};
+pub use chunk::{ChunkDecoder, ChunkEncoder};
+pub use compression::{Codec, Compression};
+pub use graph::{Graph, GraphNode};
+pub use keras::{KerasMetadata, KerasObject};
 pub use node::Node;
+pub use run_metadata::{DeviceStepStats, NodeExecStats, RunMetadata};
+pub use run_options::{RunOptions, TraceLevel};
 pub use saved_model::SavedModel;
-pub use tensor::{Tensor, TensorAny, TensorType};
+pub use signature::{Signature, TensorInfo};
+pub use slice::TensorSlice;
+pub use tensor::{AnyTensor, OwnedVaccelTensor, Tensor, TensorAny, TensorType};
+pub use view::{MmapTensor, TensorView};
 
 #[derive(Debug)]
 pub enum Code {
@@ -98,13 +125,24 @@ impl Status {
     }
 }
 
+/// Convert a `Status` to `TFStatus`
+impl From<&Status> for TFStatus {
+    fn from(status: &Status) -> Self {
+        TFStatus {
+            error_code: status.error_code() as u32,
+            message: status.message(),
+            ..Default::default()
+        }
+    }
+}
+
 impl fmt::Display for Status {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.to_string())
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum DataType {
     UnknownValue(u32),
     Float,
@@ -176,6 +214,7 @@ impl DataType {
             ffi::VACCEL_TF_BOOL => DataType::Bool,
             ffi::VACCEL_TF_QINT8 => DataType::QInt8,
             ffi::VACCEL_TF_QUINT8 => DataType::QUInt8,
+            ffi::VACCEL_TF_QINT32 => DataType::QInt32,
             ffi::VACCEL_TF_BFLOAT16 => DataType::BFloat16,
             ffi::VACCEL_TF_QINT16 => DataType::QInt16,
             ffi::VACCEL_TF_QUINT16 => DataType::QUInt16,
@@ -196,3 +235,19 @@ impl Default for DataType {
         DataType::Float
     }
 }
+
+/// Convert a `DataType` to `TFDataType`
+///
+/// Unknown data types have no `TFDataType` value, so they become `UNUSED`.
+impl From<DataType> for TFDataType {
+    fn from(data_type: DataType) -> Self {
+        TFDataType::from_i32(data_type.to_int() as i32).unwrap_or(TFDataType::UNUSED)
+    }
+}
+
+/// Convert a `TFDataType` to `DataType`
+impl From<TFDataType> for DataType {
+    fn from(data_type: TFDataType) -> Self {
+        DataType::from_int(data_type.value() as u32)
+    }
+}
@@ -3,6 +3,7 @@ use crate::{Error, Result};
 
 use protocols::tensorflow::TFNode;
 
+use std::convert::TryFrom;
 use std::ffi::{CStr, CString};
 use std::fmt;
 
@@ -12,14 +13,26 @@ pub struct Node {
 
 impl Node {
     pub fn new(name: &str, id: i64) -> Self {
+        Node::try_new(name, id).expect("Invalid TensorFlow node name")
+    }
+
+    /// Create a new TensorFlow node
+    ///
+    /// Returns `Error::InvalidArgument` if `name` contains a NUL byte.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the node
+    /// * `id` - The index of the node's output
+    pub fn try_new(name: &str, id: i64) -> Result<Self> {
         let name = CString::new(name)
-            .expect("Invalid TensorFlow node name")
+            .map_err(|_| Error::InvalidArgument)?
             .into_raw();
 
         let inner = unsafe { ffi::vaccel_tf_node_new(name, id) };
         assert!(!inner.is_null(), "Memory allocation failure");
 
-        Node { inner }
+        Ok(Node { inner })
     }
 
     pub unsafe fn from_vaccel_node(node: *mut ffi::vaccel_tf_node) -> Result<Self> {
@@ -67,12 +80,13 @@ impl fmt::Display for Node {
 
 /// Convert a `TFNode` to a `tensorflow::Node`
 ///
-/// This can fail if the creating the underlying node
-/// fails.
-///
-impl From<&TFNode> for Node {
-    fn from(node: &TFNode) -> Self {
-        Node::new(node.get_name(), node.get_id())
+/// This fails with `Error::InvalidArgument` if the node name
+/// contains a NUL byte.
+impl TryFrom<&TFNode> for Node {
+    type Error = Error;
+
+    fn try_from(node: &TFNode) -> Result<Self> {
+        Node::try_new(node.get_name(), node.get_id())
     }
 }
 
@@ -112,3 +126,20 @@ impl From<&ffi::vaccel_tf_node> for TFNode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn node_rejects_nul_in_name() {
+        let mut node = TFNode::new();
+        node.set_name("in\0put".to_owned());
+        assert!(matches!(Node::try_from(&node), Err(Error::InvalidArgument)));
+
+        node.set_name("input".to_owned());
+        node.set_id(1);
+        let node = Node::try_from(&node).unwrap();
+        assert_eq!(node.to_string(), "input:1");
+    }
+}
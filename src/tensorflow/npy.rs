@@ -0,0 +1,533 @@
+use crate::tensorflow::slice::strides;
+use crate::tensorflow::{AnyTensor, DataType, Tensor, TensorType};
+use crate::{Error, Result};
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Header alignment required by the format specification
+const HEADER_ALIGN: usize = 64;
+
+/// The parsed header of a `.npy` file
+///
+/// The format is described in
+/// <https://numpy.org/doc/stable/reference/generated/numpy.lib.format.html>
+#[derive(Debug, PartialEq)]
+pub struct Header {
+    /// Type of the array elements
+    pub data_type: DataType,
+
+    /// Whether elements are stored big-endian
+    pub big_endian: bool,
+
+    /// Whether elements are stored in column-major order
+    pub fortran_order: bool,
+
+    /// Dimensions of the array
+    pub dims: Vec<u64>,
+
+    /// Size of the header, including the magic string, in bytes
+    pub len: usize,
+}
+
+/// Map a NumPy type descriptor, e.g. `<f4`, to a `DataType`
+fn parse_descr(descr: &str) -> Result<(DataType, bool)> {
+    let mut chars = descr.chars();
+    let big_endian = match chars.next() {
+        Some('<') | Some('|') => false,
+        Some('=') => cfg!(target_endian = "big"),
+        Some('>') => true,
+        _ => return Err(Error::Npy(format!("invalid type descriptor '{}'", descr))),
+    };
+
+    let data_type = match chars.as_str() {
+        "f4" => DataType::Float,
+        "f8" => DataType::Double,
+        "i1" => DataType::Int8,
+        "i2" => DataType::Int16,
+        "i4" => DataType::Int32,
+        "i8" => DataType::Int64,
+        "u1" => DataType::UInt8,
+        "u2" => DataType::UInt16,
+        "u4" => DataType::UInt32,
+        "u8" => DataType::UInt64,
+        "b1" => DataType::Bool,
+        _ => {
+            return Err(Error::Npy(format!(
+                "unsupported type descriptor '{}'",
+                descr
+            )))
+        }
+    };
+
+    Ok((data_type, big_endian))
+}
+
+/// Map a `DataType` to its little-endian NumPy type descriptor
+fn descr(data_type: &DataType) -> Result<&'static str> {
+    match data_type {
+        DataType::Float => Ok("<f4"),
+        DataType::Double => Ok("<f8"),
+        DataType::Int8 => Ok("|i1"),
+        DataType::Int16 => Ok("<i2"),
+        DataType::Int32 => Ok("<i4"),
+        DataType::Int64 => Ok("<i8"),
+        DataType::UInt8 => Ok("|u1"),
+        DataType::UInt16 => Ok("<u2"),
+        DataType::UInt32 => Ok("<u4"),
+        DataType::UInt64 => Ok("<u8"),
+        DataType::Bool => Ok("|b1"),
+        dt => Err(Error::Npy(format!("no NumPy type for {:?}", dt))),
+    }
+}
+
+/// Find the value of `key` in the Python dictionary literal of a header
+fn dict_value<'a>(dict: &'a str, key: &str) -> Result<&'a str> {
+    let pattern = format!("'{}':", key);
+    let start = dict
+        .find(&pattern)
+        .ok_or_else(|| Error::Npy(format!("header is missing '{}'", key)))?
+        + pattern.len();
+    let value = dict[start..].trim_start();
+
+    // Values are either tuples, quoted strings or bare words
+    let end = match value.chars().next() {
+        Some('(') => value.find(')').map(|e| e + 1),
+        Some('\'') => value[1..].find('\'').map(|e| e + 2),
+        _ => value.find(|c| c == ',' || c == '}'),
+    }
+    .ok_or_else(|| Error::Npy(format!("malformed value for '{}'", key)))?;
+
+    Ok(&value[..end])
+}
+
+fn parse_shape(shape: &str) -> Result<Vec<u64>> {
+    shape
+        .trim_start_matches('(')
+        .trim_end_matches(')')
+        .split(',')
+        .map(|d| d.trim())
+        .filter(|d| !d.is_empty())
+        .map(|d| {
+            d.parse::<u64>()
+                .map_err(|_| Error::Npy(format!("invalid dimension '{}' in shape {}", d, shape)))
+        })
+        .collect()
+}
+
+impl Header {
+    /// Read and parse the header at the start of a `.npy` stream
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut preamble = [0u8; 8];
+        reader.read_exact(&mut preamble)?;
+        if &preamble[..6] != MAGIC {
+            return Err(Error::Npy("not a NumPy file".to_owned()));
+        }
+
+        let (dict_len, prefix_len) = match preamble[6] {
+            1 => {
+                let mut len = [0u8; 2];
+                reader.read_exact(&mut len)?;
+                (u16::from_le_bytes(len) as usize, 10)
+            }
+            2 | 3 => {
+                let mut len = [0u8; 4];
+                reader.read_exact(&mut len)?;
+                (u32::from_le_bytes(len) as usize, 12)
+            }
+            v => return Err(Error::Npy(format!("unsupported format version {}", v))),
+        };
+
+        let mut dict = vec![0u8; dict_len];
+        reader.read_exact(&mut dict)?;
+        let dict = String::from_utf8(dict).map_err(|_| Error::Npy("invalid header".to_owned()))?;
+
+        let (data_type, big_endian) = parse_descr(dict_value(&dict, "descr")?.trim_matches('\''))?;
+        let fortran_order = match dict_value(&dict, "fortran_order")? {
+            "True" => true,
+            "False" => false,
+            v => return Err(Error::Npy(format!("invalid fortran_order '{}'", v))),
+        };
+        let dims = parse_shape(dict_value(&dict, "shape")?)?;
+
+        Ok(Header {
+            data_type,
+            big_endian,
+            fortran_order,
+            dims,
+            len: prefix_len + dict_len,
+        })
+    }
+
+    /// Serialize a version 1.0 header describing a C-order, little-endian array
+    fn write<W: Write>(data_type: &DataType, dims: &[u64], writer: &mut W) -> Result<()> {
+        let shape = match dims.len() {
+            1 => format!("({},)", dims[0]),
+            _ => format!(
+                "({})",
+                dims.iter()
+                    .map(|d| d.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        let mut dict = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+            descr(data_type)?,
+            shape
+        );
+
+        // Pad with spaces and terminate with a newline so that the data
+        // starts at an aligned offset
+        let total = MAGIC.len() + 4 + dict.len() + 1;
+        let padding = (HEADER_ALIGN - total % HEADER_ALIGN) % HEADER_ALIGN;
+        dict.push_str(&" ".repeat(padding));
+        dict.push('\n');
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[1, 0])?;
+        writer.write_all(&(dict.len() as u16).to_le_bytes())?;
+        writer.write_all(dict.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+/// Reorder the elements of a column-major buffer to row-major order
+fn fortran_to_c(data: &[u8], dims: &[u64], size: usize) -> Vec<u8> {
+    let c_strides = strides(dims);
+    let mut f_strides = vec![1usize; dims.len()];
+    for i in 1..dims.len() {
+        f_strides[i] = f_strides[i - 1] * dims[i - 1] as usize;
+    }
+
+    let mut out = vec![0u8; data.len()];
+    for (c, chunk) in out.chunks_exact_mut(size).enumerate() {
+        let f: usize = c_strides
+            .iter()
+            .zip(dims)
+            .zip(&f_strides)
+            .map(|((cs, d), fs)| (c / cs) % (*d as usize) * fs)
+            .sum();
+        chunk.copy_from_slice(&data[f * size..(f + 1) * size]);
+    }
+
+    out
+}
+
+/// Read the data following `header` into a new tensor
+fn read_data<T: TensorType, R: Read>(reader: &mut R, header: &Header) -> Result<Tensor<T>> {
+    if header.data_type != T::data_type() {
+        return Err(Error::Npy(format!(
+            "type mismatch: file holds {:?} but tensor expects {:?}",
+            header.data_type,
+            T::data_type()
+        )));
+    }
+
+    let size = std::mem::size_of::<T>();
+    let len = header
+        .dims
+        .iter()
+        .try_fold(size as u64, |len, d| len.checked_mul(*d))
+        .ok_or_else(|| Error::Npy(format!("shape {:?} is too large", header.dims)))?;
+
+    // The buffer grows as data is read, so a corrupt shape cannot make us
+    // allocate more than the file holds
+    let mut data = Vec::new();
+    reader.take(len).read_to_end(&mut data)?;
+    if data.len() as u64 != len {
+        return Err(Error::Npy(format!(
+            "shape {:?} needs {} bytes of data but only {} are present",
+            header.dims,
+            len,
+            data.len()
+        )));
+    }
+
+    if T::data_type() == DataType::Bool && data.iter().any(|b| *b > 1) {
+        return Err(Error::Npy("invalid boolean value".to_owned()));
+    }
+
+    if header.big_endian != cfg!(target_endian = "big") {
+        for e in data.chunks_exact_mut(size) {
+            e.reverse();
+        }
+    }
+
+    if header.fortran_order {
+        data = fortran_to_c(&data, &header.dims, size);
+    }
+
+    let mut tensor = Tensor::<T>::new(&header.dims);
+    unsafe {
+        std::ptr::copy_nonoverlapping(data.as_ptr(), tensor.as_mut_ptr() as *mut u8, data.len());
+    }
+
+    Ok(tensor)
+}
+
+fn read_any<R: Read>(reader: &mut R) -> Result<AnyTensor> {
+    let header = Header::read(reader)?;
+
+    Ok(match header.data_type {
+        DataType::Float => AnyTensor::Float(read_data(reader, &header)?),
+        DataType::Double => AnyTensor::Double(read_data(reader, &header)?),
+        DataType::Int32 => AnyTensor::Int32(read_data(reader, &header)?),
+        DataType::UInt8 => AnyTensor::UInt8(read_data(reader, &header)?),
+        DataType::Int16 => AnyTensor::Int16(read_data(reader, &header)?),
+        DataType::Int8 => AnyTensor::Int8(read_data(reader, &header)?),
+        DataType::Int64 => AnyTensor::Int64(read_data(reader, &header)?),
+        DataType::UInt16 => AnyTensor::UInt16(read_data(reader, &header)?),
+        DataType::UInt32 => AnyTensor::UInt32(read_data(reader, &header)?),
+        DataType::UInt64 => AnyTensor::UInt64(read_data(reader, &header)?),
+        DataType::Bool => AnyTensor::Bool(read_data(reader, &header)?),
+        dt => return Err(Error::Npy(format!("unsupported type {:?}", dt))),
+    })
+}
+
+impl<T: TensorType> Tensor<T> {
+    /// Read a tensor from a `.npy` stream
+    ///
+    /// The element type of the stream must match `T`. Both C and Fortran
+    /// ordered arrays are accepted, the tensor is always in C order.
+    pub fn from_npy<R: Read>(reader: &mut R) -> Result<Self> {
+        let header = Header::read(reader)?;
+        read_data(reader, &header)
+    }
+
+    /// Read a tensor from a `.npy` file
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the `.npy` file
+    pub fn read_npy(path: &Path) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        Self::from_npy(&mut reader)
+    }
+
+    /// Write the tensor to a `.npy` stream
+    pub fn to_npy<W: Write>(&self, writer: &mut W) -> Result<()> {
+        Header::write(&T::data_type(), self.dims(), writer)?;
+
+        let data = unsafe {
+            std::slice::from_raw_parts(
+                self.as_ptr() as *const u8,
+                self.len() * std::mem::size_of::<T>(),
+            )
+        };
+
+        if cfg!(target_endian = "big") {
+            for e in data.chunks_exact(std::mem::size_of::<T>()) {
+                let mut e = e.to_owned();
+                e.reverse();
+                writer.write_all(&e)?;
+            }
+        } else {
+            writer.write_all(data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the tensor to a `.npy` file
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the `.npy` file. It is created if it does not exist
+    /// and truncated if it does.
+    pub fn write_npy(&self, path: &Path) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.to_npy(&mut writer)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+impl AnyTensor {
+    /// Read a tensor of any supported type from a `.npy` stream
+    pub fn from_npy<R: Read>(reader: &mut R) -> Result<Self> {
+        read_any(reader)
+    }
+
+    /// Read a tensor of any supported type from a `.npy` file
+    pub fn read_npy(path: &Path) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        read_any(&mut reader)
+    }
+}
+
+/// Read all the arrays of a `.npz` archive
+///
+/// Both plain (`numpy.savez`) and compressed (`numpy.savez_compressed`)
+/// archives are supported. The returned map is keyed by array name, i.e. the
+/// name of each entry without the `.npy` suffix.
+///
+/// # Arguments
+///
+/// * `path` - The path of the `.npz` file
+pub fn read_npz(path: &Path) -> Result<HashMap<String, AnyTensor>> {
+    let file = BufReader::new(File::open(path)?);
+    let mut archive = zip::ZipArchive::new(file)
+        .map_err(|e| Error::Npy(format!("invalid npz archive: {}", e)))?;
+
+    let mut tensors = HashMap::new();
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index(i)
+            .map_err(|e| Error::Npy(format!("invalid npz archive: {}", e)))?;
+
+        let name = entry
+            .name()
+            .strip_suffix(".npy")
+            .unwrap_or_else(|| entry.name())
+            .to_owned();
+
+        let tensor = read_any(&mut entry).map_err(|e| match e {
+            Error::Npy(msg) => Error::Npy(format!("array '{}': {}", name, msg)),
+            e => e,
+        })?;
+        tensors.insert(name, tensor);
+    }
+
+    Ok(tensors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/tensorflow/testdata")
+            .join(name)
+    }
+
+    fn npy_error<T>(result: Result<T>) -> String {
+        match result {
+            Err(Error::Npy(msg)) => msg,
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("unexpected success"),
+        }
+    }
+
+    #[test]
+    fn read_header() {
+        let data = std::fs::read(fixture("f4_2x3.npy")).unwrap();
+        let header = Header::read(&mut &data[..]).unwrap();
+        assert_eq!(
+            header,
+            Header {
+                data_type: DataType::Float,
+                big_endian: false,
+                fortran_order: false,
+                dims: vec![2, 3],
+                len: 128,
+            }
+        );
+
+        assert_eq!(parse_shape("()").unwrap(), Vec::<u64>::new());
+        assert_eq!(parse_shape("(5,)").unwrap(), &[5]);
+        assert!(parse_shape("(5, x)").is_err());
+        assert!(parse_descr("<c8").is_err());
+        assert!(Header::read(&mut &b"\x93NUMPZ\x01\x00"[..]).is_err());
+    }
+
+    #[test]
+    fn read_c_order() {
+        let tensor = Tensor::<f32>::read_npy(&fixture("f4_2x3.npy")).unwrap();
+        assert_eq!(tensor.dims(), &[2, 3]);
+        assert_eq!(&tensor[..], &[0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+    }
+
+    #[test]
+    fn read_fortran_order() {
+        let tensor = Tensor::<i64>::read_npy(&fixture("i8_fortran_2x3.npy")).unwrap();
+        assert_eq!(tensor.dims(), &[2, 3]);
+        assert_eq!(&tensor[..], &[0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn read_any_type() {
+        match AnyTensor::read_npy(&fixture("u2_big_endian.npy")).unwrap() {
+            AnyTensor::UInt16(t) => assert_eq!(&t[..], &[1, 2, 515]),
+            t => panic!("unexpected type {:?}", t.data_type()),
+        }
+
+        match AnyTensor::read_npy(&fixture("b1.npy")).unwrap() {
+            AnyTensor::Bool(t) => assert_eq!(&t[..], &[true, false, true, true]),
+            t => panic!("unexpected type {:?}", t.data_type()),
+        }
+
+        match AnyTensor::read_npy(&fixture("f8_scalar.npy")).unwrap() {
+            AnyTensor::Double(t) => {
+                assert!(t.dims().is_empty());
+                assert_eq!(&t[..], &[2.5]);
+            }
+            t => panic!("unexpected type {:?}", t.data_type()),
+        }
+    }
+
+    #[test]
+    fn reject_mismatches() {
+        let msg = npy_error(Tensor::<f64>::read_npy(&fixture("f4_2x3.npy")));
+        assert!(msg.contains("type mismatch"), "{}", msg);
+
+        let data = std::fs::read(fixture("f4_2x3.npy")).unwrap();
+        let msg = npy_error(Tensor::<f32>::from_npy(&mut &data[..data.len() - 1]));
+        assert!(msg.contains("needs 24 bytes"), "{}", msg);
+
+        let mut data = std::fs::read(fixture("b1.npy")).unwrap();
+        *data.last_mut().unwrap() = 2;
+        let msg = npy_error(Tensor::<bool>::from_npy(&mut &data[..]));
+        assert!(msg.contains("boolean"), "{}", msg);
+    }
+
+    #[test]
+    fn write_matches_numpy() {
+        let tensor = Tensor::<f32>::new(&[2, 3])
+            .with_data(&[0.0, 1.0, 2.0, 3.0, 4.0, 5.0])
+            .unwrap();
+
+        let mut data = Vec::new();
+        tensor.to_npy(&mut data).unwrap();
+        assert_eq!(data, std::fs::read(fixture("f4_2x3.npy")).unwrap());
+
+        let tensor = Tensor::<bool>::new(&[4])
+            .with_data(&[true, false, true, true])
+            .unwrap();
+        let mut data = Vec::new();
+        tensor.to_npy(&mut data).unwrap();
+        assert_eq!(data, std::fs::read(fixture("b1.npy")).unwrap());
+    }
+
+    #[test]
+    fn read_npz_archives() {
+        for name in ["arrays.npz", "arrays_compressed.npz"] {
+            let tensors = read_npz(&fixture(name)).unwrap();
+            assert_eq!(tensors.len(), 2);
+
+            match &tensors["x"] {
+                AnyTensor::Float(t) => {
+                    assert_eq!(t.dims(), &[2, 2]);
+                    assert_eq!(&t[..], &[0.5, 1.5, 2.5, 3.5]);
+                }
+                t => panic!("unexpected type {:?}", t.data_type()),
+            }
+            match &tensors["y"] {
+                AnyTensor::UInt8(t) => assert_eq!(&t[..], &[7, 8, 9]),
+                t => panic!("unexpected type {:?}", t.data_type()),
+            }
+        }
+
+        let msg = npy_error(read_npz(&fixture("f4_2x3.npy")));
+        assert!(msg.contains("invalid npz archive"), "{}", msg);
+    }
+}
@@ -0,0 +1,213 @@
+// Minimal decoders for the TensorFlow protobuf messages we need to inspect.
+//
+// TensorFlow does not ship Rust bindings for its protobuf definitions, so we
+// decode the handful of messages (and fields) we care about by hand. Field
+// numbers follow the definitions under `tensorflow/core/protobuf` and
+// `tensorflow/core/framework`. Unknown fields are skipped.
+
+use crate::Result;
+
+use protobuf::wire_format::WireType;
+use protobuf::CodedInputStream;
+
+/// Call `f` with the number and wire type of each field of a message
+///
+/// `f` must consume the value of the field from the stream, e.g. by calling
+/// `skip_field` for fields it does not care about.
+pub(crate) fn parse_fields<F>(data: &[u8], mut f: F) -> Result<()>
+where
+    F: FnMut(&mut CodedInputStream, u32, WireType) -> Result<()>,
+{
+    let mut is = CodedInputStream::from_bytes(data);
+
+    while !is.eof()? {
+        let (field, wire_type) = is.read_tag_unpack()?;
+        f(&mut is, field, wire_type)?;
+    }
+
+    Ok(())
+}
+
+/// Parse an entry of a `map<string, Message>` field
+pub(crate) fn parse_map_entry(data: &[u8]) -> Result<(String, Vec<u8>)> {
+    let mut key = String::new();
+    let mut value = Vec::new();
+
+    parse_fields(data, |is, field, wire_type| {
+        match (field, wire_type) {
+            (1, WireType::WireTypeLengthDelimited) => key = is.read_string()?,
+            (2, WireType::WireTypeLengthDelimited) => value = is.read_bytes()?,
+            (_, wt) => is.skip_field(wt)?,
+        }
+        Ok(())
+    })?;
+
+    Ok((key, value))
+}
+
+/// `tensorflow.TensorShapeProto`
+#[derive(Debug, Default)]
+pub(crate) struct TensorShapeProto {
+    pub dims: Vec<i64>,
+    pub unknown_rank: bool,
+}
+
+impl TensorShapeProto {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut shape = TensorShapeProto::default();
+
+        parse_fields(data, |is, field, wire_type| {
+            match (field, wire_type) {
+                (2, WireType::WireTypeLengthDelimited) => {
+                    // TensorShapeProto.Dim
+                    let mut size = 0;
+                    parse_fields(&is.read_bytes()?, |is, field, wire_type| {
+                        match (field, wire_type) {
+                            (1, WireType::WireTypeVarint) => size = is.read_int64()?,
+                            (_, wt) => is.skip_field(wt)?,
+                        }
+                        Ok(())
+                    })?;
+                    shape.dims.push(size);
+                }
+                (3, WireType::WireTypeVarint) => shape.unknown_rank = is.read_bool()?,
+                (_, wt) => is.skip_field(wt)?,
+            }
+            Ok(())
+        })?;
+
+        Ok(shape)
+    }
+}
+
+/// `tensorflow.TensorInfo`
+#[derive(Debug, Default)]
+pub(crate) struct TensorInfoProto {
+    pub name: String,
+    pub dtype: i32,
+    pub shape: Option<TensorShapeProto>,
+}
+
+impl TensorInfoProto {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut info = TensorInfoProto::default();
+
+        parse_fields(data, |is, field, wire_type| {
+            match (field, wire_type) {
+                (1, WireType::WireTypeLengthDelimited) => info.name = is.read_string()?,
+                (2, WireType::WireTypeVarint) => info.dtype = is.read_int32()?,
+                (3, WireType::WireTypeLengthDelimited) => {
+                    info.shape = Some(TensorShapeProto::parse(&is.read_bytes()?)?)
+                }
+                (_, wt) => is.skip_field(wt)?,
+            }
+            Ok(())
+        })?;
+
+        Ok(info)
+    }
+}
+
+/// `tensorflow.SignatureDef`
+#[derive(Debug, Default)]
+pub(crate) struct SignatureDefProto {
+    pub inputs: Vec<(String, TensorInfoProto)>,
+    pub outputs: Vec<(String, TensorInfoProto)>,
+    pub method_name: String,
+}
+
+impl SignatureDefProto {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut signature = SignatureDefProto::default();
+
+        parse_fields(data, |is, field, wire_type| {
+            match (field, wire_type) {
+                (1, WireType::WireTypeLengthDelimited) => {
+                    let (key, value) = parse_map_entry(&is.read_bytes()?)?;
+                    signature
+                        .inputs
+                        .push((key, TensorInfoProto::parse(&value)?));
+                }
+                (2, WireType::WireTypeLengthDelimited) => {
+                    let (key, value) = parse_map_entry(&is.read_bytes()?)?;
+                    signature
+                        .outputs
+                        .push((key, TensorInfoProto::parse(&value)?));
+                }
+                (3, WireType::WireTypeLengthDelimited) => {
+                    signature.method_name = is.read_string()?
+                }
+                (_, wt) => is.skip_field(wt)?,
+            }
+            Ok(())
+        })?;
+
+        Ok(signature)
+    }
+}
+
+/// `tensorflow.MetaGraphDef`
+///
+/// The graph itself is skipped, we only keep the tags and signatures.
+#[derive(Debug, Default)]
+pub(crate) struct MetaGraphDefProto {
+    pub tags: Vec<String>,
+    pub signature_defs: Vec<(String, SignatureDefProto)>,
+}
+
+impl MetaGraphDefProto {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut meta_graph = MetaGraphDefProto::default();
+
+        parse_fields(data, |is, field, wire_type| {
+            match (field, wire_type) {
+                (1, WireType::WireTypeLengthDelimited) => {
+                    // MetaGraphDef.MetaInfoDef
+                    parse_fields(&is.read_bytes()?, |is, field, wire_type| {
+                        match (field, wire_type) {
+                            (4, WireType::WireTypeLengthDelimited) => {
+                                meta_graph.tags.push(is.read_string()?)
+                            }
+                            (_, wt) => is.skip_field(wt)?,
+                        }
+                        Ok(())
+                    })?;
+                }
+                (5, WireType::WireTypeLengthDelimited) => {
+                    let (key, value) = parse_map_entry(&is.read_bytes()?)?;
+                    meta_graph
+                        .signature_defs
+                        .push((key, SignatureDefProto::parse(&value)?));
+                }
+                (_, wt) => is.skip_field(wt)?,
+            }
+            Ok(())
+        })?;
+
+        Ok(meta_graph)
+    }
+}
+
+/// `tensorflow.SavedModel`
+#[derive(Debug, Default)]
+pub(crate) struct SavedModelProto {
+    pub meta_graphs: Vec<MetaGraphDefProto>,
+}
+
+impl SavedModelProto {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut saved_model = SavedModelProto::default();
+
+        parse_fields(data, |is, field, wire_type| {
+            match (field, wire_type) {
+                (2, WireType::WireTypeLengthDelimited) => saved_model
+                    .meta_graphs
+                    .push(MetaGraphDefProto::parse(&is.read_bytes()?)?),
+                (_, wt) => is.skip_field(wt)?,
+            }
+            Ok(())
+        })?;
+
+        Ok(saved_model)
+    }
+}
@@ -0,0 +1,200 @@
+use crate::tensorflow::proto::parse_fields;
+use crate::Result;
+
+use protobuf::wire_format::WireType;
+
+/// Execution statistics of a graph node
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NodeExecStats {
+    pub node_name: String,
+    pub all_start_micros: i64,
+    pub op_start_rel_micros: i64,
+    pub op_end_rel_micros: i64,
+    pub all_end_rel_micros: i64,
+    pub timeline_label: String,
+    pub scheduled_micros: i64,
+    pub thread_id: u32,
+}
+
+impl NodeExecStats {
+    fn parse(data: &[u8]) -> Result<Self> {
+        let mut stats = NodeExecStats::default();
+
+        parse_fields(data, |is, field, wire_type| {
+            match (field, wire_type) {
+                (1, WireType::WireTypeLengthDelimited) => stats.node_name = is.read_string()?,
+                (2, WireType::WireTypeVarint) => stats.all_start_micros = is.read_int64()?,
+                (3, WireType::WireTypeVarint) => stats.op_start_rel_micros = is.read_int64()?,
+                (4, WireType::WireTypeVarint) => stats.op_end_rel_micros = is.read_int64()?,
+                (5, WireType::WireTypeVarint) => stats.all_end_rel_micros = is.read_int64()?,
+                (8, WireType::WireTypeLengthDelimited) => {
+                    stats.timeline_label = is.read_string()?
+                }
+                (9, WireType::WireTypeVarint) => stats.scheduled_micros = is.read_int64()?,
+                (10, WireType::WireTypeVarint) => stats.thread_id = is.read_uint32()?,
+                (_, wt) => is.skip_field(wt)?,
+            }
+            Ok(())
+        })?;
+
+        Ok(stats)
+    }
+
+    /// Time spent running the node's operation, in microseconds
+    pub fn op_micros(&self) -> i64 {
+        self.op_end_rel_micros - self.op_start_rel_micros
+    }
+
+    /// Total time spent on the node, in microseconds
+    pub fn all_micros(&self) -> i64 {
+        self.all_end_rel_micros
+    }
+}
+
+/// Execution statistics of the nodes that ran on a device
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceStepStats {
+    pub device: String,
+    pub node_stats: Vec<NodeExecStats>,
+}
+
+impl DeviceStepStats {
+    fn parse(data: &[u8]) -> Result<Self> {
+        let mut stats = DeviceStepStats::default();
+
+        parse_fields(data, |is, field, wire_type| {
+            match (field, wire_type) {
+                (1, WireType::WireTypeLengthDelimited) => stats.device = is.read_string()?,
+                (2, WireType::WireTypeLengthDelimited) => stats
+                    .node_stats
+                    .push(NodeExecStats::parse(&is.read_bytes()?)?),
+                (_, wt) => is.skip_field(wt)?,
+            }
+            Ok(())
+        })?;
+
+        Ok(stats)
+    }
+}
+
+/// Metadata of a TensorFlow session run
+///
+/// This is decoded from a `tensorflow.RunMetadata` protobuf message, as
+/// produced by runs with a `TraceLevel` other than `NoTrace`. Only the step
+/// statistics are decoded.
+///
+/// The vAccel runtime does not return the metadata of the runs it performs,
+/// so `InferenceResult::run_metadata` always fails. Metadata collected by
+/// other means, e.g. traces written by the backend, can be parsed here.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunMetadata {
+    pub step_stats: Vec<DeviceStepStats>,
+}
+
+impl RunMetadata {
+    /// Parse a serialized `tensorflow.RunMetadata` message
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The serialized message, e.g. the contents of a `tf::Buffer`
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut metadata = RunMetadata::default();
+
+        parse_fields(data, |is, field, wire_type| {
+            match (field, wire_type) {
+                (1, WireType::WireTypeLengthDelimited) => {
+                    // StepStats
+                    parse_fields(&is.read_bytes()?, |is, field, wire_type| {
+                        match (field, wire_type) {
+                            (1, WireType::WireTypeLengthDelimited) => metadata
+                                .step_stats
+                                .push(DeviceStepStats::parse(&is.read_bytes()?)?),
+                            (_, wt) => is.skip_field(wt)?,
+                        }
+                        Ok(())
+                    })?;
+                }
+                (_, wt) => is.skip_field(wt)?,
+            }
+            Ok(())
+        })?;
+
+        Ok(metadata)
+    }
+
+    /// Iterate over the statistics of all nodes, on all devices
+    pub fn node_stats(&self) -> impl Iterator<Item = &NodeExecStats> {
+        self.step_stats.iter().flat_map(|d| d.node_stats.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+    use std::path::Path;
+
+    const T0: i64 = 1_700_000_000_000_000;
+
+    #[test]
+    fn parse_run_metadata() {
+        let data = fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("src/tensorflow/testdata/run_metadata.pb"),
+        )
+        .unwrap();
+        let metadata = RunMetadata::parse(&data).unwrap();
+
+        let devices: Vec<_> = metadata
+            .step_stats
+            .iter()
+            .map(|d| d.device.as_str())
+            .collect();
+        assert_eq!(
+            devices,
+            [
+                "/job:localhost/replica:0/task:0/device:CPU:0",
+                "/job:localhost/replica:0/task:0/device:GPU:0"
+            ]
+        );
+
+        let nodes: Vec<_> = metadata
+            .node_stats()
+            .map(|n| n.node_name.as_str())
+            .collect();
+        assert_eq!(nodes, ["_SOURCE", "MatMul", "MatMul/_1"]);
+
+        // Memory, outputs and nanosecond timings are skipped
+        let matmul = &metadata.step_stats[0].node_stats[1];
+        assert_eq!(
+            *matmul,
+            NodeExecStats {
+                node_name: "MatMul".to_string(),
+                all_start_micros: T0 + 10,
+                op_start_rel_micros: 1,
+                op_end_rel_micros: 41,
+                all_end_rel_micros: 45,
+                timeline_label: "MatMul = MatMul(a, b)".to_string(),
+                scheduled_micros: T0 + 6,
+                thread_id: 7,
+            }
+        );
+        assert_eq!(matmul.op_micros(), 40);
+        assert_eq!(matmul.all_micros(), 45);
+    }
+
+    #[test]
+    fn parse_empty_run_metadata() {
+        assert_eq!(RunMetadata::parse(&[]).unwrap(), RunMetadata::default());
+    }
+
+    #[test]
+    fn reject_truncated_run_metadata() {
+        let data = fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("src/tensorflow/testdata/run_metadata.pb"),
+        )
+        .unwrap();
+
+        assert!(RunMetadata::parse(&data[..data.len() / 2]).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use crate::tensorflow::Buffer;
+
+use protobuf::CodedOutputStream;
+
+/// Amount of tracing to perform during a run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceLevel {
+    NoTrace = 0,
+    SoftwareTrace = 1,
+    HardwareTrace = 2,
+    FullTrace = 3,
+}
+
+/// Options of a TensorFlow session run
+///
+/// This is a builder for the `tensorflow.RunOptions` protobuf message, which
+/// is passed to the runtime through `InferenceArgs::set_run_options`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunOptions {
+    trace_level: TraceLevel,
+    timeout_in_ms: i64,
+    inter_op_thread_pool: i32,
+    report_tensor_allocations_upon_oom: bool,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            trace_level: TraceLevel::NoTrace,
+            timeout_in_ms: 0,
+            inter_op_thread_pool: 0,
+            report_tensor_allocations_upon_oom: false,
+        }
+    }
+}
+
+impl RunOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the amount of tracing to perform
+    ///
+    /// The runtime does not return the run metadata the traces end up in, see
+    /// `RunMetadata`.
+    pub fn with_trace_level(mut self, trace_level: TraceLevel) -> Self {
+        self.trace_level = trace_level;
+        self
+    }
+
+    /// Set the time after which the run is cancelled, `0` for no timeout
+    pub fn with_timeout_in_ms(mut self, timeout_in_ms: i64) -> Self {
+        self.timeout_in_ms = timeout_in_ms;
+        self
+    }
+
+    /// Set the inter-op thread pool of the TensorFlow session to use
+    pub fn with_inter_op_thread_pool(mut self, inter_op_thread_pool: i32) -> Self {
+        self.inter_op_thread_pool = inter_op_thread_pool;
+        self
+    }
+
+    /// Report the allocated tensors if the run runs out of memory
+    pub fn with_report_tensor_allocations_upon_oom(mut self, report: bool) -> Self {
+        self.report_tensor_allocations_upon_oom = report;
+        self
+    }
+
+    pub fn trace_level(&self) -> TraceLevel {
+        self.trace_level
+    }
+
+    pub fn timeout_in_ms(&self) -> i64 {
+        self.timeout_in_ms
+    }
+
+    pub fn inter_op_thread_pool(&self) -> i32 {
+        self.inter_op_thread_pool
+    }
+
+    pub fn report_tensor_allocations_upon_oom(&self) -> bool {
+        self.report_tensor_allocations_upon_oom
+    }
+
+    /// Serialize the options in a `tensorflow.RunOptions` protobuf message
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        {
+            let mut os = CodedOutputStream::vec(&mut data);
+
+            // Writing to a vector cannot fail. Fields with default values
+            // are omitted, like protobuf encoders do.
+            if self.trace_level != TraceLevel::NoTrace {
+                os.write_enum(1, self.trace_level as i32).unwrap();
+            }
+            if self.timeout_in_ms != 0 {
+                os.write_int64(2, self.timeout_in_ms).unwrap();
+            }
+            if self.inter_op_thread_pool != 0 {
+                os.write_int32(3, self.inter_op_thread_pool).unwrap();
+            }
+            if self.report_tensor_allocations_upon_oom {
+                os.write_bool(7, true).unwrap();
+            }
+            os.flush().unwrap();
+        }
+
+        data
+    }
+
+    /// Serialize the options in a buffer, ready for `InferenceArgs::set_run_options`
+    pub fn to_buffer(&self) -> Buffer {
+        Buffer::from_vec(self.to_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tensorflow::proto::parse_fields;
+
+    use protobuf::wire_format::WireType;
+
+    fn decode(data: &[u8]) -> Vec<(u32, i64)> {
+        let mut fields = Vec::new();
+        parse_fields(data, |is, field, wire_type| {
+            assert_eq!(wire_type, WireType::WireTypeVarint);
+            fields.push((field, is.read_int64()?));
+            Ok(())
+        })
+        .unwrap();
+
+        fields
+    }
+
+    #[test]
+    fn default_options_are_empty() {
+        assert!(RunOptions::new().to_bytes().is_empty());
+        assert!(RunOptions::new().to_buffer().as_slice().is_empty());
+    }
+
+    #[test]
+    fn encode_run_options() {
+        let opts = RunOptions::new()
+            .with_trace_level(TraceLevel::FullTrace)
+            .with_timeout_in_ms(1500)
+            .with_inter_op_thread_pool(-1)
+            .with_report_tensor_allocations_upon_oom(true);
+
+        assert_eq!(
+            decode(&opts.to_bytes()),
+            [(1, 3), (2, 1500), (3, -1), (7, 1)]
+        );
+        assert_eq!(opts.to_buffer().as_slice(), &opts.to_bytes()[..]);
+
+        let opts = RunOptions::new().with_timeout_in_ms(20);
+        assert_eq!(opts.to_bytes(), [0x10, 20]);
+    }
+}
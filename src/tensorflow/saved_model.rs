@@ -1,13 +1,25 @@
 use crate::ffi;
+use crate::tensorflow::checkpoint;
+use crate::tensorflow::Signature;
 use crate::VaccelId;
 use crate::{Error, Result};
+
+use protocols::resources::{CreateResourceRequest, CreateTensorflowSavedModelRequest};
 use std::any::Any;
+use std::convert::TryFrom;
 use std::ffi::{CStr, CString};
+use std::fs;
 use std::path::{Path, PathBuf};
 
 #[derive(Debug, PartialEq)]
 pub struct SavedModel {
     inner: *mut ffi::vaccel_tf_saved_model,
+
+    // Signatures of the model, parsed on first use
+    pub(crate) signatures: Option<Vec<Signature>>,
+
+    // In-memory data owned by the model, which the runtime points to
+    buffers: Vec<Vec<u8>>,
 }
 
 impl SavedModel {
@@ -15,12 +27,18 @@ impl SavedModel {
     pub fn new() -> Self {
         SavedModel {
             inner: unsafe { ffi::vaccel_tf_saved_model_new() },
+            signatures: None,
+            buffers: Vec::new(),
         }
     }
 
     /// Create a new SavedModel from a vaccel saved model type
     pub fn from_vaccel(inner: *mut ffi::vaccel_tf_saved_model) -> Self {
-        SavedModel { inner }
+        SavedModel {
+            inner,
+            signatures: None,
+            buffers: Vec::new(),
+        }
     }
 
     /// Get the id of the model
@@ -120,6 +138,93 @@ impl SavedModel {
         }
     }
 
+    /// Create the resource from in-memory data with variables split in shards
+    ///
+    /// The runtime accepts a single checkpoint, so multiple shards are merged
+    /// in a new in-memory checkpoint, which is kept alive by the model. That
+    /// is a copy of all the shards, so while the caller holds on to them, the
+    /// variables take twice their size in memory. `from_export_dir_in_memory`
+    /// reads the shards of an export directory without copying them.
+    ///
+    /// # Arguments
+    ///
+    /// * `protobuf` - The contents of `saved_model.pb`
+    /// * `shards` - The contents of the `variables.data-*-of-*` files, in shard order
+    /// * `variable_index` - The contents of `variables.index`
+    pub fn from_in_memory_shards(
+        self,
+        protobuf: &[u8],
+        shards: &[&[u8]],
+        variable_index: &[u8],
+    ) -> Result<Self> {
+        match shards {
+            [] => Err(Error::InvalidArgument),
+            [checkpoint] => self.from_in_memory(protobuf, checkpoint, variable_index),
+            _ => {
+                let (var_index, checkpoint) = checkpoint::merge_shards(variable_index, shards)?;
+                self.from_owned_data(protobuf.to_vec(), var_index, vec![checkpoint])
+            }
+        }
+    }
+
+    /// Create the resource by reading an export directory in memory
+    ///
+    /// All the variables shards of the directory are loaded. Loading fails if
+    /// any of them is missing. Shards are read one after the other in a single
+    /// buffer, so the variables are held in memory only once.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path in the filesystem to the export directory
+    pub fn from_export_dir_in_memory(self, path: &Path) -> Result<Self> {
+        let (protobuf, var_index, checkpoint) = Self::read_export_dir(path)?;
+        self.from_owned_data(protobuf, var_index, vec![checkpoint])
+    }
+
+    /// Read the `saved_model.pb`, variables index and variables data of an
+    /// export directory, merging the data shards if there are several
+    fn read_export_dir(path: &Path) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+        let protobuf = fs::read(path.join("saved_model.pb"))?;
+        let var_index = fs::read(path.join("variables").join("variables.index"))?;
+        let shards = Self::variable_shards(path)?;
+        let (var_index, checkpoint) = checkpoint::read_shards(var_index, &shards)?;
+
+        Ok((protobuf, var_index, checkpoint))
+    }
+
+    /// Create the resource from in-memory data that the model keeps alive
+    ///
+    /// `shards` must be in shard order.
+    pub(crate) fn from_owned_data(
+        mut self,
+        protobuf: Vec<u8>,
+        var_index: Vec<u8>,
+        shards: Vec<Vec<u8>>,
+    ) -> Result<Self> {
+        let (var_index, checkpoint) = checkpoint::single_shard(var_index, shards)?;
+
+        self = self.from_in_memory(&protobuf, &checkpoint, &var_index)?;
+
+        // Moving the vectors does not move their data
+        self.buffers.push(protobuf);
+        self.buffers.push(checkpoint);
+        self.buffers.push(var_index);
+
+        Ok(self)
+    }
+
+    /// Get the paths of the variables data shards of an export directory
+    ///
+    /// The paths are returned in shard order. This fails if the set of
+    /// `variables.data-NNNNN-of-MMMMM` files is not complete.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path in the filesystem to the export directory
+    pub fn variable_shards(path: &Path) -> Result<Vec<PathBuf>> {
+        checkpoint::shard_files(&path.join("variables"))
+    }
+
     pub(crate) fn inner(&self) -> *const ffi::vaccel_tf_saved_model {
         self.inner
     }
@@ -130,9 +235,12 @@ impl SavedModel {
 
     /// Get the path of the export directory if it exists
     pub fn get_path(&self) -> Option<PathBuf> {
-        let path_str = match unsafe {
-            CStr::from_ptr(ffi::vaccel_tf_saved_model_get_path(self.inner)).to_str()
-        } {
+        let path = unsafe { ffi::vaccel_tf_saved_model_get_path(self.inner) };
+        if path.is_null() {
+            return None;
+        }
+
+        let path_str = match unsafe { CStr::from_ptr(path).to_str() } {
             Ok(s) => s,
             Err(_) => return None,
         };
@@ -216,3 +324,81 @@ impl crate::resource::Resource for SavedModel {
         self
     }
 }
+
+/// Convert a `SavedModel` to the request creating it on a vAccel agent
+///
+/// The request carries the in-memory data of the model. Models created from
+/// an export directory are read from it, merging their variables shards.
+/// Fails with `Error::Uninitialized` if the model has neither.
+impl TryFrom<&SavedModel> for CreateResourceRequest {
+    type Error = Error;
+
+    fn try_from(model: &SavedModel) -> Result<Self> {
+        let (model_pb, checkpoint, var_index) = match (
+            model.get_protobuf(),
+            model.get_checkpoint(),
+            model.get_var_index(),
+        ) {
+            (Some(protobuf), Some(checkpoint), Some(var_index)) => {
+                (protobuf.to_vec(), checkpoint.to_vec(), var_index.to_vec())
+            }
+            _ => {
+                let path = model.get_path().ok_or(Error::Uninitialized)?;
+                let (protobuf, var_index, checkpoint) = SavedModel::read_export_dir(&path)?;
+                (protobuf, checkpoint, var_index)
+            }
+        };
+
+        let mut req = CreateResourceRequest::new();
+        req.set_tf_saved(CreateTensorflowSavedModelRequest {
+            model_pb,
+            checkpoint,
+            var_index,
+            ..Default::default()
+        });
+
+        Ok(req)
+    }
+}
+
+/// Create a `SavedModel` from the request of a vAccel agent client
+///
+/// The data of the request is copied to buffers owned by the model. Fails
+/// with `Error::InvalidArgument` if the request is not for a SavedModel.
+impl TryFrom<&CreateResourceRequest> for SavedModel {
+    type Error = Error;
+
+    fn try_from(req: &CreateResourceRequest) -> Result<Self> {
+        if !req.has_tf_saved() {
+            return Err(Error::InvalidArgument);
+        }
+
+        let model = req.get_tf_saved();
+        SavedModel::new().from_owned_data(
+            model.get_model_pb().to_vec(),
+            model.get_var_index().to_vec(),
+            vec![model.get_checkpoint().to_vec()],
+        )
+    }
+}
+
+/// Create a `SavedModel` from the request of a vAccel agent client
+///
+/// Unlike the conversion from a reference, the data of the request is moved
+/// to the model.
+impl TryFrom<CreateResourceRequest> for SavedModel {
+    type Error = Error;
+
+    fn try_from(mut req: CreateResourceRequest) -> Result<Self> {
+        if !req.has_tf_saved() {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut model = req.take_tf_saved();
+        SavedModel::new().from_owned_data(
+            model.take_model_pb(),
+            model.take_var_index(),
+            vec![model.take_checkpoint()],
+        )
+    }
+}
@@ -0,0 +1,389 @@
+use crate::ffi;
+use crate::tensorflow::proto::{SavedModelProto, TensorInfoProto};
+use crate::tensorflow::{Code, DataType, Node, SavedModel};
+use crate::{Error, Result};
+
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::fs;
+
+/// Description of an input or output tensor of a signature
+#[derive(Debug, Clone, PartialEq)]
+pub struct TensorInfo {
+    /// Name of the tensor in the graph, e.g. `serving_default_input_1:0`
+    pub name: String,
+
+    /// Type of the tensor elements
+    pub data_type: DataType,
+
+    /// Dimensions of the tensor, `-1` for dimensions of unknown size.
+    /// `None` if the rank of the tensor is unknown.
+    pub shape: Option<Vec<i64>>,
+}
+
+impl TensorInfo {
+    fn from_proto(info: TensorInfoProto) -> Self {
+        let shape = match info.shape {
+            Some(shape) if !shape.unknown_rank => Some(shape.dims),
+            _ => None,
+        };
+
+        TensorInfo {
+            name: info.name,
+            data_type: DataType::from_int(info.dtype as u32),
+            shape,
+        }
+    }
+
+    /// Name of the graph node producing the tensor
+    pub fn node_name(&self) -> &str {
+        match self.name.rsplit_once(':') {
+            Some((name, _)) => name,
+            None => &self.name,
+        }
+    }
+
+    /// Index of the tensor among the outputs of its node
+    pub fn node_index(&self) -> i64 {
+        match self.name.rsplit_once(':') {
+            Some((_, idx)) => idx.parse().unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    /// Create the `Node` to use when passing this tensor to `InferenceArgs`
+    pub fn node(&self) -> Node {
+        Node::new(self.node_name(), self.node_index())
+    }
+
+    /// Check whether a tensor with the given type and dimensions matches
+    ///
+    /// Dimensions declared as `-1` match any size. If the signature does not
+    /// declare the type or the rank of the tensor, any type or rank matches.
+    pub fn is_compatible(&self, data_type: DataType, dims: &[u64]) -> bool {
+        if self.data_type != DataType::UnknownValue(0) && self.data_type != data_type {
+            return false;
+        }
+
+        match &self.shape {
+            Some(shape) => {
+                shape.len() == dims.len()
+                    && shape
+                        .iter()
+                        .zip(dims)
+                        .all(|(s, d)| *s < 0 || *s as u64 == *d)
+            }
+            None => true,
+        }
+    }
+
+    fn spec(&self) -> String {
+        match &self.shape {
+            Some(shape) => format!("{:?}{:?}", self.data_type, shape),
+            None => format!("{:?}[..]", self.data_type),
+        }
+    }
+}
+
+/// A `SignatureDef` of a SavedModel
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    /// Name of the signature, e.g. `serving_default`
+    pub name: String,
+
+    /// Tags of the MetaGraph the signature belongs to, e.g. `serve`
+    pub tags: Vec<String>,
+
+    /// Method of the signature, e.g. `tensorflow/serving/predict`
+    pub method_name: String,
+
+    /// Inputs of the signature, keyed by their logical name
+    pub inputs: BTreeMap<String, TensorInfo>,
+
+    /// Outputs of the signature, keyed by their logical name
+    pub outputs: BTreeMap<String, TensorInfo>,
+}
+
+/// Parse the signatures of all the MetaGraphs in a `saved_model.pb`
+pub(crate) fn parse_signatures(data: &[u8]) -> Result<Vec<Signature>> {
+    let saved_model = SavedModelProto::parse(data)?;
+
+    let mut signatures = Vec::new();
+    for meta_graph in saved_model.meta_graphs {
+        for (name, def) in meta_graph.signature_defs {
+            signatures.push(Signature {
+                name,
+                tags: meta_graph.tags.clone(),
+                method_name: def.method_name,
+                inputs: def
+                    .inputs
+                    .into_iter()
+                    .map(|(k, v)| (k, TensorInfo::from_proto(v)))
+                    .collect(),
+                outputs: def
+                    .outputs
+                    .into_iter()
+                    .map(|(k, v)| (k, TensorInfo::from_proto(v)))
+                    .collect(),
+            });
+        }
+    }
+
+    signatures.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(signatures)
+}
+
+impl SavedModel {
+    /// Get the signatures of the model
+    ///
+    /// This parses the `saved_model.pb` of the model, using the in-memory
+    /// data if the model was created from memory, otherwise the file in the
+    /// export directory. Signatures are sorted by name.
+    pub fn signatures(&self) -> Result<Vec<Signature>> {
+        if let Some(signatures) = &self.signatures {
+            return Ok(signatures.clone());
+        }
+
+        if let Some(data) = self.get_protobuf() {
+            return parse_signatures(data);
+        }
+
+        let path = self.get_path().ok_or(Error::Uninitialized)?;
+        parse_signatures(&fs::read(path.join("saved_model.pb"))?)
+    }
+
+    /// Get a signature of the model by name
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the signature, e.g. `serving_default`
+    pub fn signature(&self, name: &str) -> Result<Signature> {
+        self.signatures()?
+            .into_iter()
+            .find(|s| s.name == name)
+            .ok_or(Error::TensorFlow(Code::NotFound))
+    }
+}
+
+impl SavedModel {
+    /// Check the input tensors of a session run against the model signatures
+    ///
+    /// Inputs are matched to signature inputs by node name and index. Inputs
+    /// that are not part of any signature, as well as models without
+    /// signatures, are not checked. The signatures are parsed on the first
+    /// run. If that fails, the error is returned and parsing is retried on the
+    /// next run.
+    pub(crate) fn validate_inputs(
+        &mut self,
+        nodes: &[ffi::vaccel_tf_node],
+        tensors: &[*const ffi::vaccel_tf_tensor],
+    ) -> Result<()> {
+        if self.signatures.is_none() {
+            self.signatures = Some(self.signatures()?);
+        }
+        let signatures = self.signatures.as_deref().unwrap_or_default();
+
+        for (node, tensor) in nodes.iter().zip(tensors) {
+            if node.name.is_null() || tensor.is_null() {
+                continue;
+            }
+
+            let name = unsafe { CStr::from_ptr(node.name) }.to_str().unwrap_or("");
+            let infos: Vec<&TensorInfo> = signatures
+                .iter()
+                .flat_map(|s| s.inputs.values())
+                .filter(|i| i.node_name() == name && i.node_index() == node.id)
+                .collect();
+            if infos.is_empty() {
+                continue;
+            }
+
+            let (data_type, dims) = unsafe {
+                let tensor = &**tensor;
+                let dims: &[u64] = if tensor.nr_dims <= 0 || tensor.dims.is_null() {
+                    // Scalars may have no dimensions array at all
+                    &[]
+                } else {
+                    std::slice::from_raw_parts(tensor.dims as *const u64, tensor.nr_dims as usize)
+                };
+
+                (DataType::from_int(tensor.data_type), dims)
+            };
+
+            if !infos.iter().any(|i| i.is_compatible(data_type, dims)) {
+                return Err(Error::InputMismatch {
+                    node: format!("{}:{}", name, node.id),
+                    expected: infos[0].spec(),
+                    actual: format!("{:?}{:?}", data_type, dims),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tensorflow::{Tensor, TensorAny};
+
+    fn info(name: &str, data_type: DataType, shape: Option<Vec<i64>>) -> TensorInfo {
+        TensorInfo {
+            name: name.to_owned(),
+            data_type,
+            shape,
+        }
+    }
+
+    /// A model whose signatures take a `[-1, 30]` float input
+    fn model() -> SavedModel {
+        let data = fs::read(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/files/tf/lstm2/saved_model.pb"
+        ))
+        .unwrap();
+
+        let mut model = SavedModel::new();
+        model.signatures = Some(parse_signatures(&data).unwrap());
+        model
+    }
+
+    fn validate(
+        model: &mut SavedModel,
+        name: &str,
+        id: i64,
+        tensor: *const ffi::vaccel_tf_tensor,
+    ) -> Result<()> {
+        let node = Node::new(name, id);
+        model.validate_inputs(&[unsafe { *node.inner() }], &[tensor])
+    }
+
+    #[test]
+    fn parse_saved_model_signatures() {
+        let signatures = model().signatures().unwrap();
+        let names: Vec<&str> = signatures.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, &["__saved_model_init_op", "serving_default"]);
+
+        let signature = &signatures[1];
+        assert_eq!(signature.tags, &["serve"]);
+        assert_eq!(signature.method_name, "tensorflow/serving/predict");
+        assert_eq!(
+            signature.inputs["input_1"],
+            info(
+                "serving_default_input_1:0",
+                DataType::Float,
+                Some(vec![-1, 30])
+            )
+        );
+        assert_eq!(
+            signature.outputs["activation"],
+            info(
+                "StatefulPartitionedCall:0",
+                DataType::Float,
+                Some(vec![-1, 30, 61])
+            )
+        );
+
+        // Signatures without a declared type or shape
+        let init = &signatures[0].outputs["__saved_model_init_op"];
+        assert_eq!(init.data_type, DataType::UnknownValue(0));
+        assert_eq!(init.shape, None);
+    }
+
+    #[test]
+    fn tensor_node_names() {
+        let output = info("StatefulPartitionedCall:1", DataType::Float, None);
+        assert_eq!(output.node_name(), "StatefulPartitionedCall");
+        assert_eq!(output.node_index(), 1);
+
+        let bare = info("input", DataType::Float, None);
+        assert_eq!(bare.node_name(), "input");
+        assert_eq!(bare.node_index(), 0);
+    }
+
+    #[test]
+    fn compatible_tensors() {
+        let input = info("x:0", DataType::Float, Some(vec![-1, 30]));
+        assert!(input.is_compatible(DataType::Float, &[1, 30]));
+        assert!(input.is_compatible(DataType::Float, &[64, 30]));
+        assert!(!input.is_compatible(DataType::Float, &[1, 31]));
+        assert!(!input.is_compatible(DataType::Float, &[30]));
+        assert!(!input.is_compatible(DataType::Float, &[1, 30, 1]));
+        assert!(!input.is_compatible(DataType::Double, &[1, 30]));
+
+        let unknown_rank = info("x:0", DataType::Int32, None);
+        assert!(unknown_rank.is_compatible(DataType::Int32, &[]));
+        assert!(unknown_rank.is_compatible(DataType::Int32, &[2, 3, 4]));
+        assert!(!unknown_rank.is_compatible(DataType::Int64, &[2]));
+
+        let unknown_type = info("x:0", DataType::UnknownValue(0), Some(vec![2]));
+        assert!(unknown_type.is_compatible(DataType::Bool, &[2]));
+        assert!(!unknown_type.is_compatible(DataType::Bool, &[3]));
+
+        let scalar = info("x:0", DataType::Float, Some(vec![]));
+        assert!(scalar.is_compatible(DataType::Float, &[]));
+        assert!(!scalar.is_compatible(DataType::Float, &[1]));
+    }
+
+    #[test]
+    fn validate_reports_mismatches() {
+        let mut model = model();
+
+        let input = Tensor::<f32>::new(&[2, 30]);
+        validate(&mut model, "serving_default_input_1", 0, input.inner()).unwrap();
+
+        // Inputs that are not part of a signature are not checked
+        let other = Tensor::<i32>::new(&[1]);
+        validate(&mut model, "serving_default_input_1", 1, other.inner()).unwrap();
+        validate(&mut model, "other", 0, other.inner()).unwrap();
+
+        let wrong = Tensor::<i32>::new(&[2, 30]);
+        let err = validate(&mut model, "serving_default_input_1", 0, wrong.inner()).unwrap_err();
+        match &err {
+            Error::InputMismatch {
+                node,
+                expected,
+                actual,
+            } => {
+                assert_eq!(node, "serving_default_input_1:0");
+                assert_eq!(expected, "Float[-1, 30]");
+                assert_eq!(actual, "Int32[2, 30]");
+            }
+            e => panic!("unexpected error {}", e),
+        }
+        assert_eq!(
+            err.to_string(),
+            "Input serving_default_input_1:0 does not match the model signature: \
+             expected Float[-1, 30], got Int32[2, 30]"
+        );
+    }
+
+    #[test]
+    fn validate_scalar_without_dims() {
+        let mut model = model();
+
+        let scalar = ffi::vaccel_tf_tensor {
+            data: std::ptr::null_mut(),
+            size: 0,
+            owned: false,
+            nr_dims: 0,
+            dims: std::ptr::null_mut(),
+            data_type: DataType::Float.to_int(),
+        };
+        let err = validate(&mut model, "serving_default_input_1", 0, &scalar).unwrap_err();
+        assert!(matches!(err, Error::InputMismatch { actual, .. } if actual == "Float[]"));
+    }
+
+    #[test]
+    fn signature_errors_are_not_cached() {
+        // Neither an export directory nor in-memory data to read signatures from
+        let mut model = SavedModel::new();
+        let input = Tensor::<f32>::new(&[2, 30]);
+
+        assert!(validate(&mut model, "serving_default_input_1", 0, input.inner()).is_err());
+        assert!(model.signatures.is_none());
+    }
+}
@@ -0,0 +1,324 @@
+use crate::tensorflow::{Code, Tensor, TensorType};
+use crate::{Error, Result};
+
+use std::ops::{Index, Range};
+
+/// A strided, read-only view over the data of a tensor
+///
+/// Views are created from a `Tensor` through `Tensor::view`, `Tensor::slice`
+/// and `Tensor::axis_iter`. They never copy the underlying data, they just
+/// record how the elements of the original tensor map to the view.
+#[derive(Debug, Clone)]
+pub struct TensorSlice<'a, T: TensorType> {
+    data: &'a [T],
+    dims: Vec<u64>,
+    strides: Vec<usize>,
+    offset: usize,
+}
+
+/// Compute row-major (C order) strides for a shape
+pub(crate) fn strides(dims: &[u64]) -> Vec<usize> {
+    let mut strides = vec![1usize; dims.len()];
+    for i in (0..dims.len().saturating_sub(1)).rev() {
+        strides[i] = strides[i + 1] * dims[i + 1] as usize;
+    }
+
+    strides
+}
+
+impl<'a, T: TensorType> TensorSlice<'a, T> {
+    /// Create a view covering a contiguous, row-major buffer
+    pub(crate) fn new(data: &'a [T], dims: &[u64]) -> Self {
+        TensorSlice {
+            data,
+            dims: Vec::from(dims),
+            strides: strides(dims),
+            offset: 0,
+        }
+    }
+
+    pub fn dims(&self) -> &[u64] {
+        &self.dims
+    }
+
+    pub fn nr_dims(&self) -> u64 {
+        self.dims.len() as u64
+    }
+
+    pub fn dim(&self, idx: usize) -> Result<u64> {
+        if idx >= self.dims.len() {
+            return Err(Error::TensorFlow(Code::OutOfRange));
+        }
+
+        Ok(self.dims[idx])
+    }
+
+    /// Number of elements in the view
+    pub fn len(&self) -> usize {
+        self.dims.iter().product::<u64>() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn position(&self, index: &[usize]) -> Option<usize> {
+        if index.len() != self.dims.len() {
+            return None;
+        }
+
+        let mut pos = self.offset;
+        for ((i, d), s) in index.iter().zip(&self.dims).zip(&self.strides) {
+            if *i as u64 >= *d {
+                return None;
+            }
+            pos += i * s;
+        }
+
+        Some(pos)
+    }
+
+    /// Get a reference to the element at a multi-dimensional index
+    pub fn get(&self, index: &[usize]) -> Option<&'a T> {
+        let data = self.data;
+        self.position(index).map(|pos| &data[pos])
+    }
+
+    /// Restrict the view to `range` along `axis`
+    ///
+    /// The number of dimensions of the view does not change.
+    pub fn slice(&self, axis: usize, range: Range<usize>) -> Result<TensorSlice<'a, T>> {
+        let dim = self.dim(axis)? as usize;
+        if range.start > range.end || range.end > dim {
+            return Err(Error::TensorFlow(Code::OutOfRange));
+        }
+
+        let mut dims = self.dims.clone();
+        dims[axis] = (range.end - range.start) as u64;
+
+        Ok(TensorSlice {
+            data: self.data,
+            dims,
+            strides: self.strides.clone(),
+            offset: self.offset + range.start * self.strides[axis],
+        })
+    }
+
+    /// Select the sub-tensor at `index` along `axis`
+    ///
+    /// The returned view has one dimension less than `self`.
+    pub fn index_axis(&self, axis: usize, index: usize) -> Result<TensorSlice<'a, T>> {
+        if index as u64 >= self.dim(axis)? {
+            return Err(Error::TensorFlow(Code::OutOfRange));
+        }
+
+        let mut dims = self.dims.clone();
+        let mut strides = self.strides.clone();
+        dims.remove(axis);
+        strides.remove(axis);
+
+        Ok(TensorSlice {
+            data: self.data,
+            dims,
+            strides,
+            offset: self.offset + index * self.strides[axis],
+        })
+    }
+
+    /// Iterate over the sub-tensors along `axis`
+    pub fn axis_iter(&self, axis: usize) -> Result<AxisIter<'a, T>> {
+        let end = self.dim(axis)? as usize;
+
+        Ok(AxisIter {
+            view: self.clone(),
+            axis,
+            next: 0,
+            end,
+        })
+    }
+
+    /// Iterate over the elements of the view in row-major order
+    pub fn iter(&self) -> Iter<'a, T> {
+        Iter {
+            view: self.clone(),
+            index: vec![0; self.dims.len()],
+            remaining: self.len(),
+        }
+    }
+
+    /// Copy the elements of the view in a new, row-major, vector
+    pub fn to_vec(&self) -> Vec<T> {
+        self.iter().cloned().collect()
+    }
+
+    /// Copy the view into a new tensor
+    pub fn to_tensor(&self) -> Result<Tensor<T>> {
+        Tensor::new(&self.dims).with_data(&self.to_vec())
+    }
+}
+
+impl<'a, T: TensorType> Index<&[usize]> for TensorSlice<'a, T> {
+    type Output = T;
+
+    fn index(&self, index: &[usize]) -> &T {
+        match self.get(index) {
+            Some(e) => e,
+            None => panic!(
+                "index {:?} is out of bounds for tensor with dims {:?}",
+                index, self.dims
+            ),
+        }
+    }
+}
+
+impl<'a, T: TensorType, const N: usize> Index<[usize; N]> for TensorSlice<'a, T> {
+    type Output = T;
+
+    fn index(&self, index: [usize; N]) -> &T {
+        &self[&index[..]]
+    }
+}
+
+/// Iterator over the sub-tensors of a view along a given axis
+pub struct AxisIter<'a, T: TensorType> {
+    view: TensorSlice<'a, T>,
+    axis: usize,
+    next: usize,
+    end: usize,
+}
+
+impl<'a, T: TensorType> Iterator for AxisIter<'a, T> {
+    type Item = TensorSlice<'a, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            return None;
+        }
+
+        let item = self.view.index_axis(self.axis, self.next).ok();
+        self.next += 1;
+        item
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.next;
+        (len, Some(len))
+    }
+}
+
+impl<'a, T: TensorType> ExactSizeIterator for AxisIter<'a, T> {}
+
+/// Row-major iterator over the elements of a view
+pub struct Iter<'a, T: TensorType> {
+    view: TensorSlice<'a, T>,
+    index: Vec<usize>,
+    remaining: usize,
+}
+
+impl<'a, T: TensorType> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let item = self.view.get(&self.index);
+
+        // Advance the multi-dimensional index, last axis first
+        for i in (0..self.index.len()).rev() {
+            self.index[i] += 1;
+            if (self.index[i] as u64) < self.view.dims[i] {
+                break;
+            }
+            self.index[i] = 0;
+        }
+        self.remaining -= 1;
+
+        item
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T: TensorType> ExactSizeIterator for Iter<'a, T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `[3, 4]` tensor holding `0..12`
+    fn tensor() -> Tensor<i32> {
+        let data: Vec<i32> = (0..12).collect();
+        Tensor::new(&[3, 4]).with_data(&data).unwrap()
+    }
+
+    #[test]
+    fn strides_are_row_major() {
+        assert_eq!(strides(&[2, 3, 4]), &[12, 4, 1]);
+        assert_eq!(strides(&[5]), &[1]);
+        assert!(strides(&[]).is_empty());
+    }
+
+    #[test]
+    fn slice_offsets() {
+        let tensor = tensor();
+
+        let rows = tensor.slice(0, 1..3).unwrap();
+        assert_eq!(rows.dims(), &[2, 4]);
+        assert_eq!(rows[[0, 0]], 4);
+        assert_eq!(rows.to_vec(), (4..12).collect::<Vec<_>>());
+
+        let cols = tensor.slice(1, 1..3).unwrap();
+        assert_eq!(cols.dims(), &[3, 2]);
+        assert_eq!(cols.to_vec(), &[1, 2, 5, 6, 9, 10]);
+
+        // Slicing a slice keeps both offsets
+        let block = rows.slice(1, 2..4).unwrap();
+        assert_eq!(block.to_vec(), &[6, 7, 10, 11]);
+        assert_eq!(block.get(&[1, 1]), Some(&11));
+        assert_eq!(block.get(&[2, 0]), None);
+
+        let copy = block.to_tensor().unwrap();
+        assert_eq!(copy.dims(), &[2, 2]);
+        assert_eq!(&copy[..], &[6, 7, 10, 11]);
+
+        assert!(tensor.slice(0, 3..3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn slice_out_of_range() {
+        let tensor = tensor();
+        assert!(tensor.slice(1, 3..5).is_err());
+        assert!(tensor.slice(2, 0..1).is_err());
+        assert!(tensor.view().index_axis(0, 3).is_err());
+        assert!(tensor.axis_iter(2).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn slice_index_out_of_range() {
+        let tensor = tensor();
+        let cols = tensor.slice(1, 1..3).unwrap();
+        let _ = cols[[0, 2]];
+    }
+
+    #[test]
+    fn axis_iter_is_strided() {
+        let tensor = tensor();
+
+        let rows = tensor.axis_iter(0).unwrap();
+        assert_eq!(rows.len(), 3);
+        let rows: Vec<_> = rows.map(|r| r.to_vec()).collect();
+        assert_eq!(rows[2], &[8, 9, 10, 11]);
+
+        let cols: Vec<_> = tensor.axis_iter(1).unwrap().collect();
+        assert_eq!(cols.len(), 4);
+        assert_eq!(cols[0].dims(), &[3]);
+        assert_eq!(cols[0].to_vec(), &[0, 4, 8]);
+        assert_eq!(cols[3].to_vec(), &[3, 7, 11]);
+        assert_eq!(cols[1][[2]], 9);
+    }
+}
@@ -1,17 +1,29 @@
 use crate::ffi;
+use crate::tensorflow::chunk::element_size;
+use crate::tensorflow::compression;
+use crate::tensorflow::slice::{strides, AxisIter, TensorSlice};
 use crate::tensorflow::{Code, DataType};
 use crate::{Error, Result};
 
 use protobuf::ProtobufEnum;
 use protocols::tensorflow::{TFDataType, TFTensor};
 
-use std::ops::{Deref, DerefMut};
+use std::any::Any;
+use std::convert::TryFrom;
+use std::ops::{
+    Deref, DerefMut, Index, IndexMut, Range, RangeFrom, RangeFull, RangeInclusive, RangeTo,
+    RangeToInclusive,
+};
 
 pub struct Tensor<T: TensorType> {
     inner: *mut ffi::vaccel_tf_tensor,
     dims: Vec<u64>,
     data_count: usize,
-    data: Vec<T>,
+
+    // Backing memory of the tensor data, when it is allocated by us. It is
+    // `None` for tensors whose data is allocated by the vAccel runtime. In
+    // both cases the data is only ever accessed through `inner`.
+    storage: Option<Vec<T>>,
 }
 
 pub trait TensorType: Default + Clone {
@@ -25,15 +37,56 @@ pub trait TensorType: Default + Clone {
     fn zero() -> Self;
 }
 
-fn product(values: &[u64]) -> u64 {
-    values.iter().product()
+/// The product of `values`, or `None` on overflow, for dimensions we do not trust
+fn checked_product(values: &[u64]) -> Option<u64> {
+    values.iter().try_fold(1u64, |acc, v| acc.checked_mul(*v))
+}
+
+/// The number of elements of type `T` in a tensor with dimensions `dims`
+///
+/// Returns `None` if the data of such a tensor does not fit in memory.
+fn data_count<T>(dims: &[u64]) -> Option<usize> {
+    let count = checked_product(dims)?;
+    count.checked_mul(std::mem::size_of::<T>() as u64)?;
+    usize::try_from(count).ok()
+}
+
+/// Resolve a shape that may contain a single `-1` placeholder
+///
+/// The placeholder dimension is inferred so that the shape describes exactly
+/// `count` elements.
+fn infer_dims(dims: &[i64], count: usize) -> Result<Vec<u64>> {
+    let mut unknown = None;
+    let mut known = 1u64;
+
+    for (i, d) in dims.iter().enumerate() {
+        match *d {
+            -1 if unknown.is_none() => unknown = Some(i),
+            d if d >= 0 => known = known.checked_mul(d as u64).ok_or(Error::InvalidArgument)?,
+            _ => return Err(Error::InvalidArgument),
+        }
+    }
+
+    let mut ret: Vec<u64> = dims.iter().map(|d| *d as u64).collect();
+    if let Some(i) = unknown {
+        if known == 0 || count as u64 % known != 0 {
+            return Err(Error::InvalidArgument);
+        }
+        ret[i] = count as u64 / known;
+    }
+
+    if checked_product(&ret) != Some(count as u64) {
+        return Err(Error::InvalidArgument);
+    }
+
+    Ok(ret)
 }
 
 impl<T: TensorType> Deref for Tensor<T> {
     type Target = [T];
 
     fn deref(&self) -> &[T] {
-        if self.inner.is_null() {
+        if self.inner.is_null() || unsafe { (*self.inner).data.is_null() } {
             &[]
         } else {
             let data = unsafe { (*self.inner).data } as *const T;
@@ -44,7 +97,7 @@ impl<T: TensorType> Deref for Tensor<T> {
 
 impl<T: TensorType> DerefMut for Tensor<T> {
     fn deref_mut(&mut self) -> &mut [T] {
-        if self.inner.is_null() {
+        if self.inner.is_null() || unsafe { (*self.inner).data.is_null() } {
             &mut []
         } else {
             let data = unsafe { (*self.inner).data } as *mut T;
@@ -56,7 +109,7 @@ impl<T: TensorType> DerefMut for Tensor<T> {
 impl<T: TensorType> Tensor<T> {
     pub fn new(dims: &[u64]) -> Self {
         let dims = Vec::from(dims);
-        let data_count = product(&dims) as usize;
+        let data_count = data_count::<T>(&dims).expect("Tensor dimensions overflow");
         let mut data = Vec::with_capacity(data_count);
         data.resize(data_count, T::zero());
 
@@ -80,10 +133,64 @@ impl<T: TensorType> Tensor<T> {
             inner,
             dims,
             data_count,
-            data,
+            storage: Some(data),
+        }
+    }
+
+    /// Create a new tensor with storage allocated by the vAccel runtime
+    ///
+    /// The runtime owns the data of the tensor and releases it when the
+    /// tensor is dropped. This allows the runtime to provide memory with
+    /// particular requirements, e.g. aligned or pinned memory, for its
+    /// backends. The elements of the tensor are initialized to zero.
+    ///
+    /// # Arguments
+    ///
+    /// * `dims` - The dimensions of the tensor
+    pub fn allocate(dims: &[u64]) -> Result<Self> {
+        let dims = Vec::from(dims);
+        let data_count = data_count::<T>(&dims).ok_or(Error::InvalidArgument)?;
+
+        let inner = unsafe {
+            ffi::vaccel_tf_tensor_allocate(
+                dims.len() as i32,
+                dims.as_ptr() as *mut _,
+                T::data_type().to_int(),
+                (data_count * std::mem::size_of::<T>()) as u64,
+            )
+        };
+
+        if inner.is_null() || (data_count > 0 && unsafe { (*inner).data.is_null() }) {
+            if !inner.is_null() {
+                unsafe { ffi::vaccel_tf_tensor_destroy(inner) };
+            }
+            return Err(Error::Runtime(ffi::VACCEL_ENOMEM));
         }
+
+        // The memory returned by the runtime is uninitialized
+        let data = unsafe { (*inner).data } as *mut T;
+        for i in 0..data_count {
+            unsafe { std::ptr::write(data.add(i), T::zero()) };
+        }
+
+        Ok(Tensor {
+            inner,
+            dims,
+            data_count,
+            storage: None,
+        })
     }
 
+    /// Wrap a tensor created by the vAccel runtime
+    ///
+    /// The returned `Tensor` takes ownership of `tensor` and destroys it when
+    /// dropped. The data of the tensor is not copied. It is released on drop
+    /// only if it is owned by the runtime tensor.
+    ///
+    /// # Safety
+    ///
+    /// `tensor` must point to a valid vAccel tensor, which is not used or
+    /// destroyed through any other handle afterwards.
     pub unsafe fn from_vaccel_tensor(tensor: *mut ffi::vaccel_tf_tensor) -> Result<Tensor<T>> {
         if tensor.is_null() {
             return Err(Error::InvalidArgument);
@@ -93,26 +200,26 @@ impl<T: TensorType> Tensor<T> {
             return Err(Error::InvalidArgument);
         }
 
-        let dims = std::slice::from_raw_parts((*tensor).dims as *mut _, (*tensor).nr_dims as usize);
+        let dims: &[u64] = if (*tensor).nr_dims <= 0 || (*tensor).dims.is_null() {
+            &[]
+        } else {
+            std::slice::from_raw_parts((*tensor).dims as *const _, (*tensor).nr_dims as usize)
+        };
 
-        let data_count = product(&dims) as usize;
+        let data_count = data_count::<T>(dims).ok_or(Error::InvalidArgument)?;
 
         let ptr = ffi::vaccel_tf_tensor_get_data(tensor);
-        let data = if ptr.is_null() {
-            let mut data = Vec::with_capacity(data_count);
-            data.resize(data_count, T::zero());
-            data
-        } else {
-            let data =
-                std::slice::from_raw_parts(ptr as *mut T, data_count * std::mem::size_of::<T>());
-            Vec::from(data)
-        };
+        if data_count > 0
+            && (ptr.is_null() || ((*tensor).size as usize) < data_count * std::mem::size_of::<T>())
+        {
+            return Err(Error::InvalidArgument);
+        }
 
         Ok(Tensor::<T> {
             inner: tensor,
             dims: Vec::from(dims),
             data_count,
-            data,
+            storage: None,
         })
     }
 
@@ -140,22 +247,296 @@ impl<T: TensorType> Tensor<T> {
         Ok(self.dims[idx])
     }
 
+    pub fn dims(&self) -> &[u64] {
+        &self.dims
+    }
+
     pub fn data_type(&self) -> DataType {
         T::data_type()
     }
 
+    /// Replace the dimensions of the tensor, keeping its data as is
+    ///
+    /// The array holding the dimensions of a vAccel tensor is allocated by the
+    /// runtime, so it cannot be resized from here. Instead, the vAccel tensor
+    /// is replaced by a new one with the new dimensions, which takes over the
+    /// data.
+    fn set_dims(&mut self, dims: Vec<u64>) -> Result<()> {
+        let inner = unsafe {
+            ffi::vaccel_tf_tensor_new(
+                dims.len() as i32,
+                dims.as_ptr() as *mut _,
+                T::data_type().to_int(),
+            )
+        };
+        if inner.is_null() {
+            return Err(Error::Runtime(ffi::VACCEL_ENOMEM));
+        }
+
+        unsafe {
+            let old = self.inner;
+            ffi::vaccel_tf_tensor_set_data(inner, (*old).data, (*old).size);
+
+            // Data allocated by the runtime is now released with the new tensor
+            (*inner).owned = (*old).owned;
+            (*old).owned = false;
+            ffi::vaccel_tf_tensor_destroy(old);
+        }
+
+        self.inner = inner;
+        self.dims = dims;
+        Ok(())
+    }
+
+    /// Overwrite the dimensions of the tensor in place
+    ///
+    /// There must not be more new dimensions than current ones, as the array
+    /// of the vAccel tensor is not resized.
+    fn write_dims(&mut self, dims: Vec<u64>) {
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                dims.as_ptr(),
+                (*self.inner).dims as *mut u64,
+                dims.len(),
+            );
+            (*self.inner).nr_dims = dims.len() as _;
+        }
+
+        self.dims = dims;
+    }
+
+    /// Change the shape of the tensor
+    ///
+    /// The number of elements must stay the same. At most one of the new
+    /// dimensions can be `-1`, in which case it is inferred from the rest.
+    ///
+    /// # Arguments
+    ///
+    /// * `dims` - The new dimensions of the tensor
+    pub fn reshape(mut self, dims: &[i64]) -> Result<Self> {
+        let dims = infer_dims(dims, self.data_count)?;
+        self.set_dims(dims)?;
+
+        Ok(self)
+    }
+
+    /// Remove all dimensions of size 1
+    pub fn squeeze(mut self) -> Self {
+        // Removing dimensions never needs a bigger array
+        let dims = self.dims.iter().cloned().filter(|d| *d != 1).collect();
+        self.write_dims(dims);
+
+        self
+    }
+
+    /// Insert a dimension of size 1 at position `axis`
+    pub fn expand_dims(mut self, axis: usize) -> Result<Self> {
+        if axis > self.dims.len() {
+            return Err(Error::TensorFlow(Code::OutOfRange));
+        }
+
+        let mut dims = self.dims.clone();
+        dims.insert(axis, 1);
+        self.set_dims(dims)?;
+
+        Ok(self)
+    }
+
+    fn position(&self, index: &[usize]) -> Option<usize> {
+        if index.len() != self.dims.len() {
+            return None;
+        }
+
+        let mut pos = 0;
+        for ((i, d), s) in index.iter().zip(&self.dims).zip(strides(&self.dims)) {
+            if *i as u64 >= *d {
+                return None;
+            }
+            pos += i * s;
+        }
+
+        Some(pos)
+    }
+
+    /// Get a reference to the element at a multi-dimensional index
+    pub fn get(&self, index: &[usize]) -> Option<&T> {
+        self.position(index).map(move |pos| &self[pos])
+    }
+
+    /// Get a mutable reference to the element at a multi-dimensional index
+    pub fn get_mut(&mut self, index: &[usize]) -> Option<&mut T> {
+        match self.position(index) {
+            Some(pos) => Some(&mut self[pos]),
+            None => None,
+        }
+    }
+
+    /// Get a view of the whole tensor
+    pub fn view(&self) -> TensorSlice<'_, T> {
+        TensorSlice::new(self, &self.dims)
+    }
+
+    /// Get a view of the tensor restricted to `range` along `axis`
+    pub fn slice(&self, axis: usize, range: Range<usize>) -> Result<TensorSlice<'_, T>> {
+        self.view().slice(axis, range)
+    }
+
+    /// Iterate over the sub-tensors along `axis`
+    ///
+    /// For example, iterating over axis 0 of a `[batch, classes]` tensor yields
+    /// one `[classes]` view per batch entry.
+    pub fn axis_iter(&self, axis: usize) -> Result<AxisIter<'_, T>> {
+        self.view().axis_iter(axis)
+    }
+
+    /// Convert the tensor to a `TFTensor`, copying its data
+    ///
+    /// The data is not compressed here. Sessions that negotiated compression
+    /// compress the tensors they send, and `from_grpc` decompresses them.
     pub fn as_grpc(&self) -> TFTensor {
         let data = unsafe {
-            std::slice::from_raw_parts((*self.inner).data as *const u8, (*self.inner).size as usize)
+            if (*self.inner).data.is_null() {
+                &[]
+            } else {
+                std::slice::from_raw_parts(
+                    (*self.inner).data as *const u8,
+                    (*self.inner).size as usize,
+                )
+            }
         };
 
         TFTensor {
             data: data.to_owned(),
             dims: self.dims.clone(),
-            field_type: TFDataType::from_i32(self.data_type().to_int() as i32).unwrap(),
+            field_type: self.data_type().into(),
             ..Default::default()
         }
     }
+
+    /// Create a tensor from a `TFTensor`, copying its data
+    ///
+    /// The `TFTensor` must hold elements of type `T`, and exactly as many
+    /// bytes as its dimensions require. Compressed data is decompressed.
+    pub fn from_grpc(tensor: &TFTensor) -> Result<Self> {
+        if DataType::from(tensor.get_field_type()) != T::data_type() {
+            return Err(Error::TensorFlow(Code::InvalidArgument));
+        }
+
+        // Check the dimensions before allocating the tensor, as they come
+        // from the peer
+        let len = checked_product(tensor.get_dims())
+            .and_then(|count| count.checked_mul(std::mem::size_of::<T>() as u64))
+            .ok_or(Error::InvalidArgument)?;
+
+        let data = compression::decompressed_data(tensor)?;
+        if data.len() as u64 != len {
+            return Err(Error::InvalidArgument);
+        }
+
+        // Any other byte is not a valid `bool`
+        if T::data_type() == DataType::Bool && data.iter().any(|b| *b > 1) {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut t = Tensor::<T>::new(tensor.get_dims());
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                t.deref_mut().as_mut_ptr() as *mut u8,
+                data.len(),
+            )
+        };
+
+        Ok(t)
+    }
+}
+
+impl<T: TensorType> Index<usize> for Tensor<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        &self.deref()[index]
+    }
+}
+
+impl<T: TensorType> IndexMut<usize> for Tensor<T> {
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        &mut self.deref_mut()[index]
+    }
+}
+
+/// Forward range indexing to the underlying slice
+///
+/// Implementing `Index` for multi-dimensional indices disables the automatic
+/// deref on indexing, so ranges need to be forwarded explicitly.
+macro_rules! impl_range_index {
+    ($($range:ty),*) => {
+        $(
+            impl<T: TensorType> Index<$range> for Tensor<T> {
+                type Output = [T];
+
+                fn index(&self, index: $range) -> &[T] {
+                    &self.deref()[index]
+                }
+            }
+
+            impl<T: TensorType> IndexMut<$range> for Tensor<T> {
+                fn index_mut(&mut self, index: $range) -> &mut [T] {
+                    &mut self.deref_mut()[index]
+                }
+            }
+        )*
+    };
+}
+
+impl_range_index!(
+    Range<usize>,
+    RangeFrom<usize>,
+    RangeTo<usize>,
+    RangeFull,
+    RangeInclusive<usize>,
+    RangeToInclusive<usize>
+);
+
+impl<T: TensorType> Index<&[usize]> for Tensor<T> {
+    type Output = T;
+
+    fn index(&self, index: &[usize]) -> &T {
+        match self.get(index) {
+            Some(e) => e,
+            None => panic!(
+                "index {:?} is out of bounds for tensor with dims {:?}",
+                index, self.dims
+            ),
+        }
+    }
+}
+
+impl<T: TensorType> IndexMut<&[usize]> for Tensor<T> {
+    fn index_mut(&mut self, index: &[usize]) -> &mut T {
+        match self.position(index) {
+            Some(pos) => &mut self[pos],
+            None => panic!(
+                "index {:?} is out of bounds for tensor with dims {:?}",
+                index, self.dims
+            ),
+        }
+    }
+}
+
+impl<T: TensorType, const N: usize> Index<[usize; N]> for Tensor<T> {
+    type Output = T;
+
+    fn index(&self, index: [usize; N]) -> &T {
+        &self[&index[..]]
+    }
+}
+
+impl<T: TensorType, const N: usize> IndexMut<[usize; N]> for Tensor<T> {
+    fn index_mut(&mut self, index: [usize; N]) -> &mut T {
+        &mut self[&index[..]]
+    }
 }
 
 impl<T: TensorType> Drop for Tensor<T> {
@@ -164,6 +545,8 @@ impl<T: TensorType> Drop for Tensor<T> {
             return;
         }
 
+        // This releases the data as well if it is owned by the runtime. Data
+        // allocated by us is released when `storage` is dropped.
         unsafe { ffi::vaccel_tf_tensor_destroy(self.inner) };
         self.inner = std::ptr::null_mut();
     }
@@ -191,47 +574,154 @@ impl<T: TensorType> TensorAny for Tensor<T> {
     }
 }
 
-impl TensorAny for TFTensor {
-    fn inner(&self) -> *const ffi::vaccel_tf_tensor {
-        let inner = unsafe {
-            ffi::vaccel_tf_tensor_new(
-                self.get_dims().len() as i32,
-                self.get_dims().as_ptr() as *mut _,
-                self.get_field_type().value() as u32,
-            )
-        };
+/// The data of an `OwnedVaccelTensor`, aligned for any element type
+///
+/// Byte vectors are kept as is when they happen to be aligned, which is the
+/// common case, and copied to a vector of words otherwise.
+enum AlignedData {
+    Bytes(Vec<u8>),
+    Words(Vec<u64>, usize),
+}
 
-        let size = self.get_data().len() as u64;
-        let data = self.get_data().to_owned();
+impl AlignedData {
+    fn new(data: Vec<u8>) -> Self {
+        if data.as_ptr() as usize % std::mem::align_of::<u64>() == 0 {
+            return AlignedData::Bytes(data);
+        }
 
-        unsafe { ffi::vaccel_tf_tensor_set_data(inner, data.as_ptr() as *mut libc::c_void, size) };
+        let word = std::mem::size_of::<u64>();
+        let mut words = vec![0u64; (data.len() + word - 1) / word];
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), words.as_mut_ptr() as *mut u8, data.len())
+        };
 
-        std::mem::forget(data);
+        AlignedData::Words(words, data.len())
+    }
 
-        inner
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            AlignedData::Bytes(data) => data,
+            AlignedData::Words(words, len) => unsafe {
+                std::slice::from_raw_parts(words.as_ptr() as *const u8, *len)
+            },
+        }
     }
+}
+
+/// A vAccel tensor created from a `TFTensor`
+///
+/// The tensor owns a copy of the `TFTensor` payload. Both the vAccel tensor
+/// and its data are released when this is dropped, so it can be used to pass
+/// tensors received over gRPC to `InferenceArgs::add_input` without leaking
+/// them.
+pub struct OwnedVaccelTensor {
+    inner: *mut ffi::vaccel_tf_tensor,
+    data: AlignedData,
+}
+
+impl OwnedVaccelTensor {
+    /// Create a vAccel tensor holding `data`
+    ///
+    /// For data types with elements of fixed size, the size of `data` must be
+    /// exactly the one `dims` require.
+    pub(crate) fn new(dims: &[u64], data_type: TFDataType, data: Vec<u8>) -> Result<Self> {
+        if let Some(size) = element_size(DataType::from(data_type)) {
+            let expected = dims
+                .iter()
+                .try_fold(size as u64, |len, d| len.checked_mul(*d));
+            if expected != Some(data.len() as u64) {
+                return Err(Error::InvalidArgument);
+            }
+        }
 
-    fn inner_mut(&mut self) -> *mut ffi::vaccel_tf_tensor {
         let inner = unsafe {
             ffi::vaccel_tf_tensor_new(
-                self.get_dims().len() as i32,
-                self.get_dims().as_ptr() as *mut _,
-                self.get_field_type().value() as u32,
+                dims.len() as i32,
+                dims.as_ptr() as *mut _,
+                data_type.value() as u32,
             )
         };
+        if inner.is_null() {
+            return Err(Error::Runtime(ffi::VACCEL_ENOMEM));
+        }
+
+        // The data is owned by us, the runtime only borrows it
+        let data = AlignedData::new(data);
+        unsafe {
+            ffi::vaccel_tf_tensor_set_data(
+                inner,
+                data.as_bytes().as_ptr() as *mut libc::c_void,
+                data.as_bytes().len() as u64,
+            )
+        };
+
+        Ok(OwnedVaccelTensor { inner, data })
+    }
+
+    pub fn dims(&self) -> &[u64] {
+        unsafe {
+            std::slice::from_raw_parts(
+                (*self.inner).dims as *const u64,
+                (*self.inner).nr_dims as usize,
+            )
+        }
+    }
 
-        let size = self.get_data().len() as u64;
-        let data = self.get_data().to_owned();
+    pub fn data(&self) -> &[u8] {
+        self.data.as_bytes()
+    }
+}
 
-        unsafe { ffi::vaccel_tf_tensor_set_data(inner, data.as_ptr() as *mut libc::c_void, size) };
+/// Copy a `TFTensor` into a new vAccel tensor
+///
+/// Fails with `Error::InvalidArgument` if the payload does not match the
+/// dimensions of the tensor.
+impl TryFrom<&TFTensor> for OwnedVaccelTensor {
+    type Error = Error;
+
+    fn try_from(tensor: &TFTensor) -> Result<Self> {
+        OwnedVaccelTensor::new(
+            tensor.get_dims(),
+            tensor.get_field_type(),
+            tensor.get_data().to_owned(),
+        )
+    }
+}
 
-        std::mem::forget(data);
+/// Move the payload of a `TFTensor` into a new vAccel tensor
+///
+/// See the conversion from `&TFTensor`.
+impl TryFrom<TFTensor> for OwnedVaccelTensor {
+    type Error = Error;
 
-        inner
+    fn try_from(mut tensor: TFTensor) -> Result<Self> {
+        let data = tensor.take_data();
+        OwnedVaccelTensor::new(tensor.get_dims(), tensor.get_field_type(), data)
+    }
+}
+
+impl Drop for OwnedVaccelTensor {
+    fn drop(&mut self) {
+        if self.inner.is_null() {
+            return;
+        }
+
+        unsafe { ffi::vaccel_tf_tensor_destroy(self.inner) };
+        self.inner = std::ptr::null_mut();
+    }
+}
+
+impl TensorAny for OwnedVaccelTensor {
+    fn inner(&self) -> *const ffi::vaccel_tf_tensor {
+        self.inner
+    }
+
+    fn inner_mut(&mut self) -> *mut ffi::vaccel_tf_tensor {
+        self.inner
     }
 
     fn data_type(&self) -> DataType {
-        DataType::from_int(self.get_field_type().value() as u32)
+        DataType::from_int(unsafe { (*self.inner).data_type })
     }
 }
 
@@ -249,6 +739,137 @@ impl TensorAny for *mut ffi::vaccel_tf_tensor {
     }
 }
 
+/// A tensor of any of the element types implementing `TensorType`
+///
+/// This is useful when the type of a tensor is only known at runtime, for
+/// example when reading tensors from files.
+pub enum AnyTensor {
+    Float(Tensor<f32>),
+    Double(Tensor<f64>),
+    Int32(Tensor<i32>),
+    UInt8(Tensor<u8>),
+    Int16(Tensor<i16>),
+    Int8(Tensor<i8>),
+    Int64(Tensor<i64>),
+    UInt16(Tensor<u16>),
+    UInt32(Tensor<u32>),
+    UInt64(Tensor<u64>),
+    Bool(Tensor<bool>),
+}
+
+/// Apply the same expression to the inner tensor of any `AnyTensor` variant
+macro_rules! any_tensor_apply {
+    ($any:expr, $t:ident => $e:expr) => {
+        match $any {
+            AnyTensor::Float($t) => $e,
+            AnyTensor::Double($t) => $e,
+            AnyTensor::Int32($t) => $e,
+            AnyTensor::UInt8($t) => $e,
+            AnyTensor::Int16($t) => $e,
+            AnyTensor::Int8($t) => $e,
+            AnyTensor::Int64($t) => $e,
+            AnyTensor::UInt16($t) => $e,
+            AnyTensor::UInt32($t) => $e,
+            AnyTensor::UInt64($t) => $e,
+            AnyTensor::Bool($t) => $e,
+        }
+    };
+}
+
+impl AnyTensor {
+    /// Create a tensor from a vAccel tensor, based on its runtime data type
+    ///
+    /// See `Tensor::from_vaccel_tensor` for the ownership semantics.
+    ///
+    /// # Safety
+    ///
+    /// `tensor` must point to a valid vAccel tensor, which is not used or
+    /// destroyed through any other handle afterwards.
+    pub unsafe fn from_vaccel_tensor(tensor: *mut ffi::vaccel_tf_tensor) -> Result<Self> {
+        if tensor.is_null() {
+            return Err(Error::InvalidArgument);
+        }
+
+        Ok(match DataType::from_int((*tensor).data_type) {
+            DataType::Float => AnyTensor::Float(Tensor::from_vaccel_tensor(tensor)?),
+            DataType::Double => AnyTensor::Double(Tensor::from_vaccel_tensor(tensor)?),
+            DataType::Int32 => AnyTensor::Int32(Tensor::from_vaccel_tensor(tensor)?),
+            DataType::UInt8 => AnyTensor::UInt8(Tensor::from_vaccel_tensor(tensor)?),
+            DataType::Int16 => AnyTensor::Int16(Tensor::from_vaccel_tensor(tensor)?),
+            DataType::Int8 => AnyTensor::Int8(Tensor::from_vaccel_tensor(tensor)?),
+            DataType::Int64 => AnyTensor::Int64(Tensor::from_vaccel_tensor(tensor)?),
+            DataType::UInt16 => AnyTensor::UInt16(Tensor::from_vaccel_tensor(tensor)?),
+            DataType::UInt32 => AnyTensor::UInt32(Tensor::from_vaccel_tensor(tensor)?),
+            DataType::UInt64 => AnyTensor::UInt64(Tensor::from_vaccel_tensor(tensor)?),
+            DataType::Bool => AnyTensor::Bool(Tensor::from_vaccel_tensor(tensor)?),
+            _ => return Err(Error::TensorFlow(Code::Unimplemented)),
+        })
+    }
+
+    pub fn dims(&self) -> &[u64] {
+        any_tensor_apply!(self, t => t.dims())
+    }
+
+    pub fn nr_dims(&self) -> u64 {
+        any_tensor_apply!(self, t => t.nr_dims())
+    }
+
+    pub fn data_type(&self) -> DataType {
+        any_tensor_apply!(self, t => t.data_type())
+    }
+
+    pub fn as_grpc(&self) -> TFTensor {
+        any_tensor_apply!(self, t => t.as_grpc())
+    }
+
+    /// Create a tensor from a `TFTensor`, based on its data type
+    ///
+    /// See `Tensor::from_grpc`.
+    pub fn from_grpc(tensor: &TFTensor) -> Result<Self> {
+        Ok(match DataType::from(tensor.get_field_type()) {
+            DataType::Float => AnyTensor::Float(Tensor::from_grpc(tensor)?),
+            DataType::Double => AnyTensor::Double(Tensor::from_grpc(tensor)?),
+            DataType::Int32 => AnyTensor::Int32(Tensor::from_grpc(tensor)?),
+            DataType::UInt8 => AnyTensor::UInt8(Tensor::from_grpc(tensor)?),
+            DataType::Int16 => AnyTensor::Int16(Tensor::from_grpc(tensor)?),
+            DataType::Int8 => AnyTensor::Int8(Tensor::from_grpc(tensor)?),
+            DataType::Int64 => AnyTensor::Int64(Tensor::from_grpc(tensor)?),
+            DataType::UInt16 => AnyTensor::UInt16(Tensor::from_grpc(tensor)?),
+            DataType::UInt32 => AnyTensor::UInt32(Tensor::from_grpc(tensor)?),
+            DataType::UInt64 => AnyTensor::UInt64(Tensor::from_grpc(tensor)?),
+            DataType::Bool => AnyTensor::Bool(Tensor::from_grpc(tensor)?),
+            _ => return Err(Error::TensorFlow(Code::Unimplemented)),
+        })
+    }
+
+    /// Get the typed tensor, if it holds elements of type `T`
+    pub fn downcast<T: TensorType + 'static>(self) -> Result<Tensor<T>> {
+        let any: Box<dyn Any> = any_tensor_apply!(self, t => Box::new(t));
+        any.downcast::<Tensor<T>>()
+            .map(|t| *t)
+            .map_err(|_| Error::TensorFlow(Code::InvalidArgument))
+    }
+
+    /// Get a reference to the typed tensor, if it holds elements of type `T`
+    pub fn downcast_ref<T: TensorType + 'static>(&self) -> Option<&Tensor<T>> {
+        any_tensor_apply!(self, t => (t as &dyn Any).downcast_ref::<Tensor<T>>())
+    }
+}
+
+impl TensorAny for AnyTensor {
+    fn inner(&self) -> *const ffi::vaccel_tf_tensor {
+        any_tensor_apply!(self, t => t.inner)
+    }
+
+    fn inner_mut(&mut self) -> *mut ffi::vaccel_tf_tensor {
+        any_tensor_apply!(self, t => t.inner)
+    }
+
+    fn data_type(&self) -> DataType {
+        self.data_type()
+    }
+}
+
 impl TensorType for f32 {
     fn data_type() -> DataType {
         DataType::Float
@@ -405,15 +1026,203 @@ impl TensorType for bool {
 
 impl From<&ffi::vaccel_tf_tensor> for TFTensor {
     fn from(tensor: &ffi::vaccel_tf_tensor) -> Self {
-        unsafe {
-            TFTensor {
-                dims: std::slice::from_raw_parts(tensor.dims as *mut u64, tensor.nr_dims as usize)
-                    .to_owned(),
-                field_type: TFDataType::from_i32((*tensor).data_type as i32).unwrap(),
-                data: std::slice::from_raw_parts(tensor.data as *mut u8, tensor.size as usize)
-                    .to_owned(),
-                ..Default::default()
+        let (dims, data) = unsafe {
+            let dims = if tensor.dims.is_null() {
+                &[]
+            } else {
+                std::slice::from_raw_parts(tensor.dims as *const u64, tensor.nr_dims as usize)
+            };
+            let data = if tensor.data.is_null() {
+                &[]
+            } else {
+                std::slice::from_raw_parts(tensor.data as *const u8, tensor.size as usize)
+            };
+            (dims, data)
+        };
+
+        TFTensor {
+            dims: dims.to_owned(),
+            field_type: DataType::from_int(tensor.data_type).into(),
+            data: data.to_owned(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Convert a `Tensor` to `TFTensor`, copying its data
+impl<T: TensorType> From<&Tensor<T>> for TFTensor {
+    fn from(tensor: &Tensor<T>) -> Self {
+        tensor.as_grpc()
+    }
+}
+
+/// Convert a `TFTensor` to `Tensor`, copying its data
+impl<T: TensorType> TryFrom<&TFTensor> for Tensor<T> {
+    type Error = Error;
+
+    fn try_from(tensor: &TFTensor) -> Result<Self> {
+        Tensor::from_grpc(tensor)
+    }
+}
+
+/// Convert an `AnyTensor` to `TFTensor`, copying its data
+impl From<&AnyTensor> for TFTensor {
+    fn from(tensor: &AnyTensor) -> Self {
+        tensor.as_grpc()
+    }
+}
+
+/// Convert a `TFTensor` to `AnyTensor`, copying its data
+impl TryFrom<&TFTensor> for AnyTensor {
+    type Error = Error;
+
+    fn try_from(tensor: &TFTensor) -> Result<Self> {
+        AnyTensor::from_grpc(tensor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_type_round_trip() {
+        for data_type in TFDataType::values() {
+            if *data_type != TFDataType::UNUSED {
+                assert_eq!(TFDataType::from(DataType::from(*data_type)), *data_type);
             }
         }
     }
+
+    #[test]
+    fn tensor_round_trip() {
+        let tensor = Tensor::<f32>::new(&[2, 3])
+            .with_data(&[0.0, 1.0, 2.0, 3.0, 4.0, 5.0])
+            .unwrap();
+
+        let grpc = TFTensor::from(&tensor);
+        assert_eq!(grpc.get_dims(), &[2, 3]);
+        assert_eq!(grpc.get_field_type(), TFDataType::FLOAT);
+
+        let copy = Tensor::<f32>::try_from(&grpc).unwrap();
+        assert_eq!(copy.dims(), tensor.dims());
+        assert_eq!(&copy[..], &tensor[..]);
+        assert_eq!(TFTensor::from(&copy), grpc);
+    }
+
+    #[test]
+    fn any_tensor_round_trip() {
+        let tensor = Tensor::<i32>::new(&[3]).with_data(&[1, 2, 3]).unwrap();
+        let grpc = TFTensor::from(&tensor);
+
+        let any = AnyTensor::try_from(&grpc).unwrap();
+        assert_eq!(any.data_type(), DataType::Int32);
+        assert_eq!(TFTensor::from(&any), grpc);
+    }
+
+    #[test]
+    fn tensor_from_grpc_checks_size() {
+        let mut grpc = TFTensor::from(&Tensor::<f32>::new(&[2]));
+        grpc.mut_data().pop();
+        assert!(Tensor::<f32>::try_from(&grpc).is_err());
+
+        grpc.set_dims(vec![u64::MAX, 2]);
+        assert!(Tensor::<f32>::try_from(&grpc).is_err());
+    }
+
+    fn range_tensor(dims: &[u64]) -> Tensor<i32> {
+        let data: Vec<i32> = (0..product(dims) as i32).collect();
+        Tensor::new(dims).with_data(&data).unwrap()
+    }
+
+    fn product(dims: &[u64]) -> u64 {
+        dims.iter().product()
+    }
+
+    fn vaccel_dims<T: TensorType>(tensor: &Tensor<T>) -> Vec<u64> {
+        TFTensor::from(unsafe { &*tensor.inner })
+            .get_dims()
+            .to_vec()
+    }
+
+    #[test]
+    fn reshape_infers_dims() {
+        let tensor = range_tensor(&[2, 3]).reshape(&[3, -1]).unwrap();
+        assert_eq!(tensor.dims(), &[3, 2]);
+        assert_eq!(tensor[[2, 1]], 5);
+
+        // More dimensions than the vAccel tensor had
+        let tensor = tensor.reshape(&[1, -1, 2, 1]).unwrap();
+        assert_eq!(tensor.dims(), &[1, 3, 2, 1]);
+        assert_eq!(vaccel_dims(&tensor), tensor.dims());
+        assert_eq!(&tensor[..], &[0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn reshape_rejects_invalid_dims() {
+        for dims in [
+            &[4, -1][..],
+            &[-1, -1],
+            &[7],
+            &[-2, -3],
+            &[0, -1],
+            &[i64::MAX, i64::MAX],
+        ] {
+            assert!(range_tensor(&[2, 3]).reshape(dims).is_err(), "{:?}", dims);
+        }
+    }
+
+    #[test]
+    fn reshape_keeps_runtime_data() {
+        let mut tensor = Tensor::<f32>::allocate(&[4]).unwrap();
+        tensor.copy_from_slice(&[1.0, 2.0, 3.0, 4.0]);
+
+        let tensor = tensor.reshape(&[2, 2, 1]).unwrap();
+        assert_eq!(vaccel_dims(&tensor), &[2, 2, 1]);
+        assert!(unsafe { (*tensor.inner).owned });
+        assert_eq!(tensor[[1, 0, 0]], 3.0);
+    }
+
+    #[test]
+    fn squeeze_and_expand_dims() {
+        let tensor = range_tensor(&[1, 3, 1]).squeeze();
+        assert_eq!(tensor.dims(), &[3]);
+        assert_eq!(vaccel_dims(&tensor), &[3]);
+
+        let tensor = tensor.expand_dims(0).unwrap().expand_dims(2).unwrap();
+        assert_eq!(tensor.dims(), &[1, 3, 1]);
+        assert_eq!(vaccel_dims(&tensor), &[1, 3, 1]);
+        assert!(tensor.expand_dims(4).is_err());
+    }
+
+    #[test]
+    fn multi_dimensional_index() {
+        let mut tensor = range_tensor(&[2, 3, 4]);
+        assert_eq!(tensor[[1, 2, 3]], 23);
+        assert_eq!(tensor[&[0, 1, 2][..]], 6);
+        assert_eq!(tensor.get(&[1, 3, 0]), None);
+        assert_eq!(tensor.get(&[1, 2]), None);
+
+        tensor[[0, 0, 1]] = -1;
+        assert_eq!(tensor[1], -1);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn index_out_of_range() {
+        let tensor = range_tensor(&[2, 3]);
+        let _ = tensor[[2, 0]];
+    }
+
+    #[test]
+    fn tensor_size_overflow() {
+        assert!(Tensor::<f32>::allocate(&[u64::MAX, 2]).is_err());
+        assert!(Tensor::<f64>::allocate(&[1 << 62]).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "overflow")]
+    fn new_tensor_size_overflow() {
+        Tensor::<f32>::new(&[u64::MAX, 2]);
+    }
 }
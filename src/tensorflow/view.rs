@@ -0,0 +1,350 @@
+use crate::ffi;
+use crate::tensorflow::npy::Header;
+use crate::tensorflow::{DataType, TensorAny, TensorType};
+use crate::{Error, Result};
+
+use memmap2::Mmap;
+
+use std::fs::File;
+use std::io::BufReader;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::path::Path;
+
+/// Create a vAccel tensor that points to `data` without copying it
+///
+/// The runtime does not take ownership of the data, so it is not freed when
+/// the tensor is destroyed.
+fn new_borrowed_tensor<T: TensorType>(
+    dims: &[u64],
+    data: &[T],
+) -> Result<*mut ffi::vaccel_tf_tensor> {
+    let len = dims.iter().try_fold(1u64, |len, d| len.checked_mul(*d));
+    if len != Some(data.len() as u64) {
+        return Err(Error::InvalidArgument);
+    }
+
+    let inner = unsafe {
+        ffi::vaccel_tf_tensor_new(
+            dims.len() as i32,
+            dims.as_ptr() as *mut _,
+            T::data_type().to_int(),
+        )
+    };
+    assert!(!inner.is_null(), "Memory allocation failure");
+
+    unsafe {
+        ffi::vaccel_tf_tensor_set_data(
+            inner,
+            data.as_ptr() as *mut _,
+            (data.len() * std::mem::size_of::<T>()) as u64,
+        )
+    };
+
+    Ok(inner)
+}
+
+/// A tensor backed by memory borrowed from the caller
+///
+/// The data is registered with the vAccel runtime as is, without any copies.
+/// The borrow is held for as long as the view lives, so the data cannot be
+/// modified or freed while an inference that uses the view is in flight.
+pub struct TensorView<'a, T: TensorType> {
+    inner: *mut ffi::vaccel_tf_tensor,
+    dims: Vec<u64>,
+    data: &'a [T],
+}
+
+impl<'a, T: TensorType> TensorView<'a, T> {
+    /// Create a new tensor view
+    ///
+    /// # Arguments
+    ///
+    /// * `dims` - The dimensions of the tensor
+    /// * `data` - The elements of the tensor in row-major order. Its length must match `dims`.
+    pub fn new(dims: &[u64], data: &'a [T]) -> Result<Self> {
+        let inner = new_borrowed_tensor(dims, data)?;
+
+        Ok(TensorView {
+            inner,
+            dims: Vec::from(dims),
+            data,
+        })
+    }
+
+    pub fn dims(&self) -> &[u64] {
+        &self.dims
+    }
+
+    pub fn nr_dims(&self) -> u64 {
+        self.dims.len() as u64
+    }
+
+    pub fn data_type(&self) -> DataType {
+        T::data_type()
+    }
+}
+
+impl<'a, T: TensorType> Deref for TensorView<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.data
+    }
+}
+
+impl<'a, T: TensorType> Drop for TensorView<'a, T> {
+    fn drop(&mut self) {
+        unsafe { ffi::vaccel_tf_tensor_destroy(self.inner) };
+    }
+}
+
+impl<'a, T: TensorType> TensorAny for TensorView<'a, T> {
+    fn inner(&self) -> *const ffi::vaccel_tf_tensor {
+        self.inner
+    }
+
+    fn inner_mut(&mut self) -> *mut ffi::vaccel_tf_tensor {
+        self.inner
+    }
+
+    fn data_type(&self) -> DataType {
+        T::data_type()
+    }
+}
+
+/// A read-only tensor backed by a memory-mapped file
+///
+/// Pages of the file are loaded on demand when the runtime reads the tensor,
+/// so large inputs can be used without reading them in memory first.
+pub struct MmapTensor<T: TensorType> {
+    inner: *mut ffi::vaccel_tf_tensor,
+    dims: Vec<u64>,
+    mmap: Mmap,
+    offset: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: TensorType> MmapTensor<T> {
+    /// Map a file holding raw tensor data
+    ///
+    /// The file must hold the elements of the tensor in row-major order and
+    /// in native byte order, starting at `offset`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file
+    /// * `offset` - The offset of the first element in the file
+    /// * `dims` - The dimensions of the tensor
+    pub fn open(path: &Path, offset: usize, dims: &[u64]) -> Result<Self> {
+        let file = File::open(path)?;
+
+        // The file might change under our feet, but that is no different
+        // from the caller modifying a buffer passed to the runtime.
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        let len = dims
+            .iter()
+            .try_fold(std::mem::size_of::<T>(), |len, d| {
+                len.checked_mul(*d as usize)
+            })
+            .ok_or(Error::InvalidArgument)?;
+        match offset.checked_add(len) {
+            Some(end) if end <= mmap.len() => (),
+            _ => return Err(Error::InvalidArgument),
+        }
+
+        if (mmap.as_ptr() as usize + offset) % std::mem::align_of::<T>() != 0 {
+            return Err(Error::InvalidArgument);
+        }
+
+        // Every byte pattern is a valid value for the rest of the types
+        if T::data_type() == DataType::Bool && mmap[offset..offset + len].iter().any(|b| *b > 1) {
+            return Err(Error::InvalidArgument);
+        }
+
+        let data = unsafe {
+            std::slice::from_raw_parts(
+                mmap.as_ptr().add(offset) as *const T,
+                len / std::mem::size_of::<T>(),
+            )
+        };
+        let inner = new_borrowed_tensor(dims, data)?;
+
+        Ok(MmapTensor {
+            inner,
+            dims: Vec::from(dims),
+            mmap,
+            offset,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Map a `.npy` file
+    ///
+    /// Only arrays in C order and native byte order can be mapped, since the
+    /// data is used as is.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the `.npy` file
+    pub fn open_npy(path: &Path) -> Result<Self> {
+        let header = Header::read(&mut BufReader::new(File::open(path)?))?;
+
+        if header.data_type != T::data_type() {
+            return Err(Error::Npy(format!(
+                "type mismatch: file holds {:?} but tensor expects {:?}",
+                header.data_type,
+                T::data_type()
+            )));
+        }
+
+        if header.fortran_order || header.big_endian != cfg!(target_endian = "big") {
+            return Err(Error::Npy(
+                "only C order, native endian arrays can be mapped".to_owned(),
+            ));
+        }
+
+        Self::open(path, header.len, &header.dims)
+    }
+
+    pub fn dims(&self) -> &[u64] {
+        &self.dims
+    }
+
+    pub fn nr_dims(&self) -> u64 {
+        self.dims.len() as u64
+    }
+
+    pub fn data_type(&self) -> DataType {
+        T::data_type()
+    }
+}
+
+impl<T: TensorType> Deref for MmapTensor<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        let len = self.dims.iter().product::<u64>() as usize;
+        unsafe { std::slice::from_raw_parts(self.mmap.as_ptr().add(self.offset) as *const T, len) }
+    }
+}
+
+impl<T: TensorType> Drop for MmapTensor<T> {
+    fn drop(&mut self) {
+        unsafe { ffi::vaccel_tf_tensor_destroy(self.inner) };
+    }
+}
+
+impl<T: TensorType> TensorAny for MmapTensor<T> {
+    fn inner(&self) -> *const ffi::vaccel_tf_tensor {
+        self.inner
+    }
+
+    fn inner_mut(&mut self) -> *mut ffi::vaccel_tf_tensor {
+        self.inner
+    }
+
+    fn data_type(&self) -> DataType {
+        T::data_type()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/tensorflow/testdata")
+            .join(name)
+    }
+
+    /// A file in the temporary directory, removed on drop
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, data: &[u8]) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("vaccel-view-{}-{}", std::process::id(), name));
+            std::fs::write(&path, data).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn data_ptr<T: TensorAny>(tensor: &T) -> *const u8 {
+        unsafe { (*tensor.inner()).data as *const u8 }
+    }
+
+    #[test]
+    fn view_borrows_data() {
+        let data = [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let view = TensorView::new(&[2, 3], &data).unwrap();
+        assert_eq!(view.dims(), &[2, 3]);
+        assert_eq!(&view[..], &data);
+        assert_eq!(data_ptr(&view), data.as_ptr() as *const u8);
+
+        assert!(TensorView::new(&[4, 2], &data).is_err());
+        assert!(TensorView::new(&[u64::MAX, 2], &data).is_err());
+    }
+
+    #[test]
+    fn map_npy() {
+        let tensor = MmapTensor::<f32>::open_npy(&fixture("f4_2x3.npy")).unwrap();
+        assert_eq!(tensor.dims(), &[2, 3]);
+        assert_eq!(&tensor[..], &[0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(data_ptr(&tensor), tensor.as_ptr() as *const u8);
+
+        let tensor = MmapTensor::<bool>::open_npy(&fixture("b1.npy")).unwrap();
+        assert_eq!(&tensor[..], &[true, false, true, true]);
+    }
+
+    #[test]
+    fn map_npy_rejects_unmappable_arrays() {
+        assert!(matches!(
+            MmapTensor::<f64>::open_npy(&fixture("f4_2x3.npy")),
+            Err(Error::Npy(_))
+        ));
+        assert!(matches!(
+            MmapTensor::<i64>::open_npy(&fixture("i8_fortran_2x3.npy")),
+            Err(Error::Npy(_))
+        ));
+        assert!(matches!(
+            MmapTensor::<u16>::open_npy(&fixture("u2_big_endian.npy")),
+            Err(Error::Npy(_))
+        ));
+
+        let mut data = std::fs::read(fixture("b1.npy")).unwrap();
+        *data.last_mut().unwrap() = 2;
+        let file = TempFile::new("bool.npy", &data);
+        assert!(MmapTensor::<bool>::open_npy(&file.0).is_err());
+    }
+
+    #[test]
+    fn map_raw_data() {
+        let data: Vec<u8> = (0..4u32)
+            .flat_map(|v| v.to_ne_bytes().to_vec())
+            .chain(vec![0; 4])
+            .collect();
+        let file = TempFile::new("raw", &data);
+
+        let tensor = MmapTensor::<u32>::open(&file.0, 4, &[3]).unwrap();
+        assert_eq!(&tensor[..], &[1, 2, 3]);
+
+        // Misaligned for the element type
+        assert!(MmapTensor::<u32>::open(&file.0, 2, &[2]).is_err());
+
+        // Past the end of the file
+        assert!(MmapTensor::<u32>::open(&file.0, 12, &[3]).is_err());
+        assert!(MmapTensor::<u32>::open(&file.0, usize::MAX, &[1]).is_err());
+        assert!(MmapTensor::<u32>::open(&file.0, 0, &[u64::MAX, 2]).is_err());
+    }
+}
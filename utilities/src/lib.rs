@@ -5,6 +5,7 @@ use std::io::Read;
 use std::path::{Path, PathBuf};
 
 extern crate vaccel;
+use vaccel::tensorflow::SavedModel;
 
 pub enum Error {
     IO(std::io::Error),
@@ -38,18 +39,19 @@ pub fn vec_from_file(path: &Path) -> Result<Vec<u8>> {
     Ok(data)
 }
 
-pub fn load_in_mem(path: &Path) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+pub fn load_in_mem(path: &Path) -> Result<(Vec<u8>, Vec<Vec<u8>>, Vec<u8>)> {
     let mut model_path = PathBuf::from(path);
     model_path.push("saved_model.pb");
     let model_pb = vec_from_file(&model_path)?;
 
-    let mut ckpt_path = PathBuf::from(path);
-    ckpt_path.push("variables/variables.data-00000-of-00001");
-    let checkpoint = vec_from_file(&ckpt_path)?;
+    let mut checkpoints = Vec::new();
+    for ckpt_path in SavedModel::variable_shards(path)? {
+        checkpoints.push(vec_from_file(&ckpt_path)?);
+    }
 
     let mut var_index_path = PathBuf::from(path);
     var_index_path.push("variables/variables.index");
     let var_index = vec_from_file(&var_index_path)?;
 
-    Ok((model_pb, checkpoint, var_index))
+    Ok((model_pb, checkpoints, var_index))
 }